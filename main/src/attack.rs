@@ -12,6 +12,8 @@ use oqs::{
 use std::path::PathBuf;
 use structopt::StructOpt;
 
+use crate::utils::KeySeedOptions;
+
 #[derive(StructOpt, Debug)]
 pub enum FrodoParams {
     // Select the 640 AES paramter set
@@ -69,6 +71,9 @@ pub enum Attacks {
         /// Measurment source, either external, internal or oracle
         #[structopt(short, long)]
         measure_source: fo_timing::MeasureSource,
+
+        #[structopt(flatten)]
+        key_seed: KeySeedOptions,
     },
     /// Run the MEMCPY attack against the FrodoKEM implementation. See sources for liboqs to know
     /// if your version is patched aginst this vulnerability or not.
@@ -95,6 +100,9 @@ pub enum Attacks {
         /// Measurment source, either external, internal or oracle
         #[structopt(short, long)]
         measure_source: fo_timing::MeasureSource,
+
+        #[structopt(flatten)]
+        key_seed: KeySeedOptions,
     },
     /// Run a variant of the baseline analysis better geared towards finding
     /// small runtime differences due to cache and other non-constant time behaviour.
@@ -125,6 +133,9 @@ pub enum Attacks {
         /// Measurment source, either external, internal or oracle
         #[structopt(short, long)]
         measure_source: fo_timing::MeasureSource,
+
+        #[structopt(flatten)]
+        key_seed: KeySeedOptions,
     },
     /// Run a muiltipoint profiling of the supported algorithms
     FOMultipointProfiling {
@@ -150,6 +161,9 @@ pub enum Attacks {
         /// Save measurments to a csv file
         #[structopt(short("f"), long)]
         save: Option<PathBuf>,
+
+        #[structopt(flatten)]
+        key_seed: KeySeedOptions,
     },
     /// Run an attack on the Rejection Sampling techniques used by BIKE and HQC
     RejectionSampling {
@@ -175,6 +189,7 @@ pub fn run(options: AttackOptions) -> Result<(), String> {
             warmup,
             measure_source,
             save,
+            key_seed,
         } => {
             let f = match params {
                 KemAlg::Frodo(FrodoParams::Kem640aes) => {
@@ -203,6 +218,7 @@ pub fn run(options: AttackOptions) -> Result<(), String> {
                 }
             };
 
+            key_seed.apply();
             f(samples, warmup, measure_source, save)
         }
         Attacks::MemcmpFrodoCrackS {
@@ -212,12 +228,14 @@ pub fn run(options: AttackOptions) -> Result<(), String> {
             iterations,
             measure_source,
             save_to_file,
+            key_seed,
         } => {
             let f = match params {
                 FrodoParams::Kem640aes => fo_timing::frodo_crack_s::<FrodoKem640aes>,
                 FrodoParams::Kem1344aes => fo_timing::frodo_crack_s::<FrodoKem1344aes>,
             };
 
+            key_seed.apply();
             f(warmup, iterations, profiling, measure_source, save_to_file)
         }
         Attacks::CacheAttackFOBaseline {
@@ -228,6 +246,7 @@ pub fn run(options: AttackOptions) -> Result<(), String> {
             samples,
             save,
             measure_source,
+            key_seed,
         } => {
             let f = match params {
                 KemAlg::Frodo(FrodoParams::Kem640aes) => {
@@ -256,6 +275,7 @@ pub fn run(options: AttackOptions) -> Result<(), String> {
                 }
             };
 
+            key_seed.apply();
             f(samples, nencaps, nkeys, warmup, measure_source, save)
         }
         Attacks::FOMultipointProfiling {
@@ -265,6 +285,7 @@ pub fn run(options: AttackOptions) -> Result<(), String> {
             nencaps,
             nkeys,
             save,
+            key_seed,
         } => {
             let f = match params {
                 KemAlg::Frodo(FrodoParams::Kem640aes) => {
@@ -293,6 +314,7 @@ pub fn run(options: AttackOptions) -> Result<(), String> {
                 }
             };
 
+            key_seed.apply();
             f(samples, nencaps, nkeys, warmup, save)
         }
         Attacks::RejectionSampling { sub } => rejection_sampling::run(sub),
@@ -4,7 +4,7 @@ use liboqs_rs_bindings as oqs;
 use log::info;
 use log_derive::logfn_inputs;
 use oqs::{
-    bike::{BikeL1, BikeL3},
+    bike::{BikeL1, BikeL3, BikeL5},
     hqc::{Hqc128, Hqc192, Hqc256},
 };
 use structopt::StructOpt;
@@ -13,30 +13,59 @@ use crate::utils::StrErr;
 
 use self::{
     attack::SimulateAttackOptions,
+    batch::BatchOptions,
     bike_attack::BikeAttackOptions,
     bike_distance_spectrum::BikeDistanceSpectrumOptions,
+    bike_eval_distance_spectrum::BikeEvalDistanceSpectrumOptions,
+    bike_merge_lists::BikeMergeListsOptions,
     bike_error_weight::{BikeErrorWeightSearchOptions, BikeErrorWeightTestOptions},
-    bike_eval_distinguisher::BikeEvalDistinguisherOptions,
+    bike_reconstruct::BikeReconstructOptions,
+    eval_distinguisher::EvalDistinguisherOptions,
     histogram_rejections::HistogramRejectionsOptions,
+    hqc_attack::HqcAttackOptions,
     iteration_timings::IterationTimingsOptions,
+    plaintext_db::{
+        ExportPlaintextDbOptions, ImportPlaintextDbOptions, MergePlaintextDbOptions,
+        StatsPlaintextDbOptions,
+    },
     plaintexts::CollectPlaintextsOptions,
     verify_timing_attack::VerifyTimingAttackOptions,
 };
 
+mod accounting;
 mod attack;
+mod batch;
+mod bgf;
 mod bike_attack;
 mod bike_distance_spectrum;
 mod bike_error_weight;
-mod bike_eval_distinguisher;
+mod bike_eval_distance_spectrum;
+mod bike_merge_lists;
+mod bike_reconstruct;
+mod combinators;
+mod decision_trace;
+mod eval_distinguisher;
+mod experiment;
+mod gf2x;
 mod histogram_rejections;
+mod hqc;
+mod hqc_attack;
 mod iteration_timings;
+mod noise;
+mod oracle;
+mod plaintext_db;
 mod plaintexts;
+mod rejections;
 mod verify_timing_attack;
 
 #[derive(StructOpt, Debug)]
 pub enum BikeParams {
+    /// BIKE Level 1, with r = 12323 and t = 134
     KemL1,
+    /// BIKE Level 3, with r = 24659 and t = 199
     KemL3,
+    /// BIKE Level 5, with r = 40973 and t = 264
+    KemL5,
 }
 
 #[derive(StructOpt, Debug)]
@@ -55,6 +84,68 @@ pub enum RejectionSamplingAlgorithms {
     Hqc(HqcParams),
 }
 
+#[derive(StructOpt, Debug)]
+pub enum PlaintextDbCommand {
+    /// Merges plaintext databases into one, skipping duplicate plaintexts and summing the encountered counts
+    Merge {
+        #[structopt(subcommand, name = "kem-algs")]
+        alg: RejectionSamplingAlgorithms,
+
+        #[structopt(flatten)]
+        opt: MergePlaintextDbOptions,
+    },
+    /// Exports a selection of plaintexts from a database into a small compressed file
+    Export {
+        #[structopt(subcommand, name = "kem-algs")]
+        alg: RejectionSamplingAlgorithms,
+
+        #[structopt(flatten)]
+        opt: ExportPlaintextDbOptions,
+    },
+    /// Imports plaintexts from a file created by the export command, skipping duplicate plaintexts
+    Import {
+        #[structopt(subcommand, name = "kem-algs")]
+        alg: RejectionSamplingAlgorithms,
+
+        #[structopt(flatten)]
+        opt: ImportPlaintextDbOptions,
+    },
+    /// Lists the KEMs in a database and, per iteration count, the number of encountered and stored plaintexts and how likely they are
+    Stats {
+        #[structopt(flatten)]
+        opt: StatsPlaintextDbOptions,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum BatchExperiment {
+    /// Rejection sampling attack on BIKE, see bike-attack. Succeeds if the chain is complete, or with --reconstruct if
+    /// the secret key is recovered.
+    BikeAttack {
+        #[structopt(subcommand, name = "bike-algs")]
+        alg: BikeParams,
+
+        #[structopt(flatten)]
+        opt: BikeAttackOptions,
+    },
+    /// Evaluate the timing distinguisher, see eval-distinguisher
+    EvalDistinguisher {
+        #[structopt(subcommand, name = "kem-algs")]
+        alg: RejectionSamplingAlgorithms,
+
+        #[structopt(flatten)]
+        opt: EvalDistinguisherOptions,
+    },
+    /// Evaluate the decoding failure rate of an error weight, see bike-error-weight-test
+    BikeErrorWeightTest {
+        #[structopt(subcommand, name = "bike-algs")]
+        alg: BikeParams,
+
+        #[structopt(flatten)]
+        opt: BikeErrorWeightTestOptions,
+    },
+}
+
 #[derive(StructOpt, Debug)]
 #[structopt(name = "type")]
 pub enum Subroutine {
@@ -87,6 +178,11 @@ pub enum Subroutine {
         #[structopt(flatten)]
         opt: CollectPlaintextsOptions,
     },
+    /// Merge, export or import plaintexts collected by the collect-plaintexts command
+    PlaintextDb {
+        #[structopt(subcommand)]
+        cmd: PlaintextDbCommand,
+    },
     /// Measures the timings of all plaintexts in specified DB. Outputs recodings in CSV format.
     /// 
     /// Uses a randomly generated keypair
@@ -99,8 +195,8 @@ pub enum Subroutine {
     },
     /// Attempts to find a suitable error_weight to use with the rejection sampling attack on BIKE
     ///
-    /// Note the distinction that the entire error weight is located in e_1 instead of spread out over e_0 and e_1.
-    /// The impact of this is what this command is used to simulate.
+    /// Note the distinction that by default the entire error weight is located in e_1 instead of spread out over e_0
+    /// and e_1, see "--split". The impact of this is what this command is used to simulate.
     BikeErrorWeightSearch {
         #[structopt(subcommand, name = "bike-algs")]
         alg: BikeParams,
@@ -110,8 +206,8 @@ pub enum Subroutine {
     },
     /// Evaluates the decoding failure rate of a choosen error weight
     ///
-    /// Note the distinction that the entire error weight is located in e_1 instead of spread out over e_0 and e_1.
-    /// The impact of this is what this command is used to simulate.
+    /// Note the distinction that by default the entire error weight is located in e_1 instead of spread out over e_0
+    /// and e_1, see "--split". The impact of this is what this command is used to simulate.
     BikeErrorWeightTest {
         #[structopt(subcommand, name = "bike-algs")]
         alg: BikeParams,
@@ -127,13 +223,13 @@ pub enum Subroutine {
         #[structopt(flatten)]
         opt: BikeAttackOptions,
     },
-    /// Evaluate the timing distinguisher for the current BIKE implementation
-    BikeEvalDistinguisher {
-        #[structopt(subcommand, name = "bike-algs")]
-        alg: BikeParams,
+    /// Evaluate the timing distinguisher for the current BIKE or HQC implementation against the decoder intermediaries
+    EvalDistinguisher {
+        #[structopt(subcommand, name = "kem-algs")]
+        alg: RejectionSamplingAlgorithms,
 
         #[structopt(flatten)]
-        opt: BikeEvalDistinguisherOptions,
+        opt: EvalDistinguisherOptions,
     },
     /// Calculate distance spectrum of secret key
     BikeDistanceSpectrum {
@@ -143,6 +239,50 @@ pub enum Subroutine {
         #[structopt(flatten)]
         opt: BikeDistanceSpectrumOptions,
     },
+    /// Sum the distance spectrum lists of several bike-attack runs against the same key pair
+    BikeMergeLists {
+        #[structopt(subcommand, name = "bike-algs")]
+        alg: BikeParams,
+
+        #[structopt(flatten)]
+        opt: BikeMergeListsOptions,
+    },
+    /// Evaluate the distance spectrum lists written by bike-attack against the true distance spectrum, over the accumulated chain length
+    BikeEvalDistanceSpectrum {
+        #[structopt(subcommand, name = "bike-algs")]
+        alg: BikeParams,
+
+        #[structopt(flatten)]
+        opt: BikeEvalDistanceSpectrumOptions,
+    },
+    /// Reconstruct the secret key from the distance spectrum lists written by bike-attack, and check it against the key pair
+    BikeReconstruct {
+        #[structopt(subcommand, name = "bike-algs")]
+        alg: BikeParams,
+
+        #[structopt(flatten)]
+        opt: BikeReconstructOptions,
+    },
+    /// Rejection sampling attack on HQC, recovering the secret key y block by block
+    HqcAttack {
+        #[structopt(subcommand, name = "hqc-algs")]
+        alg: HqcParams,
+
+        #[structopt(flatten)]
+        opt: HqcAttackOptions,
+    },
+    /// Run bike-attack, eval-distinguisher or bike-error-weight-test against several independent key pairs, and report
+    /// the DFR variance, the distribution of the number of queries and the success fraction over the keys
+    ///
+    /// Every key gets its own seed and query counts, so with --jobs several keys run at the same time, each with the
+    /// threads of the experiment.
+    Batch {
+        #[structopt(flatten)]
+        opt: BatchOptions,
+
+        #[structopt(subcommand)]
+        experiment: BatchExperiment,
+    },
 }
 
 #[logfn_inputs(Debug)]
@@ -156,6 +296,9 @@ pub fn run(sub: Subroutine) -> Result<(), String> {
                 RejectionSamplingAlgorithms::Bike(BikeParams::KemL3) => {
                     histogram_rejections::run::<BikeL3>
                 }
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL5) => {
+                    histogram_rejections::run::<BikeL5>
+                }
                 RejectionSamplingAlgorithms::Hqc(HqcParams::Kem128) => {
                     histogram_rejections::run::<Hqc128>
                 }
@@ -181,6 +324,7 @@ pub fn run(sub: Subroutine) -> Result<(), String> {
             }
             verify_timing_attack::run::<BikeL1, _>(&opt, &mut save)?;
             verify_timing_attack::run::<BikeL3, _>(&opt, &mut save)?;
+            verify_timing_attack::run::<BikeL5, _>(&opt, &mut save)?;
             verify_timing_attack::run::<Hqc128, _>(&opt, &mut save)?;
             verify_timing_attack::run::<Hqc192, _>(&opt, &mut save)?;
             verify_timing_attack::run::<Hqc256, _>(&opt, &mut save)?;
@@ -191,6 +335,7 @@ pub fn run(sub: Subroutine) -> Result<(), String> {
             let f = match alg {
                 RejectionSamplingAlgorithms::Bike(BikeParams::KemL1) => attack::run::<BikeL1>,
                 RejectionSamplingAlgorithms::Bike(BikeParams::KemL3) => attack::run::<BikeL3>,
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL5) => attack::run::<BikeL5>,
                 RejectionSamplingAlgorithms::Hqc(HqcParams::Kem128) => attack::run::<Hqc128>,
                 RejectionSamplingAlgorithms::Hqc(HqcParams::Kem192) => attack::run::<Hqc192>,
                 RejectionSamplingAlgorithms::Hqc(HqcParams::Kem256) => attack::run::<Hqc256>,
@@ -201,12 +346,91 @@ pub fn run(sub: Subroutine) -> Result<(), String> {
             let f = match alg {
                 RejectionSamplingAlgorithms::Bike(BikeParams::KemL1) => plaintexts::run::<BikeL1>,
                 RejectionSamplingAlgorithms::Bike(BikeParams::KemL3) => plaintexts::run::<BikeL3>,
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL5) => plaintexts::run::<BikeL5>,
                 RejectionSamplingAlgorithms::Hqc(HqcParams::Kem128) => plaintexts::run::<Hqc128>,
                 RejectionSamplingAlgorithms::Hqc(HqcParams::Kem192) => plaintexts::run::<Hqc192>,
                 RejectionSamplingAlgorithms::Hqc(HqcParams::Kem256) => plaintexts::run::<Hqc256>,
             };
             f(opt)
         }
+        Subroutine::PlaintextDb {
+            cmd: PlaintextDbCommand::Merge { alg, opt },
+        } => {
+            let f = match alg {
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL1) => {
+                    plaintext_db::run_merge::<BikeL1>
+                }
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL3) => {
+                    plaintext_db::run_merge::<BikeL3>
+                }
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL5) => {
+                    plaintext_db::run_merge::<BikeL5>
+                }
+                RejectionSamplingAlgorithms::Hqc(HqcParams::Kem128) => {
+                    plaintext_db::run_merge::<Hqc128>
+                }
+                RejectionSamplingAlgorithms::Hqc(HqcParams::Kem192) => {
+                    plaintext_db::run_merge::<Hqc192>
+                }
+                RejectionSamplingAlgorithms::Hqc(HqcParams::Kem256) => {
+                    plaintext_db::run_merge::<Hqc256>
+                }
+            };
+            f(opt)
+        }
+        Subroutine::PlaintextDb {
+            cmd: PlaintextDbCommand::Export { alg, opt },
+        } => {
+            let f = match alg {
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL1) => {
+                    plaintext_db::run_export::<BikeL1>
+                }
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL3) => {
+                    plaintext_db::run_export::<BikeL3>
+                }
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL5) => {
+                    plaintext_db::run_export::<BikeL5>
+                }
+                RejectionSamplingAlgorithms::Hqc(HqcParams::Kem128) => {
+                    plaintext_db::run_export::<Hqc128>
+                }
+                RejectionSamplingAlgorithms::Hqc(HqcParams::Kem192) => {
+                    plaintext_db::run_export::<Hqc192>
+                }
+                RejectionSamplingAlgorithms::Hqc(HqcParams::Kem256) => {
+                    plaintext_db::run_export::<Hqc256>
+                }
+            };
+            f(opt)
+        }
+        Subroutine::PlaintextDb {
+            cmd: PlaintextDbCommand::Import { alg, opt },
+        } => {
+            let f = match alg {
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL1) => {
+                    plaintext_db::run_import::<BikeL1>
+                }
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL3) => {
+                    plaintext_db::run_import::<BikeL3>
+                }
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL5) => {
+                    plaintext_db::run_import::<BikeL5>
+                }
+                RejectionSamplingAlgorithms::Hqc(HqcParams::Kem128) => {
+                    plaintext_db::run_import::<Hqc128>
+                }
+                RejectionSamplingAlgorithms::Hqc(HqcParams::Kem192) => {
+                    plaintext_db::run_import::<Hqc192>
+                }
+                RejectionSamplingAlgorithms::Hqc(HqcParams::Kem256) => {
+                    plaintext_db::run_import::<Hqc256>
+                }
+            };
+            f(opt)
+        }
+        Subroutine::PlaintextDb {
+            cmd: PlaintextDbCommand::Stats { opt },
+        } => plaintext_db::run_stats(opt),
         Subroutine::IterationTimings { alg, opt } => {
             let f = match alg {
                 RejectionSamplingAlgorithms::Bike(BikeParams::KemL1) => {
@@ -215,6 +439,9 @@ pub fn run(sub: Subroutine) -> Result<(), String> {
                 RejectionSamplingAlgorithms::Bike(BikeParams::KemL3) => {
                     iteration_timings::run::<BikeL3>
                 }
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL5) => {
+                    iteration_timings::run::<BikeL5>
+                }
                 RejectionSamplingAlgorithms::Hqc(HqcParams::Kem128) => {
                     iteration_timings::run::<Hqc128>
                 }
@@ -231,6 +458,7 @@ pub fn run(sub: Subroutine) -> Result<(), String> {
             let f = match alg {
                 BikeParams::KemL1 => bike_error_weight::run_search::<BikeL1>,
                 BikeParams::KemL3 => bike_error_weight::run_search::<BikeL3>,
+                BikeParams::KemL5 => bike_error_weight::run_search::<BikeL5>,
             };
             f(opt)
         }
@@ -238,6 +466,7 @@ pub fn run(sub: Subroutine) -> Result<(), String> {
             let f = match alg {
                 BikeParams::KemL1 => bike_error_weight::run_test::<BikeL1>,
                 BikeParams::KemL3 => bike_error_weight::run_test::<BikeL3>,
+                BikeParams::KemL5 => bike_error_weight::run_test::<BikeL5>,
             };
             f(opt)
         }
@@ -245,13 +474,30 @@ pub fn run(sub: Subroutine) -> Result<(), String> {
             let f = match alg {
                 BikeParams::KemL1 => bike_attack::run::<BikeL1>,
                 BikeParams::KemL3 => bike_attack::run::<BikeL3>,
+                BikeParams::KemL5 => bike_attack::run::<BikeL5>,
             };
             f(opt)
         }
-        Subroutine::BikeEvalDistinguisher { alg, opt } => {
+        Subroutine::EvalDistinguisher { alg, opt } => {
             let f = match alg {
-                BikeParams::KemL1 => bike_eval_distinguisher::run::<BikeL1>,
-                BikeParams::KemL3 => bike_eval_distinguisher::run::<BikeL3>,
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL1) => {
+                    eval_distinguisher::run::<BikeL1>
+                }
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL3) => {
+                    eval_distinguisher::run::<BikeL3>
+                }
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL5) => {
+                    eval_distinguisher::run::<BikeL5>
+                }
+                RejectionSamplingAlgorithms::Hqc(HqcParams::Kem128) => {
+                    eval_distinguisher::run::<Hqc128>
+                }
+                RejectionSamplingAlgorithms::Hqc(HqcParams::Kem192) => {
+                    eval_distinguisher::run::<Hqc192>
+                }
+                RejectionSamplingAlgorithms::Hqc(HqcParams::Kem256) => {
+                    eval_distinguisher::run::<Hqc256>
+                }
             };
             f(opt)
         }
@@ -259,8 +505,89 @@ pub fn run(sub: Subroutine) -> Result<(), String> {
             let f = match alg {
                 BikeParams::KemL1 => bike_distance_spectrum::run::<BikeL1>,
                 BikeParams::KemL3 => bike_distance_spectrum::run::<BikeL3>,
+                BikeParams::KemL5 => bike_distance_spectrum::run::<BikeL5>,
+            };
+            f(opt)
+        }
+        Subroutine::BikeMergeLists { alg, opt } => {
+            let f = match alg {
+                BikeParams::KemL1 => bike_merge_lists::run::<BikeL1>,
+                BikeParams::KemL3 => bike_merge_lists::run::<BikeL3>,
+                BikeParams::KemL5 => bike_merge_lists::run::<BikeL5>,
+            };
+            f(opt)
+        }
+        Subroutine::BikeEvalDistanceSpectrum { alg, opt } => {
+            let f = match alg {
+                BikeParams::KemL1 => bike_eval_distance_spectrum::run::<BikeL1>,
+                BikeParams::KemL3 => bike_eval_distance_spectrum::run::<BikeL3>,
+                BikeParams::KemL5 => bike_eval_distance_spectrum::run::<BikeL5>,
+            };
+            f(opt)
+        }
+        Subroutine::BikeReconstruct { alg, opt } => {
+            let f = match alg {
+                BikeParams::KemL1 => bike_reconstruct::run::<BikeL1>,
+                BikeParams::KemL3 => bike_reconstruct::run::<BikeL3>,
+                BikeParams::KemL5 => bike_reconstruct::run::<BikeL5>,
+            };
+            f(opt)
+        }
+        Subroutine::HqcAttack { alg, opt } => {
+            let f = match alg {
+                HqcParams::Kem128 => hqc_attack::run::<Hqc128>,
+                HqcParams::Kem192 => hqc_attack::run::<Hqc192>,
+                HqcParams::Kem256 => hqc_attack::run::<Hqc256>,
             };
             f(opt)
         }
+        Subroutine::Batch {
+            opt,
+            experiment: BatchExperiment::BikeAttack { alg, opt: attack },
+        } => {
+            let f = match alg {
+                BikeParams::KemL1 => batch::run_bike_attack::<BikeL1>,
+                BikeParams::KemL3 => batch::run_bike_attack::<BikeL3>,
+                BikeParams::KemL5 => batch::run_bike_attack::<BikeL5>,
+            };
+            f(opt, attack)
+        }
+        Subroutine::Batch {
+            opt,
+            experiment: BatchExperiment::EvalDistinguisher { alg, opt: eval },
+        } => {
+            let f = match alg {
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL1) => {
+                    batch::run_eval_distinguisher::<BikeL1>
+                }
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL3) => {
+                    batch::run_eval_distinguisher::<BikeL3>
+                }
+                RejectionSamplingAlgorithms::Bike(BikeParams::KemL5) => {
+                    batch::run_eval_distinguisher::<BikeL5>
+                }
+                RejectionSamplingAlgorithms::Hqc(HqcParams::Kem128) => {
+                    batch::run_eval_distinguisher::<Hqc128>
+                }
+                RejectionSamplingAlgorithms::Hqc(HqcParams::Kem192) => {
+                    batch::run_eval_distinguisher::<Hqc192>
+                }
+                RejectionSamplingAlgorithms::Hqc(HqcParams::Kem256) => {
+                    batch::run_eval_distinguisher::<Hqc256>
+                }
+            };
+            f(opt, eval)
+        }
+        Subroutine::Batch {
+            opt,
+            experiment: BatchExperiment::BikeErrorWeightTest { alg, opt: test },
+        } => {
+            let f = match alg {
+                BikeParams::KemL1 => batch::run_bike_error_weight_test::<BikeL1>,
+                BikeParams::KemL3 => batch::run_bike_error_weight_test::<BikeL3>,
+                BikeParams::KemL5 => batch::run_bike_error_weight_test::<BikeL5>,
+            };
+            f(opt, test)
+        }
     }
 }
@@ -0,0 +1,246 @@
+//! Counts the encapsulations, decapsulations and oracle queries of an attack, per phase, and limits
+//! them with budgets. The counts belong to the experiment of the counting thread, see
+//! [`Experiment`](super::experiment::Experiment), and the phase is set per thread.
+
+use std::{
+    cell::Cell,
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+
+use crate::attack::rejection_sampling::experiment;
+
+/// The phases of an attack the counts are split into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Constructing the oracle, e.g. measuring the timing profile
+    Profiling,
+    GenesisSearch,
+    /// The queries that learn about the key, e.g. the chain of bike-attack
+    Chain,
+    /// Repeated queries to confirm a decoding failure
+    Confirmation,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Operation {
+    Encaps,
+    Decaps,
+    Query,
+}
+
+/// The counts of an experiment, indexed by phase and operation
+#[derive(Debug, Default)]
+pub struct Counts([[AtomicU64; 3]; 4]);
+
+// Only used to initialize PROCESS_COUNTS
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_COUNTS: [AtomicU64; 3] = [ZERO; 3];
+// Counted outside of an experiment
+static PROCESS_COUNTS: Counts = Counts([NO_COUNTS; 4]);
+
+/// Gives access to the counts of the experiment of this thread
+fn with_counts<T>(f: impl FnOnce(&Counts) -> T) -> T {
+    experiment::with_current(|experiment| match experiment {
+        Some(experiment) => f(experiment.counts()),
+        None => f(&PROCESS_COUNTS),
+    })
+}
+
+thread_local! {
+    // Nothing is counted while None
+    static PHASE: Cell<Option<Phase>> = Cell::new(Some(Phase::Profiling));
+}
+
+/// Counts the following operations of this thread in `phase`
+pub fn set_phase(phase: Phase) {
+    PHASE.with(|p| p.set(Some(phase)));
+}
+
+/// Runs `f` without counting its operations, e.g. for the ground truth of a trace
+pub fn uncounted<T>(f: impl FnOnce() -> T) -> T {
+    let phase = PHASE.with(|p| p.replace(None));
+    let result = f();
+    PHASE.with(|p| p.set(phase));
+    result
+}
+
+/// Counts `n` operations in the phase of this thread
+pub fn count(op: Operation, n: u64) {
+    if let Some(phase) = PHASE.with(|p| p.get()) {
+        with_counts(|counts| counts.0[phase as usize][op as usize].fetch_add(n, Ordering::Relaxed));
+    }
+}
+
+/// Makes a query of an oracle, counting it
+pub fn query<T, F>(query: F) -> Result<Option<T>, String>
+where
+    F: FnOnce() -> Result<Option<T>, String>,
+{
+    count(Operation::Query, 1);
+    query()
+}
+
+/// Sets all counts of the experiment to zero, and the phase of this thread to profiling, before an attack
+pub fn reset() {
+    with_counts(|counts| {
+        counts
+            .0
+            .iter()
+            .flatten()
+            .for_each(|count| count.store(0, Ordering::Relaxed))
+    });
+    set_phase(Phase::Profiling);
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseCounts {
+    pub encaps: u64,
+    pub decaps: u64,
+    pub queries: u64,
+}
+
+impl PhaseCounts {
+    fn load(counts: &Counts, phase: Phase) -> Self {
+        let [encaps, decaps, queries] = &counts.0[phase as usize];
+        PhaseCounts {
+            encaps: encaps.load(Ordering::Relaxed),
+            decaps: decaps.load(Ordering::Relaxed),
+            queries: queries.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl std::ops::Add for PhaseCounts {
+    type Output = PhaseCounts;
+
+    fn add(self, other: PhaseCounts) -> PhaseCounts {
+        PhaseCounts {
+            encaps: self.encaps + other.encaps,
+            decaps: self.decaps + other.decaps,
+            queries: self.queries + other.queries,
+        }
+    }
+}
+
+impl fmt::Display for PhaseCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} encaps, {} decaps, {} queries",
+            self.encaps, self.decaps, self.queries
+        )
+    }
+}
+
+/// The counts of every phase
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryCounts {
+    pub profiling: PhaseCounts,
+    pub genesis_search: PhaseCounts,
+    pub chain: PhaseCounts,
+    pub confirmation: PhaseCounts,
+}
+
+impl QueryCounts {
+    /// The counts of the experiment of this thread
+    pub fn snapshot() -> Self {
+        with_counts(|counts| QueryCounts {
+            profiling: PhaseCounts::load(counts, Phase::Profiling),
+            genesis_search: PhaseCounts::load(counts, Phase::GenesisSearch),
+            chain: PhaseCounts::load(counts, Phase::Chain),
+            confirmation: PhaseCounts::load(counts, Phase::Confirmation),
+        })
+    }
+
+    pub fn total(&self) -> PhaseCounts {
+        self.profiling + self.genesis_search + self.chain + self.confirmation
+    }
+}
+
+impl std::ops::Add for QueryCounts {
+    type Output = QueryCounts;
+
+    fn add(self, other: QueryCounts) -> QueryCounts {
+        QueryCounts {
+            profiling: self.profiling + other.profiling,
+            genesis_search: self.genesis_search + other.genesis_search,
+            chain: self.chain + other.chain,
+            confirmation: self.confirmation + other.confirmation,
+        }
+    }
+}
+
+impl fmt::Display for QueryCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "profiling: {}; genesis search: {}; chain: {}; confirmation: {}; total: {}",
+            self.profiling,
+            self.genesis_search,
+            self.chain,
+            self.confirmation,
+            self.total()
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, StructOpt)]
+pub struct BudgetOptions {
+    /// Stop the attack gracefully after this many oracle queries, over all phases
+    #[structopt(long)]
+    pub max_queries: Option<u64>,
+    /// Stop the attack gracefully after this many decapsulations, over all phases
+    #[structopt(long)]
+    pub max_decaps: Option<u64>,
+}
+
+impl BudgetOptions {
+    /// Describes the budget that was used up, if any
+    pub fn exhausted(&self) -> Option<String> {
+        let total = QueryCounts::snapshot().total();
+        match (self.max_queries, self.max_decaps) {
+            (Some(max), _) if total.queries >= max => {
+                Some(format!("the budget of {} queries is used up", max))
+            }
+            (_, Some(max)) if total.decaps >= max => {
+                Some(format!("the budget of {} decapsulations is used up", max))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{count, set_phase, uncounted, Operation, Phase, QueryCounts};
+    use crate::{
+        attack::rejection_sampling::experiment::{run_on_core, Experiment},
+        utils::RngSeed,
+    };
+
+    #[test]
+    fn test_counts_per_phase() {
+        let after = Experiment::new(RngSeed::root()).run(|| {
+            run_on_core(None, || {
+                count(Operation::Encaps, 2);
+                set_phase(Phase::Confirmation);
+                count(Operation::Query, 1);
+                count(Operation::Decaps, 100);
+                uncounted(|| count(Operation::Decaps, 1));
+                Ok(())
+            })
+            .unwrap();
+            QueryCounts::snapshot()
+        });
+
+        assert_eq!(after.profiling.encaps, 2);
+        assert_eq!(after.confirmation.queries, 1);
+        assert_eq!(after.confirmation.decaps, 100);
+    }
+}
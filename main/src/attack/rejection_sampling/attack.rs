@@ -11,9 +11,13 @@ use structopt::StructOpt;
 use crate::{
     attack::{
         fo_timing::{MeasureSource, NoCachePrepping},
-        rejection_sampling::verify_timing_attack::{encapsulate_and_verify, find_min_max_pt},
+        rejection_sampling::{
+            accounting::{self, Operation},
+            experiment::root_seed,
+            verify_timing_attack::{encapsulate_and_verify, find_min_max_pt},
+        },
     },
-    utils::{mutbit, Rec, Recorder},
+    utils::{mutbit, KeySeedOptions, Rec, Recorder},
 };
 
 #[logfn_inputs(Trace)]
@@ -33,11 +37,14 @@ pub struct SimulateAttackOptions {
     /// The hamming weight of the extra noice that is applied to the ciphertext
     #[structopt(short("e"), long)]
     error_weight: i32,
+    #[structopt(flatten)]
+    pub key_seed: KeySeedOptions,
 }
 
 pub fn modify_ct<KEM: KemWithRejectionSampling>(
     mut ct: KEM::Ciphertext,
     error_weight: i32,
+    rng: &mut impl Rng,
 ) -> Result<KEM::Ciphertext, String> {
     let mut err = KEM::Ciphertext::new();
     let mut hamming_weight = 0;
@@ -46,7 +53,7 @@ pub fn modify_ct<KEM: KemWithRejectionSampling>(
         if hamming_weight >= error_weight {
             break;
         }
-        let bitnum = rand::thread_rng().gen_range(0u64..KEM::Ciphertext::len().try_into().unwrap());
+        let bitnum = rng.gen_range(0u64..KEM::Ciphertext::len().try_into().unwrap());
         let mut bit = mutbit(es, bitnum)?;
         if !bit.get() {
             bit.flip();
@@ -67,6 +74,7 @@ pub fn record_decaps_to<'a, KEM: KemWithRejectionSampling, R: Rec<'a>>(
     num_decaps: i32,
 ) -> Result<R, String> {
     let mut ss = KEM::SharedSecret::new();
+    accounting::count(Operation::Decaps, num_decaps.max(0) as u64);
     for _ in 0..num_decaps {
         if let Some(m) =
             MeasureSource::measure_decap_external::<KEM, NoCachePrepping>(ct, &mut ss, sk)?
@@ -83,6 +91,7 @@ pub fn record_decaps_to<'a, KEM: KemWithRejectionSampling, R: Rec<'a>>(
 #[logfn_inputs(Trace)]
 pub fn run<KEM: KemWithRejectionSampling>(opt: SimulateAttackOptions) -> Result<(), String> {
     info!("Launching generic attack simulation on {}", KEM::NAME);
+    opt.key_seed.apply();
 
     info!(
         "Searching {} plaintexts for best candidate...",
@@ -117,7 +126,11 @@ pub fn run<KEM: KemWithRejectionSampling>(opt: SimulateAttackOptions) -> Result<
         opt.error_weight
     );
 
-    let mut ct_mod = modify_ct::<KEM>(ct_nomod, opt.error_weight)?;
+    let mut ct_mod = modify_ct::<KEM>(
+        ct_nomod,
+        opt.error_weight,
+        &mut root_seed().purpose("ciphertext-errors").rng(),
+    )?;
 
     info!(
         "Starting {} decapsulations of modified ciphertext...",
@@ -0,0 +1,465 @@
+//! Runs an experiment against several independent key pairs, and aggregates the results over the keys
+
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+};
+
+use liboqs_rs_bindings as oqs;
+use log::{info, warn};
+use log_derive::logfn_inputs;
+use oqs::{bike::Bike, Kem, KemBuf};
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+
+use crate::{
+    attack::rejection_sampling::{
+        accounting::QueryCounts,
+        attack::get_keypair,
+        bike_attack::{self, key_fingerprint, write_keypair, BikeAttackOptions},
+        bike_error_weight::{self, BikeErrorWeightTestOptions},
+        bike_reconstruct::{self, BikeReconstructOptions},
+        eval_distinguisher::{self, EvalDistinguisherOptions},
+        experiment::{root_seed, Experiment},
+        oracle::DecodingFailureKem,
+        rejections::RejectionSamplingStats,
+    },
+    utils::{get_stop_signal, KeySeedOptions, StrErr},
+};
+
+#[derive(Debug, StructOpt)]
+pub struct BatchOptions {
+    /// The number of independent key pairs to run the experiment against
+    #[structopt(short("n"), long)]
+    pub keys: usize,
+    /// The directory to write the key pairs, the output of every key and the aggregated "report.json" to, with one
+    /// subdirectory per key. Keys with a result from an earlier batch in the directory are not run again, so an
+    /// interrupted batch can be continued.
+    #[structopt(short("o"), long)]
+    pub directory: PathBuf,
+    /// Reconstruct the secret key from the lists of bike-attack with the default options of bike-reconstruct, and
+    /// count recovered keys as successes instead of complete chains
+    #[structopt(long)]
+    pub reconstruct: bool,
+    /// The number of keys to run at the same time. Experiments measuring timings disturb each other's measurements,
+    /// so they should run one key at a time.
+    #[structopt(short("j"), long, default_value = "1")]
+    pub jobs: usize,
+}
+
+/// The outcome of an experiment against one key pair, without the key
+#[derive(Debug, Clone, Copy)]
+struct Outcome {
+    /// The observed decoding failure rate
+    dfr: Option<f64>,
+    /// The number of decoding failure oracle queries
+    queries: u64,
+    /// Whether the experiment succeeded, for experiments with a notion of success
+    success: Option<bool>,
+}
+
+/// The outcome of an experiment against one key pair, written to "result.json" in its directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyResult {
+    pub key: usize,
+    /// The SHA3-256 hash of the public key
+    pub fingerprint: String,
+    pub dfr: Option<f64>,
+    pub queries: u64,
+    pub success: Option<bool>,
+}
+
+/// Summary statistics of a value over the keys, the quartiles are linearly interpolated
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Distribution {
+    pub count: usize,
+    pub mean: f64,
+    /// The sample variance
+    pub variance: f64,
+    pub min: f64,
+    pub q1: f64,
+    pub median: f64,
+    pub q3: f64,
+    pub max: f64,
+}
+
+impl Distribution {
+    fn of(values: &[f64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let quantile = |q: f64| {
+            let pos = q * (sorted.len() - 1) as f64;
+            let (low, high) = (pos.floor() as usize, pos.ceil() as usize);
+            sorted[low] + (sorted[high] - sorted[low]) * (pos - low as f64)
+        };
+
+        let count = values.len();
+        let mean = values.iter().sum::<f64>() / count as f64;
+        let variance = match count {
+            1 => 0.0,
+            _ => values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (count - 1) as f64,
+        };
+        Some(Distribution {
+            count,
+            mean,
+            variance,
+            min: sorted[0],
+            q1: quantile(0.25),
+            median: quantile(0.5),
+            q3: quantile(0.75),
+            max: sorted[count - 1],
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchReport {
+    pub experiment: String,
+    pub algorithm: String,
+    pub keys: usize,
+    pub dfr: Option<Distribution>,
+    pub queries: Option<Distribution>,
+    /// What counts as a success, e.g. "chain completed" or "key recovered"
+    pub success_criterion: Option<String>,
+    pub success_fraction: Option<f64>,
+    pub results: Vec<KeyResult>,
+}
+
+fn key_directory(opt: &BatchOptions, key: usize) -> PathBuf {
+    opt.directory.join(format!("key-{:03}", key))
+}
+
+/// The file `destination` would be written to, in the directory of a key
+fn key_output(opt: &BatchOptions, key: usize, destination: &Path, default: &str) -> PathBuf {
+    key_directory(opt, key).join(destination.file_name().unwrap_or_else(|| default.as_ref()))
+}
+
+/// Generates the key pairs in order before any experiment runs, so they only depend on the key
+/// seed. Returns the key files and the fingerprints of their public keys.
+fn prepare_keys<KEM: Kem>(
+    opt: &BatchOptions,
+    key_seed: &KeySeedOptions,
+) -> Result<Vec<(PathBuf, String)>, String> {
+    key_seed.apply();
+    (0..opt.keys)
+        .map(|key| {
+            fs::create_dir_all(key_directory(opt, key)).strerr()?;
+            let key_file = key_directory(opt, key).join("keypair");
+            // Generated even if the file exists, so later keys do not depend on which files exist
+            let (pk, sk) = get_keypair::<KEM>()?;
+            let pk = if key_file.exists() {
+                bike_attack::read_keypair::<KEM>(&key_file, false)?.0
+            } else {
+                write_keypair::<KEM>(&key_file, &pk, &sk)?;
+                pk
+            };
+            Ok((key_file, key_fingerprint(pk.as_slice())))
+        })
+        .collect()
+}
+
+/// Runs `experiment` against every key without a result yet, and writes the aggregated report.
+/// The experiment gets the key index and the key file.
+///
+/// Every key runs as its own [`Experiment`], with its own seed and query counts, so up to
+/// `--jobs` keys can run at the same time.
+fn run_batch<KEM, F>(
+    opt: BatchOptions,
+    experiment_name: &str,
+    success_criterion: Option<&str>,
+    key_seed: &KeySeedOptions,
+    experiment: F,
+) -> Result<(), String>
+where
+    KEM: Kem,
+    F: Fn(usize, &Path) -> Result<Outcome, String> + Sync,
+{
+    let keys = prepare_keys::<KEM>(&opt, key_seed)?;
+    let stop = get_stop_signal()?;
+
+    let mut results = vec![];
+    let mut pending = vec![];
+    for (key, (key_file, fingerprint)) in keys.into_iter().enumerate() {
+        let result_file = key_directory(&opt, key).join("result.json");
+        if let Ok(reader) = File::open(&result_file) {
+            info!("Key {} already has a result in {:?}", key, result_file);
+            results.push(serde_json::from_reader(BufReader::new(reader)).strerr()?);
+        } else {
+            pending.push((key, key_file, fingerprint, result_file));
+        }
+    }
+
+    let run_key = |key: usize,
+                   key_file: &Path,
+                   fingerprint: &str,
+                   result_file: &Path|
+     -> Result<Option<KeyResult>, String> {
+        info!(
+            "Running {} against key {} of {}",
+            experiment_name,
+            key + 1,
+            opt.keys
+        );
+        // Every key gets its own error patterns, measurements and query counts
+        let outcome = Experiment::new(root_seed().purpose(&format!("key-{}", key)))
+            .run(|| experiment(key, key_file))?;
+        if stop.load(Ordering::Relaxed) {
+            warn!(
+                "Interrupted, key {} is not counted and runs again when the batch is continued",
+                key
+            );
+            return Ok(None);
+        }
+
+        let result = KeyResult {
+            key,
+            fingerprint: fingerprint.to_string(),
+            dfr: outcome.dfr,
+            queries: outcome.queries,
+            success: outcome.success,
+        };
+        info!("Result of key {}: {:?}", key, result);
+        let mut writer = BufWriter::new(File::create(result_file).strerr()?);
+        serde_json::to_writer_pretty(&mut writer, &result).strerr()?;
+        writeln!(writer).strerr()?;
+        Ok(Some(result))
+    };
+
+    // Workers take the next pending key until all ran, the batch is interrupted or a key failed
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let work = || {
+        let mut results = vec![];
+        while !stop.load(Ordering::Relaxed) && !failed.load(Ordering::Relaxed) {
+            let (key, key_file, fingerprint, result_file) =
+                match pending.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    Some(pending) => pending,
+                    None => break,
+                };
+            match run_key(*key, key_file, fingerprint, result_file) {
+                Ok(Some(result)) => results.push(result),
+                Ok(None) => break,
+                Err(e) => {
+                    failed.store(true, Ordering::Relaxed);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(results)
+    };
+    let worker_results: Vec<Result<Vec<KeyResult>, String>> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..opt.jobs.max(1)).map(|_| scope.spawn(work)).collect();
+        workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|_| Err("A batch worker panicked".to_string()))
+            })
+            .collect()
+    });
+    for worker_result in worker_results {
+        results.extend(worker_result?);
+    }
+    results.sort_by_key(|result: &KeyResult| result.key);
+
+    let dfrs: Vec<f64> = results.iter().filter_map(|r| r.dfr).collect();
+    let queries: Vec<f64> = results.iter().map(|r| r.queries as f64).collect();
+    let successes: Vec<bool> = results.iter().filter_map(|r| r.success).collect();
+    let report = BatchReport {
+        experiment: experiment_name.to_string(),
+        algorithm: KEM::NAME.to_string(),
+        keys: results.len(),
+        dfr: Distribution::of(&dfrs),
+        queries: Distribution::of(&queries),
+        success_criterion: success_criterion.map(str::to_string),
+        success_fraction: match successes.len() {
+            0 => None,
+            n => Some(successes.iter().filter(|&&s| s).count() as f64 / n as f64),
+        },
+        results,
+    };
+    if let Some(dfr) = &report.dfr {
+        info!(
+            "DFR over {} keys: mean {}, variance {}, between {} and {}",
+            dfr.count, dfr.mean, dfr.variance, dfr.min, dfr.max
+        );
+    }
+    if let Some(queries) = &report.queries {
+        info!(
+            "Queries over {} keys: median {} (quartiles {} and {}), between {} and {}",
+            queries.count, queries.median, queries.q1, queries.q3, queries.min, queries.max
+        );
+    }
+    if let (Some(criterion), Some(fraction)) = (success_criterion, report.success_fraction) {
+        info!("Final verdict: {} for {} of the keys", criterion, fraction);
+    }
+
+    let report_file = opt.directory.join("report.json");
+    info!("Writing the report to {:?}", report_file);
+    let mut writer = BufWriter::new(File::create(report_file).strerr()?);
+    serde_json::to_writer_pretty(&mut writer, &report).strerr()?;
+    writeln!(writer).strerr()?;
+
+    Ok(())
+}
+
+#[logfn_inputs(Trace)]
+pub fn run_bike_attack<BIKE: 'static + Bike + RejectionSamplingStats + std::marker::Send>(
+    opt: BatchOptions,
+    attack: BikeAttackOptions,
+) -> Result<(), String> {
+    let reconstruct = opt.reconstruct;
+    let paths: Vec<_> = (0..opt.keys)
+        .map(|key| {
+            let destination = key_output(&opt, key, &attack.destination, "bike-ds.csv.gz");
+            let checkpoint = attack
+                .checkpoint
+                .as_ref()
+                .map(|checkpoint| key_output(&opt, key, checkpoint, "bike-ds.checkpoint"));
+            (destination, checkpoint)
+        })
+        .collect();
+    let key_seed = attack.key_seed.clone();
+
+    let criterion = Some(if reconstruct {
+        "key recovered"
+    } else {
+        "chain completed"
+    });
+
+    run_batch::<BIKE, _>(opt, "bike-attack", criterion, &key_seed, |key, key_file| {
+        let (destination, checkpoint) = paths[key].clone();
+        let mut attack = attack.clone();
+        attack.key_seed.key_seed = None;
+        attack.reuse_key_pair = Some(key_file.to_path_buf());
+        attack.resume = attack.resume && checkpoint.as_ref().map_or(false, |c| c.exists());
+        attack.checkpoint = checkpoint;
+        attack.destination = destination.clone();
+
+        let metadata = match bike_attack::run_with_result::<BIKE>(attack.clone())? {
+            Some(metadata) => metadata,
+            None => {
+                return Ok(Outcome {
+                    dfr: None,
+                    queries: QueryCounts::snapshot().total().queries,
+                    success: Some(false),
+                })
+            }
+        };
+        let decisions = metadata.num_failures as u64 + metadata.num_successes as u64;
+        let success = if reconstruct {
+            let reconstruct = BikeReconstructOptions::from_iter_safe(&[
+                "bike-reconstruct".as_ref(),
+                "--source".as_ref(),
+                destination.as_os_str(),
+                "--key-file".as_ref(),
+                key_file.as_os_str(),
+            ])
+            .strerr()?;
+            bike_reconstruct::run_with_result::<BIKE>(reconstruct)?
+        } else {
+            metadata.num_failures >= attack.chain_length
+        };
+        Ok(Outcome {
+            dfr: Some(metadata.num_failures as f64 / decisions.max(1) as f64),
+            queries: metadata.queries.total().queries,
+            success: Some(success),
+        })
+    })
+}
+
+#[logfn_inputs(Trace)]
+pub fn run_eval_distinguisher<KEM: DecodingFailureKem>(
+    opt: BatchOptions,
+    eval: EvalDistinguisherOptions,
+) -> Result<(), String> {
+    let destinations: Vec<_> = (0..opt.keys)
+        .map(|key| {
+            let destination = eval.destination.clone().unwrap_or_default();
+            key_output(&opt, key, &destination, "distinguisher.json")
+        })
+        .collect();
+    let key_seed = eval.key_seed.clone();
+
+    run_batch::<KEM, _>(
+        opt,
+        "eval-distinguisher",
+        None,
+        &key_seed,
+        |key, key_file| {
+            let mut eval = eval.clone();
+            eval.key_seed.key_seed = None;
+            eval.reuse_key_pair = Some(key_file.to_path_buf());
+            eval.destination = Some(destinations[key].clone());
+
+            let result = eval_distinguisher::run_with_result::<KEM>(eval)?;
+            Ok(Outcome {
+                dfr: Some(result.num_decoding_failures as f64 / result.trials as f64),
+                queries: result.trials,
+                success: None,
+            })
+        },
+    )
+}
+
+#[logfn_inputs(Trace)]
+pub fn run_bike_error_weight_test<
+    BIKE: 'static + Bike + RejectionSamplingStats + std::marker::Send,
+>(
+    opt: BatchOptions,
+    test: BikeErrorWeightTestOptions,
+) -> Result<(), String> {
+    let key_seed = test.key_seed.clone();
+
+    run_batch::<BIKE, _>(
+        opt,
+        "bike-error-weight-test",
+        None,
+        &key_seed,
+        |_, key_file| {
+            let mut test = test.clone();
+            test.key_seed.key_seed = None;
+            test.reuse_key_pair = Some(key_file.to_path_buf());
+
+            let (decoding_failures, tests) = bike_error_weight::run_test_with_result::<BIKE>(test)?;
+            Ok(Outcome {
+                dfr: Some(decoding_failures as f64 / tests.max(1) as f64),
+                queries: tests,
+                success: None,
+            })
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Distribution;
+
+    #[test]
+    fn test_distribution() {
+        let distribution = Distribution::of(&[4.0, 1.0, 3.0, 2.0, 5.0]).unwrap();
+        assert_eq!(
+            distribution,
+            Distribution {
+                count: 5,
+                mean: 3.0,
+                variance: 2.5,
+                min: 1.0,
+                q1: 2.0,
+                median: 3.0,
+                q3: 4.0,
+                max: 5.0,
+            }
+        );
+        assert_eq!(Distribution::of(&[0.5]).unwrap().variance, 0.0);
+        assert_eq!(Distribution::of(&[1.0, 2.0]).unwrap().median, 1.5);
+        assert!(Distribution::of(&[]).is_none());
+    }
+}
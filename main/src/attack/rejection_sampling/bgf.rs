@@ -0,0 +1,432 @@
+//! A native simulation of the Black-Gray-Flip (BGF) decoder of BIKE, on the sparse h0 and h1 of
+//! the secret key, to decide decoding failures without the decapsulation of liboqs
+
+use std::{marker::PhantomData, sync::Arc};
+
+use liboqs_rs_bindings as oqs;
+use oqs::{bike::Bike, KemBuf};
+
+use crate::attack::rejection_sampling::{
+    accounting::{self, Operation},
+    gf2x::{bike_column_weight, read_bike_secret_key, BikeSecretKey, Poly, SparsePoly},
+    oracle::DecodingFailureOracle,
+};
+
+/// The parameters of the decoder, for blocks of length `r` and a column weight `d` of h0 and h1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BgfParams {
+    pub r: usize,
+    pub d: usize,
+    /// The number of iterations, the first one is followed by the black and gray iterations
+    pub iterations: usize,
+    /// Positions within `tau` below the threshold are gray
+    pub tau: u32,
+    /// The threshold of an iteration is max(⌊slope·|s| + intercept⌋, min) for a syndrome s
+    pub slope: f64,
+    pub intercept: f64,
+    pub min: u32,
+}
+
+impl BgfParams {
+    /// The parameters of the BIKE specification for the parameter set with block length `r`
+    pub fn for_r(r: usize) -> Result<Self, String> {
+        let (slope, intercept, min) = match r {
+            12323 => (0.0069722, 13.530, 36),
+            24659 => (0.005265, 15.2588, 52),
+            40973 => (0.00402312, 17.8785, 69),
+            _ => return Err(format!("Unknown BIKE parameter set with r = {}", r)),
+        };
+        Ok(BgfParams {
+            r,
+            d: bike_column_weight(r)?,
+            iterations: 5,
+            tau: 3,
+            slope,
+            intercept,
+            min,
+        })
+    }
+
+    fn threshold(&self, syndrome_weight: usize) -> u32 {
+        let threshold = (self.slope * syndrome_weight as f64 + self.intercept).floor() as u32;
+        threshold.max(self.min)
+    }
+
+    /// The threshold of the black and gray iterations
+    fn mask_threshold(&self) -> u32 {
+        (self.d as u32 + 1) / 2 + 1
+    }
+}
+
+/// A decoder for the syndromes of one secret key. Positions 0..r of an error pattern are in e0,
+/// and r..2r in e1, like the error patterns of bike-attack.
+#[derive(Debug, Clone)]
+pub struct BgfDecoder {
+    params: BgfParams,
+    /// The supports of h0 and h1
+    h: [Vec<usize>; 2],
+}
+
+impl BgfDecoder {
+    pub fn new(params: BgfParams, key: &BikeSecretKey) -> Result<Self, String> {
+        let support = |h: &SparsePoly| h.support().iter().map(|&i| i as usize).collect();
+        let h: [Vec<usize>; 2] = [support(&key.h0), support(&key.h1)];
+        if key.h0.r() != params.r || key.h1.r() != params.r || h.iter().any(|h| h.len() != params.d)
+        {
+            return Err(format!(
+                "The secret key does not have two blocks of length {} and weight {}",
+                params.r, params.d
+            ));
+        }
+        Ok(BgfDecoder { params, h })
+    }
+
+    /// The syndrome e0·h0 + e1·h1 of the error pattern `e`, with one byte per bit
+    pub fn syndrome(&self, e: &[u32]) -> Vec<u8> {
+        let mut s = vec![0; self.params.r];
+        for &p in e {
+            self.flip_syndrome(&mut s, p as usize);
+        }
+        s
+    }
+
+    /// The syndrome c0·h0 of a ciphertext starting with c0 = e0 + e1·h
+    pub fn ciphertext_syndrome(&self, ct: &[u8]) -> Result<Vec<u8>, String> {
+        let r = self.params.r;
+        let c0 = Poly::from_bytes(r, ct)?;
+        let mut s = vec![0; r];
+        for i in c0.support() {
+            for &k in &self.h[0] {
+                s[(i as usize + k) % r] ^= 1;
+            }
+        }
+        Ok(s)
+    }
+
+    /// Whether the decoder finds exactly the error pattern `e`
+    pub fn decodes(&self, e: &[u32]) -> bool {
+        match self.decode(self.syndrome(e)) {
+            Some(decoded) => {
+                let mut e = e.to_vec();
+                e.sort_unstable();
+                decoded == e
+            }
+            None => false,
+        }
+    }
+
+    /// Decodes the syndrome `s` to the positions of an error pattern, or None if the syndrome is
+    /// not zero after the last iteration
+    pub fn decode(&self, s: Vec<u8>) -> Option<Vec<u32>> {
+        let (e, iterations) = self.run(s);
+        iterations?;
+        Some((0..e.len()).filter(|&p| e[p]).map(|p| p as u32).collect())
+    }
+
+    /// The number of iterations after which the syndrome `s` is zero, or None if it is not zero
+    /// after the last one. The fewer iterations the decoder needs, the farther `s` is from a
+    /// decoding failure.
+    pub fn iterations_to_zero(&self, s: Vec<u8>) -> Option<usize> {
+        self.run(s).1
+    }
+
+    /// Runs the iterations of the decoder on `s` until it is zero, as the remaining ones would
+    /// not flip anything, and returns the error pattern with the number of iterations it took
+    fn run(&self, mut s: Vec<u8>) -> (Vec<bool>, Option<usize>) {
+        let params = &self.params;
+        let n = 2 * params.r;
+        let mut e = vec![false; n];
+        let mut upc = vec![0; n];
+        for iteration in 0..params.iterations {
+            if s.iter().all(|&bit| bit == 0) {
+                return (e, Some(iteration));
+            }
+            let threshold = params.threshold(s.iter().filter(|&&bit| bit != 0).count());
+            self.unsatisfied_parity_checks(&s, &mut upc);
+            let (mut black, mut gray) = (vec![], vec![]);
+            for (p, &count) in upc.iter().enumerate() {
+                if count as u32 >= threshold {
+                    black.push(p);
+                } else if count as u32 + params.tau >= threshold {
+                    gray.push(p);
+                }
+            }
+            for &p in &black {
+                e[p] = !e[p];
+                self.flip_syndrome(&mut s, p);
+            }
+
+            if iteration == 0 {
+                for mask in [black, gray].iter() {
+                    self.unsatisfied_parity_checks(&s, &mut upc);
+                    for &p in mask {
+                        if upc[p] as u32 >= params.mask_threshold() {
+                            e[p] = !e[p];
+                            self.flip_syndrome(&mut s, p);
+                        }
+                    }
+                }
+            }
+        }
+
+        if s.iter().all(|&bit| bit == 0) {
+            (e, Some(params.iterations))
+        } else {
+            (e, None)
+        }
+    }
+
+    /// Toggles the syndrome bits of the parity checks of position `p`
+    fn flip_syndrome(&self, s: &mut [u8], p: usize) {
+        let r = self.params.r;
+        let (block, j) = (p / r, p % r);
+        for &k in &self.h[block] {
+            s[(j + k) % r] ^= 1;
+        }
+    }
+
+    /// Counts the unsatisfied parity checks of every position, as the sum of the syndrome bits at
+    /// the positions of h0 or h1 shifted by the position
+    fn unsatisfied_parity_checks(&self, s: &[u8], upc: &mut [u8]) {
+        let r = self.params.r;
+        for (h, upc) in self.h.iter().zip(upc.chunks_exact_mut(r)) {
+            upc.iter_mut().for_each(|count| *count = 0);
+            for &k in h {
+                // Split at the wrap around, so the loops can be vectorized
+                let (head, tail) = upc.split_at_mut(r - k);
+                head.iter_mut().zip(&s[k..]).for_each(|(c, &bit)| *c += bit);
+                tail.iter_mut().zip(&s[..k]).for_each(|(c, &bit)| *c += bit);
+            }
+        }
+    }
+}
+
+/// Decides decoding failures with [`BgfDecoder`] instead of the decapsulation of liboqs. A
+/// decoding failure is a syndrome that is not zero after the last iteration, the decoder
+/// converging to another error pattern with the same syndrome is negligible.
+#[derive(Debug, Clone)]
+pub struct BgfOracle<BIKE: Bike> {
+    decoder: Arc<BgfDecoder>,
+    _pd: PhantomData<BIKE>,
+}
+
+impl<BIKE: Bike + Send> DecodingFailureOracle<BIKE> for BgfOracle<BIKE> {
+    const NAME: &'static str = "BGF";
+
+    fn generate(
+        _num_profiling: i32,
+        _num_decaps: i32,
+        _num_validate: u32,
+        _pt: &mut BIKE::Plaintext,
+        _pt2: &mut BIKE::Plaintext,
+        _pk: &mut BIKE::PublicKey,
+        sk: &mut BIKE::SecretKey,
+    ) -> Result<Option<Self>, String>
+    where
+        Self: Sized,
+    {
+        let params = BgfParams::for_r(BIKE::params::<usize>().PARAM_R)?;
+        let key = read_bike_secret_key::<BIKE>(sk)?;
+        Ok(Some(BgfOracle {
+            decoder: Arc::new(BgfDecoder::new(params, &key)?),
+            _pd: PhantomData,
+        }))
+    }
+
+    fn decoding_failure(
+        &mut self,
+        ct: &mut BIKE::Ciphertext,
+        _sk: &mut BIKE::SecretKey,
+    ) -> Result<Option<bool>, String> {
+        // Stands in for the decapsulation of a real oracle
+        accounting::count(Operation::Decaps, 1);
+        let s = self.decoder.ciphertext_syndrome(ct.as_slice())?;
+        Ok(Some(self.decoder.decode(s).is_none()))
+    }
+
+    fn needs_ciphertext(&self) -> bool {
+        false
+    }
+
+    fn error_pattern_decoding_failure(
+        &mut self,
+        e: &[u32],
+        ct: &mut BIKE::Ciphertext,
+        sk: &mut BIKE::SecretKey,
+    ) -> Result<Option<bool>, String> {
+        Ok(self
+            .weighted_decoding_failure(e, ct, sk)?
+            .map(|(failure, _)| failure))
+    }
+
+    /// Decodes the syndrome of `e` itself, which is the one of its ciphertext. It is built from
+    /// the few positions of `e` instead of the dense c0, and needs no encapsulation. A decoding
+    /// success weighs one more than the number of iterations the decoder had left when the
+    /// syndrome became zero, a decoding failure weighs 1.
+    fn weighted_decoding_failure(
+        &mut self,
+        e: &[u32],
+        _ct: &mut BIKE::Ciphertext,
+        _sk: &mut BIKE::SecretKey,
+    ) -> Result<Option<(bool, u32)>, String> {
+        accounting::count(Operation::Decaps, 1);
+        let iterations = self.decoder.params.iterations;
+        let s = self.decoder.syndrome(e);
+        Ok(Some(match self.decoder.iterations_to_zero(s) {
+            Some(converged) => (false, (iterations - converged + 1) as u32),
+            None => (true, 1),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{marker::PhantomData, sync::Arc};
+
+    use liboqs_rs_bindings::{
+        bike::{Bike, BikeL1},
+        Kem, KemBuf, KemWithRejectionSampling,
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{BgfDecoder, BgfOracle, BgfParams};
+    use crate::attack::rejection_sampling::{
+        combinators::CombinatorOptions,
+        gf2x::{BikeSecretKey, SparsePoly},
+        noise::NoiseModel,
+        oracle::{DecodingFailureOracle, NoisyOracle},
+    };
+
+    fn random_support(r: usize, weight: usize, rng: &mut StdRng) -> Vec<u32> {
+        rand::seq::index::sample(rng, r, weight)
+            .into_iter()
+            .map(|i| i as u32)
+            .collect()
+    }
+
+    fn random_key(params: &BgfParams, rng: &mut StdRng) -> BikeSecretKey {
+        loop {
+            let key = BikeSecretKey {
+                h0: SparsePoly::new(params.r, &random_support(params.r, params.d, rng)),
+                h1: SparsePoly::new(params.r, &random_support(params.r, params.d, rng)),
+            };
+            if key.public_key().is_some() {
+                return key;
+            }
+        }
+    }
+
+    #[test]
+    fn test_bgf_decoder() {
+        let params = BgfParams::for_r(12323).unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        let key = random_key(&params, &mut rng);
+        let decoder = BgfDecoder::new(params, &key).unwrap();
+
+        // The syndrome of c0 = e0 + e1·h is the one of the error pattern
+        let e = random_support(2 * params.r, 134, &mut rng);
+        let (e0, e1): (Vec<u32>, Vec<u32>) = e.iter().partition(|&&p| (p as usize) < params.r);
+        let e1: Vec<u32> = e1.iter().map(|&p| p - params.r as u32).collect();
+        let mut c0 = key
+            .public_key()
+            .unwrap()
+            .mul_sparse(&SparsePoly::new(params.r, &e1));
+        e0.iter().for_each(|&p| c0.flip(p as usize));
+        let mut bytes = vec![0; (params.r + 7) / 8]; // div_ceil
+        c0.write_bytes(&mut bytes).unwrap();
+        assert_eq!(
+            decoder.ciphertext_syndrome(&bytes).unwrap(),
+            decoder.syndrome(&e)
+        );
+
+        // The decoding failure rate is negligible at the weight of the parameter set, but not far
+        // above it
+        assert!(decoder.decodes(&e));
+        assert!(!decoder.decodes(&random_support(2 * params.r, 300, &mut rng)));
+    }
+
+    #[test]
+    fn test_weighted_decoding_failure() {
+        let params = BgfParams::for_r(12323).unwrap();
+        let mut rng = StdRng::seed_from_u64(3);
+        let key = random_key(&params, &mut rng);
+        let decoder = Arc::new(BgfDecoder::new(params, &key).unwrap());
+
+        // Without errors there is nothing to do, at the weight of the parameter set the decoder
+        // converges within its iterations, and far above it not at all
+        assert_eq!(decoder.iterations_to_zero(decoder.syndrome(&[])), Some(0));
+        let e = random_support(2 * params.r, 134, &mut rng);
+        let converged = decoder.iterations_to_zero(decoder.syndrome(&e)).unwrap();
+        assert!((1..=params.iterations).contains(&converged));
+        let heavy = random_support(2 * params.r, 300, &mut rng);
+        assert_eq!(decoder.iterations_to_zero(decoder.syndrome(&heavy)), None);
+
+        let oracle = BgfOracle::<BikeL1> {
+            decoder,
+            _pd: PhantomData,
+        };
+        let mut ct = <BikeL1 as Kem>::Ciphertext::new();
+        let mut sk = <BikeL1 as Kem>::SecretKey::new();
+        let weight = (params.iterations - converged + 1) as u32;
+        // The weights pass through the combinators while they are transparent, not the cache
+        let noise_free = NoisyOracle::<_, BikeL1>::wrap(oracle, NoiseModel::default());
+        let mut combined = CombinatorOptions::default().wrap(noise_free.clone());
+        assert_eq!(
+            combined.weighted_decoding_failure(&e, &mut ct, &mut sk),
+            Ok(Some((false, weight)))
+        );
+        assert_eq!(
+            combined.weighted_decoding_failure(&heavy, &mut ct, &mut sk),
+            Ok(Some((true, 1)))
+        );
+        let cache = CombinatorOptions {
+            cache: true,
+            ..Default::default()
+        };
+        let mut cached = cache.wrap(noise_free);
+        assert_eq!(
+            cached.weighted_decoding_failure(&e, &mut ct, &mut sk),
+            Ok(Some((false, 1)))
+        );
+    }
+
+    /// Compares the decisions of the simulated decoder, from the ciphertext and from the error
+    /// pattern, to whether the decoder of liboqs succeeded
+    #[test]
+    fn test_bgf_oracle_matches_liboqs() {
+        let (mut pk, mut sk) = BikeL1::keypair().unwrap();
+        let pt = <BikeL1 as KemWithRejectionSampling>::Plaintext::new();
+        let mut bgf = BgfOracle::<BikeL1>::generate(
+            1,
+            1,
+            1,
+            &mut pt.clone(),
+            &mut pt.clone(),
+            &mut pk,
+            &mut sk,
+        )
+        .unwrap()
+        .unwrap();
+
+        let n = 2 * BikeL1::params::<usize>().PARAM_R;
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut ct = <BikeL1 as Kem>::Ciphertext::new();
+        let mut ss = <BikeL1 as Kem>::SharedSecret::new();
+        let mut failures = 0;
+        for sample in 0..1000 {
+            // Heavy enough patterns to cause some decoding failures
+            let e = random_support(n, rng.gen_range(160..200), &mut rng);
+            BikeL1::encaps_with_plaintext_and_error_pattern(&mut ct, &mut ss, &mut pk, &pt, &e)
+                .unwrap();
+            let (_, success) = BikeL1::decaps_intermediaries(&mut ct, &mut ss, &mut sk).unwrap();
+            let simulated = bgf.decoding_failure(&mut ct, &mut sk).unwrap();
+            let from_pattern = bgf
+                .error_pattern_decoding_failure(&e, &mut ct, &mut sk)
+                .unwrap();
+            assert_eq!(simulated, Some(!success), "sample {}", sample);
+            assert_eq!(from_pattern, Some(!success), "sample {}", sample);
+            failures += !success as u32;
+        }
+        assert!(failures > 0);
+    }
+}
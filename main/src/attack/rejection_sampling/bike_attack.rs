@@ -1,46 +1,277 @@
 use std::{
+    cell::Cell,
+    ffi::CString,
     fs::File,
-    io::{BufWriter, Write},
-    marker::PhantomData,
-    ops::Range,
+    io::{BufReader, BufWriter, Write},
     path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, AtomicU32, Ordering},
-        Arc,
+        Arc, Mutex,
     },
+    time::{Duration, Instant},
 };
 
 use csv::Writer;
 use libflate::finish::AutoFinishUnchecked;
 use liboqs_rs_bindings as oqs;
-use log::{debug, info};
+use log::{debug, info, warn};
 use log_derive::logfn_inputs;
+use ms_converter::ms_into_time;
 use oqs::{bike::Bike, Kem, KemBuf};
-use rand::{thread_rng, Rng};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
 use structopt::StructOpt;
 
 use crate::{
     attack::rejection_sampling::{
+        accounting::{self, BudgetOptions, Operation, Phase, QueryCounts},
         attack::get_keypair,
-        bike_error_weight::{
-            construct_decoding_failure_oracle, random_sparse_error_pattern, BikeIdealizedOracle,
-            DecodingFailureOracle, SparseErrorPattern,
+        bgf::BgfOracle,
+        bike_error_weight::BikeIdealizedOracle,
+        combinators::{CachingOracle, CombinatorOptions, ConfirmationPolicy},
+        decision_trace::{Confusion, DecisionTrace, Query, Tracer},
+        experiment::{root_seed, run_on_core, thread_work},
+        noise::{NoiseModel, NoiseOptions},
+        oracle::{
+            construct_decoding_failure_oracle, random_split_error_pattern, DecodingFailureOracle,
+            ErrorSplit, NoisyOracle, OracleKind, SparseErrorPattern, TimingOracle,
         },
+        rejections::{RejectionCounter, RejectionSamplingStats},
+    },
+    utils::{
+        get_stop_signal, measurement_cores, pin_to_core, ClonableProgressManager, FrameworkRng,
+        KeySeedOptions, ProgressBars, RngSeed, StrErr,
     },
-    utils::{thread_work, ClonableProgressManager, ProgressBars, StrErr},
 };
 
 type DistanceSpectrum = Vec<u32>;
-struct DistanceSpectrumCollection {
+
+/// The lists of a single chain, together with its current error pattern. The F, G, A and B lists
+/// count distances between positions in e1, which relate to h1, and the F0, G0, A0 and B0 lists
+/// between positions in e0, which relate to h0.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DistanceSpectrumCollection {
+    error_pattern: SparseErrorPattern,
     f: DistanceSpectrum,
     g: DistanceSpectrum,
     a: DistanceSpectrum,
     b: DistanceSpectrum,
+    f0: DistanceSpectrum,
+    g0: DistanceSpectrum,
+    a0: DistanceSpectrum,
+    b0: DistanceSpectrum,
     num_failures: u32,
     num_successes: u32,
+    /// The changes of the hamming weight of the chain, see [`WeightControl`]
+    weight_changes: Vec<WeightChange>,
+}
+
+impl DistanceSpectrumCollection {
+    pub fn new(error_pattern: SparseErrorPattern, max_distance: usize) -> Self {
+        DistanceSpectrumCollection {
+            error_pattern,
+            f: vec![0; max_distance],
+            g: vec![0; max_distance],
+            a: vec![0; max_distance],
+            b: vec![0; max_distance],
+            f0: vec![0; max_distance],
+            g0: vec![0; max_distance],
+            a0: vec![0; max_distance],
+            b0: vec![0; max_distance],
+            num_failures: 0,
+            num_successes: 0,
+            weight_changes: vec![],
+        }
+    }
+
+    /// Adds the lists and counts of `other`, keeping the error pattern and weight changes of self
+    pub fn merge(&mut self, other: &DistanceSpectrumCollection) {
+        for (dest, src) in [
+            (&mut self.f, &other.f),
+            (&mut self.g, &other.g),
+            (&mut self.a, &other.a),
+            (&mut self.b, &other.b),
+            (&mut self.f0, &other.f0),
+            (&mut self.g0, &other.g0),
+            (&mut self.a0, &other.a0),
+            (&mut self.b0, &other.b0),
+        ] {
+            dest.iter_mut()
+                .zip(src)
+                .for_each(|(dest, src)| *dest += src);
+        }
+        self.num_failures += other.num_failures;
+        self.num_successes += other.num_successes;
+    }
+
+    pub fn lists(&self) -> [(&'static str, &DistanceSpectrum); 8] {
+        [
+            ("f", &self.f),
+            ("g", &self.g),
+            ("a", &self.a),
+            ("b", &self.b),
+            ("f0", &self.f0),
+            ("g0", &self.g0),
+            ("a0", &self.a0),
+            ("b0", &self.b0),
+        ]
+    }
+
+    fn list_mut(&mut self, name: &str) -> Option<&mut DistanceSpectrum> {
+        match name {
+            "f" => Some(&mut self.f),
+            "g" => Some(&mut self.g),
+            "a" => Some(&mut self.a),
+            "b" => Some(&mut self.b),
+            "f0" => Some(&mut self.f0),
+            "g0" => Some(&mut self.g0),
+            "a0" => Some(&mut self.a0),
+            "b0" => Some(&mut self.b0),
+            _ => None,
+        }
+    }
+}
+
+/// A change of the hamming weight of a chain, made by the [`WeightControl`]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WeightChange {
+    /// The number of decoding failures of the chain when the weight changed
+    pub num_failures: u32,
+    pub num_successes: u32,
+    /// The decoding failure rate observed since the previous adaptation
+    pub dfr: f64,
+    /// The new hamming weight
+    pub weight: usize,
+}
+
+/// Moves the hamming weight of the chains towards a target decoding failure rate, within bounds
+#[derive(Debug, Clone, Copy)]
+struct WeightControl {
+    target_dfr: f64,
+    /// The number of decisions between adaptations
+    window: u32,
+    min_weight: usize,
+    max_weight: usize,
+}
+
+impl WeightControl {
+    /// The weight after observing `failures` in a window of decisions at `weight`. It only changes
+    /// when the failures are more than two standard deviations away from the expected number.
+    fn adapt(&self, weight: usize, failures: u32) -> usize {
+        let expected = self.target_dfr * self.window as f64;
+        let deviation = 2.0 * (expected * (1.0 - self.target_dfr)).sqrt();
+        let failures = failures as f64;
+        if failures < expected - deviation && weight < self.max_weight {
+            weight + 1
+        } else if failures > expected + deviation && weight > self.min_weight {
+            weight - 1
+        } else {
+            weight
+        }
+    }
 }
 
-#[derive(Debug, StructOpt)]
+/// Describes where the lists in a file come from, stored as JSON in the comment of its GZIP header
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ListsMetadata {
+    pub algorithm: String,
+    /// The name of the decoding failure oracle
+    pub oracle: String,
+    /// The SHA3-256 hash of the public key
+    pub key: String,
+    /// The hamming weight of the genesis pattern
+    pub hamming_weight: usize,
+    /// Missing from files written before the error could be split, which had all of it in e1
+    #[serde(default)]
+    pub split: ErrorSplit,
+    /// The simulated distinguisher noise
+    pub noise: NoiseModel,
+    /// The decoding failure rate the weight control aimed for, if enabled
+    #[serde(default)]
+    pub target_dfr: Option<f64>,
+    /// The number of decoding failures, i.e. the chain length
+    pub num_failures: u32,
+    pub num_successes: u32,
+    /// The weight changes of every chain
+    #[serde(default)]
+    pub weight_changes: Vec<Vec<WeightChange>>,
+    /// How the decisions compare to the truth, if traced
+    #[serde(default)]
+    pub confusion: Option<Confusion>,
+    /// The cost of the attack, not including the runs before a resumed checkpoint
+    #[serde(default)]
+    pub queries: QueryCounts,
+    /// How the answers of the oracle were combined and confirmed
+    #[serde(default)]
+    pub combinators: CombinatorOptions,
+    /// Whether the decoding successes are weighted by the grade of the oracle, see --graded
+    #[serde(default)]
+    pub graded: bool,
+}
+
+impl ListsMetadata {
+    /// Whether the lists of both files can be added together, or a checkpoint continued by the other attack
+    pub fn compatible(&self, other: &ListsMetadata) -> bool {
+        self.algorithm == other.algorithm
+            && self.oracle == other.oracle
+            && self.key == other.key
+            && self.hamming_weight == other.hamming_weight
+            && self.split == other.split
+            && self.target_dfr == other.target_dfr
+            && self.noise == other.noise
+            && self.combinators == other.combinators
+            && self.graded == other.graded
+    }
+}
+
+pub fn key_fingerprint(pk: &[u8]) -> String {
+    Sha3_256::digest(pk)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Everything needed to continue a chain with --resume
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+struct Checkpoint<BIKE: Bike> {
+    pk: BIKE::PublicKey,
+    sk: BIKE::SecretKey,
+    pt: BIKE::Plaintext,
+    /// The attack that wrote the checkpoint, without the counts of its chains
+    metadata: ListsMetadata,
+    genesis: SparseErrorPattern,
+    /// One chain per thread
+    chains: Vec<DistanceSpectrumCollection>,
+}
+
+impl<BIKE: Bike> Checkpoint<BIKE> {
+    fn read(path: &Path) -> Result<Self, String> {
+        info!("Reading checkpoint from {:?}", path);
+        let reader = BufReader::new(File::open(path).strerr()?);
+        bincode::deserialize_from(reader).strerr()
+    }
+
+    /// Writes to a temporary file first, so an interruption never leaves a broken checkpoint
+    fn write(&self, path: &Path) -> Result<(), String> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut writer = BufWriter::new(File::create(&tmp).strerr()?);
+        bincode::serialize_into(&mut writer, self).strerr()?;
+        writer.flush().strerr()?;
+        drop(writer);
+        std::fs::rename(&tmp, path).strerr()?;
+        debug!("Wrote checkpoint to {:?}", path);
+        Ok(())
+    }
+
+    fn chain_length(&self) -> u32 {
+        self.chains.iter().map(|c| c.num_failures).sum()
+    }
+}
+
+#[derive(Debug, Clone, StructOpt)]
 pub struct BikeAttackOptions {
     /// The number of threads to use
     #[structopt(short("t"), long)]
@@ -48,13 +279,58 @@ pub struct BikeAttackOptions {
     /// The hamming weight of the error patterns to use.
     #[structopt(short("w"), long)]
     hamming_weight: usize,
+    /// How to split the hamming weight between e0 and e1: "e1" for all of it in e1, "balanced" for half of it in
+    /// each, "uniform" for uniformly over both, or the number of positions in e0. Distances are tracked in both
+    /// blocks, those in e0 relate to h0 and those in e1 to h1.
+    #[structopt(long, default_value("e1"))]
+    split: ErrorSplit,
+    /// Adapt the hamming weight of each chain towards this decoding failure rate, i.e. the fraction of mutated error
+    /// patterns causing a decoding failure. Every weight change is recorded in the metadata of the destination.
+    #[structopt(long)]
+    target_dfr: Option<f64>,
+    /// The number of decisions of a chain between adaptations of its hamming weight
+    #[structopt(long, default_value("1000"))]
+    weight_window: u32,
+    /// The lowest hamming weight the adaptation may choose, defaults to 10 below the hamming weight
+    #[structopt(long, requires("target-dfr"))]
+    min_weight: Option<usize>,
+    /// The highest hamming weight the adaptation may choose, defaults to 10 above the hamming weight
+    #[structopt(long, requires("target-dfr"))]
+    max_weight: Option<usize>,
     /// The number of decoding failures to collect before doing
     /// the key recovery step from the collected distance spectrum.
     #[structopt(short("c"), long)]
-    chain_length: u32,
-    /// The probability of making a bad distinguisher decision, between 0.0 and 1.0
-    #[structopt(short, long, default_value("0.0"))]
-    epsilon: f64,
+    pub chain_length: u32,
+    #[structopt(flatten)]
+    noise: NoiseOptions,
+    #[structopt(flatten)]
+    pub combinators: CombinatorOptions,
+    /// The oracle used to detect decoding failures, either ideal, timing or bgf
+    #[structopt(short, long, default_value("ideal"))]
+    oracle: OracleKind,
+    /// Weigh every decoding success in the A and B lists by how clearly the oracle decided it, instead of counting
+    /// it once. Only the bgf oracle grades its answers, by one more than the number of iterations the decoder had
+    /// left when the syndrome became zero. Answers changed by the noise weigh 1. Cached and voted answers have no
+    /// grade, so it cannot be combined with --cache or a majority vote.
+    #[structopt(long)]
+    graded: bool,
+    /// The number of decapsulations to measure, in the profiling phase of the timing oracle.
+    #[structopt(short("p"), long, default_value("1000"))]
+    pub num_profiling: i32,
+    /// The number of decapsulations to measure, per decision of the timing oracle.
+    #[structopt(short("s"), long, default_value("100"))]
+    pub num_decaps: i32,
+    /// The minimum granularity of the timing oracle
+    #[structopt(short("i"), long, default_value("10"))]
+    pub delta_iter: u32,
+    /// Which rejection sampling counter the granularity is measured in, either iterations or seedexpanders
+    #[structopt(long, default_value("iterations"))]
+    pub delta_by: RejectionCounter,
+    /// The CPU cores to pin the threads to, one per thread, e.g. "2,3". For the timing oracle they should be isolated
+    /// from other processes (e.g. with isolcpus), and they default to the isolated cores, or the last cores with a
+    /// warning if not enough cores are isolated.
+    #[structopt(long, use_delimiter(true))]
+    pub cores: Vec<usize>,
     /// Location of plaintext database generated by the "attack rejection-sampling collect-plaintexts" command
     #[structopt(short("d"), long("db"))]
     pub plaintext_db: PathBuf,
@@ -64,98 +340,27 @@ pub struct BikeAttackOptions {
     /// Where to write the resulting GZIP compressed CSV file, e.g. "bike-ds.csv.gz" containing the distance spectrum to
     #[structopt(short("f"), long)]
     pub destination: PathBuf,
-}
-
-#[derive(Debug, Clone)]
-struct EpsilonWrappedOracle<INNER, BIKE>
-where
-    INNER: DecodingFailureOracle<BIKE>,
-    BIKE: Bike + Send,
-{
-    inner: INNER,
-    pub epsilon: f64,
-    since_inversion: u32,
-    _bike: PhantomData<BIKE>,
-}
-
-impl<INNER, BIKE> EpsilonWrappedOracle<INNER, BIKE>
-where
-    INNER: DecodingFailureOracle<BIKE>,
-    BIKE: Bike + Send,
-{
-    fn wrap(inner: INNER, epsilon: f64) -> Self {
-        EpsilonWrappedOracle {
-            inner,
-            epsilon,
-            since_inversion: 0,
-            _bike: PhantomData,
-        }
-    }
-}
-
-impl<INNER, BIKE> DecodingFailureOracle<BIKE> for EpsilonWrappedOracle<INNER, BIKE>
-where
-    INNER: DecodingFailureOracle<BIKE>,
-    BIKE: Bike + Send,
-{
-    const NAME: &'static str = INNER::NAME;
-
-    fn generate(
-        num_profiling: i32,
-        num_decaps: i32,
-        num_validate: u32,
-        pt: &mut BIKE::Plaintext,
-        pt_delta: &mut BIKE::Plaintext,
-        pk: &mut BIKE::PublicKey,
-        sk: &mut BIKE::SecretKey,
-    ) -> Result<Option<Self>, String>
-    where
-        Self: Sized,
-    {
-        if let Some(inner) = INNER::generate(
-            num_profiling,
-            num_decaps,
-            num_validate,
-            pt,
-            pt_delta,
-            pk,
-            sk,
-        )? {
-            Ok(Some(EpsilonWrappedOracle::wrap(inner, 0.0)))
-        } else {
-            Ok(None)
-        }
-    }
-
-    fn decoding_failure(
-        &mut self,
-        ct: &mut BIKE::Ciphertext,
-        sk: &mut BIKE::SecretKey,
-    ) -> Result<Option<bool>, String> {
-        Ok(if let Some(res) = self.inner.decoding_failure(ct, sk)? {
-            // With epsilon probability we return the inverted result
-            Some({
-                let mut invert = false;
-                if self.epsilon > 0.0 {
-                    self.since_inversion += 1;
-                    if thread_rng().gen_bool(self.epsilon) {
-                        debug!(
-                            "Due to epsilon = {} we inverted the detected decoding failure: {} to decoding failure: {} ({} since last inversion)",
-                            self.epsilon,
-                            res,
-                            !res,
-                            self.since_inversion
-                        );
-                        self.since_inversion = 0;
-                        invert = true;
-                    }
-                }
-                res ^ invert
-            })
-        } else {
-            None
-        })
-    }
+    /// Where to persist the state of the chain, e.g. "bike-ds.checkpoint". It is written at every checkpoint interval,
+    /// when the chain is complete and when interrupted with Ctrl-C.
+    #[structopt(long)]
+    pub checkpoint: Option<PathBuf>,
+    /// How often to write the checkpoint, e.g. "10m"
+    #[structopt(long, default_value("1m"), parse(try_from_str=ms_into_time))]
+    pub checkpoint_interval: Duration,
+    /// Where to write a GZIP compressed CSV file tracing every query, e.g. "bike-trace.csv.gz", with the answer and
+    /// confirmation of the oracle next to the truth of an idealized oracle. The confusion matrix of the decisions is
+    /// shown while running and stored in the metadata of the destination.
+    #[structopt(long)]
+    pub trace: Option<PathBuf>,
+    #[structopt(flatten)]
+    pub budget: BudgetOptions,
+    /// Continue the chain in the checkpoint file up to the chain length, instead of searching for a new genesis pattern.
+    /// The key pair and plaintext of the checkpoint are used, the other options of the attack must be the ones it was
+    /// written with.
+    #[structopt(short("r"), long, requires("checkpoint"))]
+    pub resume: bool,
+    #[structopt(flatten)]
+    pub key_seed: KeySeedOptions,
 }
 
 #[derive(Debug)]
@@ -164,58 +369,74 @@ enum GenesisSearchResults {
     Interrupted(u32),
 }
 
+#[allow(clippy::too_many_arguments)]
 fn search_genesis_pattern<BIKE, ORACLE>(
     pk: &mut BIKE::PublicKey,
     sk: &mut BIKE::SecretKey,
     pt: &mut BIKE::Plaintext,
     oracle: &mut ORACLE,
     hamming_weight: usize,
-    error_range: Range<usize>,
+    split: ErrorSplit,
+    budget: BudgetOptions,
     abort: Arc<AtomicBool>,
+    rng: &mut FrameworkRng,
 ) -> Result<Option<GenesisSearchResults>, String>
 where
     BIKE: Bike,
     ORACLE: DecodingFailureOracle<BIKE>,
 {
     debug!("Searching for genesis pattern...");
+    accounting::set_phase(Phase::GenesisSearch);
+    let r = BIKE::params::<usize>().PARAM_R;
     let mut ss = BIKE::SharedSecret::new();
     let mut ct = BIKE::Ciphertext::new();
     for attempt in 1.. {
-        let rsep = random_sparse_error_pattern(error_range.clone(), hamming_weight)?;
-        BIKE::encaps_with_plaintext_and_error_pattern(&mut ct, &mut ss, pk, pt, &rsep)?;
-        if let Some(decoding_failure) = oracle.decoding_failure(&mut ct, sk)? {
+        let rsep = random_split_error_pattern(r, split, hamming_weight, rng)?;
+        if oracle.needs_ciphertext() {
+            BIKE::encaps_with_plaintext_and_error_pattern(&mut ct, &mut ss, pk, pt, &rsep)?;
+            accounting::count(Operation::Encaps, 1);
+        }
+        if let Some(decoding_failure) = oracle.error_pattern_decoding_failure(&rsep, &mut ct, sk)? {
             if decoding_failure {
                 info!("Found a genesis pattern!");
                 return Ok(Some(GenesisSearchResults::Success((rsep, attempt))));
             }
         }
-        if abort.load(Ordering::Relaxed) {
+        if abort.load(Ordering::Relaxed) || budget.exhausted().is_some() {
             return Ok(Some(GenesisSearchResults::Interrupted(attempt)));
         }
     }
     unreachable!()
 }
 
+/// A distance between positions in the block e0 or e1 that was added to or removed from the error pattern
 enum DSDiff {
-    Add(u32),
-    Del(u32),
+    Add(usize, u32),
+    Del(usize, u32),
 }
 
-fn iter_ds_diff<BIKE: Bike>(
+/// The distances changed by moving `del` to `add` in `op`, for blocks of length `r`
+fn iter_ds_diff(
     op: &SparseErrorPattern,
+    r: u32,
     del: u32,
     add: u32,
 ) -> impl Iterator<Item = DSDiff> + '_ {
-    let r = BIKE::params::<u32>().PARAM_R;
+    let block = move |a: u32| (a / r) as usize;
     let distance = move |a: u32, b: u32| {
-        let d = a.abs_diff(b);
+        let d = (a % r).abs_diff(b % r);
         //info!("a: {}, b: {}, d: {}, r: {}", a, b, d, r);
         assert!(d < r);
         std::cmp::min(d, r - d)
     };
+    // Only positions in the same block have a distance in the distance spectrum of its part of the key
     op.iter().flat_map(move |bitnum| {
-        let i = std::iter::once(DSDiff::Del(distance(*bitnum, del)));
-        let i = i.chain(std::iter::once(DSDiff::Add(distance(*bitnum, add))));
+        let i = std::iter::once(DSDiff::Del(block(del), distance(*bitnum, del)))
+            .filter(move |_| block(*bitnum) == block(del));
+        let i = i.chain(
+            std::iter::once(DSDiff::Add(block(add), distance(*bitnum, add)))
+                .filter(move |_| block(*bitnum) == block(add)),
+        );
         i.filter(move |_| *bitnum != del)
     })
 }
@@ -225,54 +446,106 @@ fn generate_lists_fgab<BIKE, ORACLE>(
     pk: &mut BIKE::PublicKey,
     sk: &mut BIKE::SecretKey,
     pt: &mut BIKE::Plaintext,
-    oracle: &mut ORACLE,
-    mut error_pattern: SparseErrorPattern,
+    oracle: &mut CachingOracle<ORACLE, BIKE>,
+    confirmation_policy: ConfirmationPolicy,
+    graded: bool,
+    chain: &Mutex<DistanceSpectrumCollection>,
     chain_length: u32,
-    error_range: Range<usize>,
+    split: ErrorSplit,
+    control: Option<WeightControl>,
+    mut tracer: Option<(usize, Tracer<BIKE, BikeIdealizedOracle<BIKE>>)>,
+    budget: BudgetOptions,
     sync: Arc<AtomicU32>,
-) -> Result<DistanceSpectrumCollection, String>
+    stop: Arc<AtomicBool>,
+    rng: &mut FrameworkRng,
+) -> Result<(), String>
 where
-    BIKE: Bike,
+    BIKE: Bike + Send,
     ORACLE: DecodingFailureOracle<BIKE>,
 {
     debug!("Starting generation of distance spectrum...");
-    let max_distance = (BIKE::params::<usize>().PARAM_R + 1) / 2;
-    // F,G,A,B ← empty vectors
-    let mut f = vec![0; max_distance];
-    let mut g = vec![0; max_distance];
-    let mut a = vec![0; max_distance];
-    let mut b = vec![0; max_distance];
-
+    accounting::set_phase(Phase::Chain);
+    let r = BIKE::params::<usize>().PARAM_R;
     let mut ct = BIKE::Ciphertext::new();
     let mut ss = BIKE::SharedSecret::new();
 
+    // The trace compares to the truth of the idealized oracle, which needs the ciphertext
+    let encaps = oracle.needs_ciphertext() || tracer.is_some();
     let mut mutations = 0;
-    let mut num_failures = 0;
-    let mut num_successes = 0;
-    while sync.load(Ordering::Relaxed) < chain_length {
-        let (mutated_error_pattern, del, add) =
-            mutate_error_pattern(&error_pattern, error_range.clone());
+    let mut queries = 0;
+    let (mut decisions, mut window_failures) = (0, 0);
+    while sync.load(Ordering::Relaxed) < chain_length
+        && !stop.load(Ordering::Relaxed)
+        && budget.exhausted().is_none()
+    {
+        // Only this thread changes its chain, so the query can work on a copy and the lock is only
+        // taken to apply the result, without the checkpoint waiting for the oracle
+        let (error_pattern, num_failures) = {
+            let chain = chain.lock().strerr()?;
+            (chain.error_pattern.clone(), chain.num_failures)
+        };
+        let (mutated_error_pattern, del, add) = mutate_error_pattern(&error_pattern, r, split, rng);
         mutations += 1;
-        BIKE::encaps_with_plaintext_and_error_pattern(
-            &mut ct,
-            &mut ss,
-            pk,
-            pt,
-            &mutated_error_pattern,
-        )?;
-        if let Some(decoding_failure) = oracle.decoding_failure(&mut ct, sk)? {
-            let iter_diffs = iter_ds_diff::<BIKE>(&error_pattern, del, add);
+        if encaps {
+            BIKE::encaps_with_plaintext_and_error_pattern(
+                &mut ct,
+                &mut ss,
+                pk,
+                pt,
+                &mutated_error_pattern,
+            )?;
+            accounting::count(Operation::Encaps, 1);
+        }
+        let weighted = oracle.weighted_decoding_failure(&mutated_error_pattern, &mut ct, sk)?;
+        let answer = weighted.map(|(decoding_failure, _)| decoding_failure);
+        // Decoding failures extend the chain one at a time, so only the successes are weighted
+        let weight = match weighted {
+            Some((false, weight)) if graded => weight,
+            _ => 1,
+        };
+        // confirm results before succeeding
+        let confirmation = match answer {
+            Some(true) => {
+                accounting::set_phase(Phase::Confirmation);
+                let uncached = oracle.uncached();
+                let confirmation = confirmation_policy.confirm_by(|| {
+                    uncached.error_pattern_decoding_failure(&mutated_error_pattern, &mut ct, sk)
+                })?;
+                accounting::set_phase(Phase::Chain);
+                confirmation
+            }
+            _ => None,
+        };
+        if let Some((tid, tracer)) = &mut tracer {
+            let query = Query {
+                chain: *tid,
+                query: queries,
+                pattern: num_failures,
+                del,
+                add,
+            };
+            // The truth is not part of the cost of the attack
+            accounting::uncounted(|| tracer.record(query, &mut ct, sk, answer, confirmation))?;
+        }
+        queries += 1;
+        let mut guard = chain.lock().strerr()?;
+        let chain = &mut *guard;
+        if let Some(decoding_failure) = answer {
+            let iter_diffs = iter_ds_diff(&error_pattern, r as u32, del, add);
             if decoding_failure {
-                // confirm results before succeeding
-                if let Some(true) = oracle.decoding_failure(&mut ct, sk)? {
-                    num_failures += 1;
+                if let Some(true) = confirmation {
+                    chain.num_failures += 1;
+                    decisions += 1;
+                    window_failures += 1;
                     for diff in iter_diffs {
                         match diff {
-                            DSDiff::Add(distance) => f[distance as usize] += 1,
-                            DSDiff::Del(distance) => g[distance as usize] += 1,
+                            DSDiff::Add(0, distance) => chain.f0[distance as usize] += 1,
+                            DSDiff::Add(_, distance) => chain.f[distance as usize] += 1,
+                            DSDiff::Del(0, distance) => chain.g0[distance as usize] += 1,
+                            DSDiff::Del(_, distance) => chain.g[distance as usize] += 1,
                         }
                     }
-                    error_pattern = mutated_error_pattern;
+                    chain.error_pattern = mutated_error_pattern;
                     let current_chain_length = sync.fetch_add(1, Ordering::Relaxed) + 1;
                     debug!(
                         "decoding failure {} after {} mutations",
@@ -283,35 +556,89 @@ where
                     // We ignore this result
                 }
             } else {
-                num_successes += 1;
+                chain.num_successes += 1;
+                decisions += 1;
                 for diff in iter_diffs {
                     match diff {
-                        DSDiff::Add(distance) => a[distance as usize] += 1,
-                        DSDiff::Del(distance) => b[distance as usize] += 1,
+                        DSDiff::Add(0, distance) => chain.a0[distance as usize] += weight,
+                        DSDiff::Add(_, distance) => chain.a[distance as usize] += weight,
+                        DSDiff::Del(0, distance) => chain.b0[distance as usize] += weight,
+                        DSDiff::Del(_, distance) => chain.b[distance as usize] += weight,
                     }
                 }
             }
         }
+
+        if let Some(control) = &control {
+            if decisions >= control.window {
+                let weight = chain.error_pattern.len();
+                let new_weight = control.adapt(weight, window_failures);
+                if new_weight != weight {
+                    let dfr = window_failures as f64 / decisions as f64;
+                    info!(
+                        "Changing the hamming weight of a chain from {} to {} at a decoding failure rate of {}",
+                        weight, new_weight, dfr
+                    );
+                    resize_error_pattern(
+                        &mut chain.error_pattern,
+                        r,
+                        split,
+                        new_weight > weight,
+                        rng,
+                    );
+                    chain.weight_changes.push(WeightChange {
+                        num_failures: chain.num_failures,
+                        num_successes: chain.num_successes,
+                        dfr,
+                        weight: new_weight,
+                    });
+                }
+                decisions = 0;
+                window_failures = 0;
+            }
+        }
     }
 
-    Ok(DistanceSpectrumCollection {
-        f,
-        g,
-        a,
-        b,
-        num_failures,
-        num_successes,
-    })
+    Ok(())
+}
+
+/// Adds or removes a random position of the error pattern, keeping the split
+fn resize_error_pattern(
+    error_pattern: &mut SparseErrorPattern,
+    r: usize,
+    split: ErrorSplit,
+    grow: bool,
+    rng: &mut FrameworkRng,
+) {
+    let range = split.resize_range(r, error_pattern, grow);
+    if grow {
+        loop {
+            let add = rng.gen_range(range.clone()) as u32;
+            if !error_pattern.contains(&add) {
+                error_pattern.push(add);
+                return;
+            }
+        }
+    }
+    let candidates: Vec<usize> = (0..error_pattern.len())
+        .filter(|&idx| range.contains(&(error_pattern[idx] as usize)))
+        .collect();
+    if !candidates.is_empty() {
+        error_pattern.swap_remove(candidates[rng.gen_range(0..candidates.len())]);
+    }
 }
 
+/// Moves a random position of the error pattern, within its block unless the split is uniform
 fn mutate_error_pattern(
     error_pattern: &SparseErrorPattern,
-    range: Range<usize>,
+    r: usize,
+    split: ErrorSplit,
+    rng: &mut FrameworkRng,
 ) -> (SparseErrorPattern, u32, u32) {
-    let mut rng = thread_rng();
     let mut error_pattern = error_pattern.clone();
     let idx = rng.gen_range(0..error_pattern.len());
     let del = error_pattern[idx];
+    let range = split.mutation_range(r, del);
     loop {
         let add = rng.gen_range(range.clone()) as u32;
         if !error_pattern.contains(&add) {
@@ -326,158 +653,397 @@ fn attack<BIKE, ORACLE>(
     pk: BIKE::PublicKey,
     sk: BIKE::SecretKey,
     pt: BIKE::Plaintext,
-    mut oracle: EpsilonWrappedOracle<ORACLE, BIKE>,
-) -> Result<(), String>
+    mut oracle: NoisyOracle<ORACLE, BIKE>,
+    checkpoint: Option<Checkpoint<BIKE>>,
+) -> Result<Option<ListsMetadata>, String>
 where
     BIKE: Bike + Send + 'static,
     ORACLE: DecodingFailureOracle<BIKE> + 'static,
 {
-    let params = BIKE::params();
-    let error_range = params.PARAM_R..params.PARAM_N;
-    oracle.epsilon = 0.0; // Zero for the first phase
-
-    // Find the first e which causes a decoding failure
-    info!("Starting search for genesis pattern!");
-
-    let hamming_weight = opt.hamming_weight;
-    let mut patterns = {
-        let pk = pk.clone();
-        let sk = sk.clone();
-        let pt = pt.clone();
-        let oracle = oracle.clone();
-        let error_range = error_range.clone();
-        let search_results = thread_work(
-            opt.threads,
-            (pk, sk, pt, oracle, error_range, hamming_weight),
-            move |_tid,
-                  abort: Arc<AtomicBool>,
-                  (mut pk, mut sk, mut pt, mut oracle, error_range, hamming_weight)| {
-                let result = search_genesis_pattern(
-                    &mut pk,
-                    &mut sk,
-                    &mut pt,
-                    &mut oracle,
-                    hamming_weight,
-                    error_range,
-                    abort.clone(),
-                );
-                debug!("Stopped search: {:?}", result);
-                abort.store(true, Ordering::Relaxed);
-                result
-            },
-            |sync| sync.load(Ordering::Relaxed),
-        )?;
-        let attempts: u32 = search_results
-            .iter()
-            .map(|gsr| match gsr {
-                GenesisSearchResults::Success((_, attempts)) => *attempts,
-                GenesisSearchResults::Interrupted(attempts) => *attempts,
-            })
-            .sum();
-        info!("Genesis pattern search ended after {} attempts", attempts);
-        search_results.into_iter().filter_map(|gsr| match gsr {
-            GenesisSearchResults::Success((pattern, _)) => Some(pattern),
-            _ => None,
-        })
+    let params = BIKE::params::<usize>();
+    let max_distance = (params.PARAM_R + 1) / 2; // div_ceil
+    let split = opt.split;
+    let noise = opt.noise.model()?;
+    let control = match opt.target_dfr {
+        Some(target_dfr) if target_dfr <= 0.0 || target_dfr >= 1.0 => {
+            return Err(format!(
+                "The target DFR must be between 0.0 and 1.0, got {}",
+                target_dfr
+            ))
+        }
+        Some(target_dfr) => {
+            let control = WeightControl {
+                target_dfr,
+                window: opt.weight_window,
+                min_weight: opt
+                    .min_weight
+                    .unwrap_or_else(|| opt.hamming_weight.saturating_sub(10))
+                    .max(1),
+                max_weight: opt.max_weight.unwrap_or(opt.hamming_weight + 10),
+            };
+            info!("Adapting the hamming weight: {:?}", control);
+            Some(control)
+        }
+        None => None,
     };
 
-    let chain_length = opt.chain_length;
-    if let Some(error_pattern) = patterns.next() {
-        oracle.epsilon = opt.epsilon;
-        let gcpm = ClonableProgressManager::create();
-        gcpm.add(
-            0u8,
-            chain_length as u64,
-            "Generating distance spectrum",
-            " [{elapsed_precise}] {bar:40} {pos:>7}/{len:7} [{eta_precise}] {wide_msg} ",
-        );
-        gcpm.start(None);
-
-        // Main body
-        info!("Starting generation of distance spectrum...");
-        let mut list_of_lists_of_ds = thread_work(
-            opt.threads,
-            (pk, sk, pt, oracle, error_pattern, error_range),
-            move |_tid,
-                  sync: Arc<AtomicU32>,
-                  (mut pk, mut sk, mut pt, mut oracle, error_pattern, error_range)| {
-                Ok(Some(generate_lists_fgab(
-                    &mut pk,
-                    &mut sk,
-                    &mut pt,
-                    &mut oracle,
-                    error_pattern,
-                    chain_length,
-                    error_range,
-                    sync,
-                )?))
-            },
-            |sync| {
-                let value = sync.load(Ordering::Relaxed);
-                gcpm.set_position(0, value as u64);
-                gcpm.tick();
-                value >= chain_length
-            },
-        )?;
-        info!("Finished generating the distance spectrum...");
+    // The counts are filled in when the lists are written
+    let attack_metadata = ListsMetadata {
+        algorithm: BIKE::NAME.to_string(),
+        oracle: ORACLE::NAME.to_string(),
+        key: key_fingerprint(pk.as_slice()),
+        hamming_weight: opt.hamming_weight,
+        split,
+        noise,
+        target_dfr: opt.target_dfr,
+        num_failures: 0,
+        num_successes: 0,
+        weight_changes: vec![],
+        confusion: None,
+        queries: QueryCounts::default(),
+        combinators: opt.combinators,
+        graded: opt.graded,
+    };
+
+    let (genesis, mut chains) = match checkpoint {
+        Some(checkpoint) => {
+            if !checkpoint.metadata.compatible(&attack_metadata) {
+                return Err(format!(
+                    "The checkpoint was written by another attack, it can not be continued with other options: {:?} and {:?}",
+                    checkpoint.metadata, attack_metadata
+                ));
+            }
+            info!("Resuming the chain at length {}", checkpoint.chain_length());
+            (checkpoint.genesis, checkpoint.chains)
+        }
+        None => {
+            oracle.noise = NoiseModel::default(); // Noise free for the first phase
 
-        gcpm.set_position(0, chain_length as u64);
-        gcpm.tick();
-        gcpm.stop();
+            // Find the first e which causes a decoding failure
+            info!("Starting search for genesis pattern!");
 
-        // Postamble
-        // A' ← (max)(A) −A + min(A)
-        // D ←F + G + A′ + B
+            let hamming_weight = opt.hamming_weight;
+            let mut patterns = {
+                let pk = pk.clone();
+                let sk = sk.clone();
+                let pt = pt.clone();
+                let oracle = oracle.clone();
+                let cores = opt.cores.clone();
+                let budget = opt.budget;
+                let combinators = opt.combinators;
+                let search_results = thread_work(
+                    opt.threads,
+                    root_seed().purpose("genesis-search"),
+                    (pk, sk, pt, oracle, split, hamming_weight, cores),
+                    move |tid,
+                          abort: Arc<AtomicBool>,
+                          (mut pk, mut sk, mut pt, mut oracle, split, hamming_weight, cores),
+                          seed: RngSeed| {
+                        if let Some(&core) = cores.get(tid) {
+                            pin_to_core(core)?;
+                        }
+                        oracle.reseed(seed.purpose("epsilon"));
+                        let result = search_genesis_pattern(
+                            &mut pk,
+                            &mut sk,
+                            &mut pt,
+                            &mut combinators.wrap(oracle),
+                            hamming_weight,
+                            split,
+                            budget,
+                            abort.clone(),
+                            &mut seed.rng(),
+                        );
+                        debug!("Stopped search: {:?}", result);
+                        abort.store(true, Ordering::Relaxed);
+                        result
+                    },
+                    |sync| sync.load(Ordering::Relaxed),
+                )?;
+                let attempts: u32 = search_results
+                    .iter()
+                    .map(|gsr| match gsr {
+                        GenesisSearchResults::Success((_, attempts)) => *attempts,
+                        GenesisSearchResults::Interrupted(attempts) => *attempts,
+                    })
+                    .sum();
+                info!("Genesis pattern search ended after {} attempts", attempts);
+                search_results.into_iter().filter_map(|gsr| match gsr {
+                    GenesisSearchResults::Success((pattern, _)) => Some(pattern),
+                    _ => None,
+                })
+            };
+            match patterns.next() {
+                Some(genesis) => (genesis, vec![]),
+                None => {
+                    if let Some(reason) = opt.budget.exhausted() {
+                        warn!("No genesis pattern was found before {}", reason);
+                    }
+                    info!("Cost of the attack: {}", QueryCounts::snapshot());
+                    return Ok(None);
+                }
+            }
+        }
+    };
 
-        info!("Opending target file: {:?}", opt.destination);
-        let mut writer = csv::Writer::from_writer(AutoFinishUnchecked::new(
-            libflate::gzip::Encoder::new(BufWriter::new(File::create(&opt.destination).strerr()?))
-                .strerr()?,
+    // Threads without a chain in the checkpoint start a new one from the genesis pattern
+    while chains.len() < opt.threads {
+        chains.push(DistanceSpectrumCollection::new(
+            genesis.clone(),
+            max_distance,
         ));
-        writer
-            .write_record(&["list", "distance", "count"])
-            .strerr()?;
-
-        let decoding_successes: f64 = list_of_lists_of_ds
-            .iter()
-            .map(|l| l.num_successes as f64)
-            .sum();
-        let decoding_failure: f64 = list_of_lists_of_ds
-            .iter()
-            .map(|l| l.num_failures as f64)
-            .sum();
-        info!(
-            "Number of: decoding failures: {}, decoding successes: {}, decoding failure rate: {}",
-            decoding_failure,
-            decoding_successes,
-            decoding_successes / decoding_failure
-        );
+    }
+    let resumed: u32 = chains.iter().map(|c| c.num_failures).sum();
+    let chains: Arc<Vec<Mutex<DistanceSpectrumCollection>>> =
+        Arc::new(chains.into_iter().map(Mutex::new).collect());
+    let remaining = opt.chain_length.saturating_sub(resumed);
+
+    let write_checkpoint = |chains: &[Mutex<DistanceSpectrumCollection>]| match &opt.checkpoint {
+        Some(path) => Checkpoint::<BIKE> {
+            pk: pk.clone(),
+            sk: sk.clone(),
+            pt: pt.clone(),
+            metadata: attack_metadata.clone(),
+            genesis: genesis.clone(),
+            chains: snapshot(chains)?,
+        }
+        .write(path),
+        None => Ok(()),
+    };
+
+    let trace = match &opt.trace {
+        Some(path) => Some(Arc::new(DecisionTrace::create(path)?)),
+        None => None,
+    };
+    let tracer = match &trace {
+        Some(trace) => {
+            let truth = BikeIdealizedOracle::<BIKE>::generate(
+                1,
+                1,
+                1,
+                &mut pt.clone(),
+                &mut pt.clone(),
+                &mut pk.clone(),
+                &mut sk.clone(),
+            )?
+            .ok_or_else(|| "Failed to construct the idealized oracle".to_string())?;
+            Some(Tracer::new(truth, trace.clone()))
+        }
+        None => None,
+    };
 
-        if let Some(mut l1) = list_of_lists_of_ds.pop() {
-            for l2 in list_of_lists_of_ds {
-                l1.f.iter_mut()
-                    .zip(l2.f)
-                    .for_each(|(dest, src)| *dest += src);
-                l1.g.iter_mut()
-                    .zip(l2.g)
-                    .for_each(|(dest, src)| *dest += src);
-                l1.a.iter_mut()
-                    .zip(l2.a)
-                    .for_each(|(dest, src)| *dest += src);
-                l1.b.iter_mut()
-                    .zip(l2.b)
-                    .for_each(|(dest, src)| *dest += src);
+    oracle.noise = noise;
+    let stop = get_stop_signal()?;
+    let gcpm = ClonableProgressManager::create();
+    gcpm.add(
+        0u8,
+        opt.chain_length as u64,
+        "Generating distance spectrum, stop with Ctrl-C",
+        " [{elapsed_precise}] {bar:40} {pos:>7}/{len:7} [{eta_precise}] {wide_msg} ",
+    );
+    gcpm.start(None);
+
+    // Main body
+    info!("Starting generation of distance spectrum...");
+    let seed = match resumed {
+        0 => root_seed().purpose("distance-spectrum"),
+        // Do not repeat the mutations made before the checkpoint
+        _ => root_seed()
+            .purpose("distance-spectrum")
+            .purpose(&format!("resume-{}", resumed)),
+    };
+    let last_checkpoint = Cell::new(Instant::now());
+    let budget = opt.budget;
+    let combinators = opt.combinators;
+    let graded = opt.graded;
+    thread_work(
+        opt.threads,
+        seed,
+        (
+            pk.clone(),
+            sk.clone(),
+            pt.clone(),
+            oracle,
+            split,
+            control,
+            tracer,
+            chains.clone(),
+            stop.clone(),
+            opt.cores.clone(),
+        ),
+        move |tid,
+              sync: Arc<AtomicU32>,
+              (mut pk, mut sk, mut pt, mut oracle, split, control, tracer, chains, stop, cores),
+              seed: RngSeed| {
+            if let Some(&core) = cores.get(tid) {
+                pin_to_core(core)?;
+            }
+            oracle.reseed(seed.purpose("epsilon"));
+            let mut oracle = combinators.wrap(oracle);
+            generate_lists_fgab(
+                &mut pk,
+                &mut sk,
+                &mut pt,
+                &mut oracle,
+                combinators.confirmation,
+                graded,
+                &chains[tid],
+                remaining,
+                split,
+                control,
+                tracer.map(|tracer| (tid, tracer)),
+                budget,
+                sync,
+                stop,
+                &mut seed.rng(),
+            )?;
+            if combinators.cache {
+                debug!("{} queries were answered from the cache", oracle.hits());
+            }
+            Ok(Some(()))
+        },
+        |sync| {
+            let value = sync.load(Ordering::Relaxed);
+            gcpm.set_position(0, (resumed + value) as u64);
+            if let Some(trace) = &trace {
+                gcpm.set_message(0, trace.confusion().to_string());
+            }
+            gcpm.tick();
+            if last_checkpoint.get().elapsed() >= opt.checkpoint_interval {
+                if let Err(err) = write_checkpoint(&chains) {
+                    warn!("Failed to write the checkpoint: {}", err);
+                }
+                last_checkpoint.set(Instant::now());
             }
-            write_ds(l1.f, "f", &mut writer);
-            write_ds(l1.g, "g", &mut writer);
-            write_ds(l1.a, "a", &mut writer);
-            write_ds(l1.b, "b", &mut writer);
+            value >= remaining || stop.load(Ordering::Relaxed) || budget.exhausted().is_some()
+        },
+    )?;
+    gcpm.stop();
+
+    let confusion = match &trace {
+        Some(trace) => {
+            trace.flush()?;
+            let confusion = trace.confusion();
+            info!("Confusion of the decisions: {}", confusion);
+            Some(confusion)
         }
+        None => None,
+    };
+    write_checkpoint(&chains)?;
+    let chains = snapshot(&chains)?;
+    let reached: u32 = chains.iter().map(|c| c.num_failures).sum();
+    let queries = QueryCounts::snapshot();
+    info!("Cost of the attack: {}", queries);
+    if let (true, Some(reason)) = (reached < opt.chain_length, budget.exhausted()) {
+        warn!(
+            "Stopped at chain length {} of {}, as {}, writing the partial lists",
+            reached, opt.chain_length, reason
+        );
+    } else if reached < opt.chain_length {
+        warn!(
+            "Interrupted at chain length {} of {}, writing the partial lists",
+            reached, opt.chain_length
+        );
+    } else {
+        info!("Finished generating the distance spectrum...");
+    }
+
+    // Postamble
+    // A' ← (max)(A) −A + min(A)
+    // D ←F + G + A′ + B
+    let mut lists = DistanceSpectrumCollection::new(genesis, max_distance);
+    chains.iter().for_each(|chain| lists.merge(chain));
+    let metadata = ListsMetadata {
+        num_failures: lists.num_failures,
+        num_successes: lists.num_successes,
+        weight_changes: chains.iter().map(|c| c.weight_changes.clone()).collect(),
+        confusion,
+        queries,
+        ..attack_metadata
+    };
+    write_lists(&opt.destination, &metadata, &lists)?;
+    Ok(Some(metadata))
+}
+
+fn snapshot(
+    chains: &[Mutex<DistanceSpectrumCollection>],
+) -> Result<Vec<DistanceSpectrumCollection>, String> {
+    chains
+        .iter()
+        .map(|chain| Ok(chain.lock().strerr()?.clone()))
+        .collect()
+}
+
+pub fn write_lists(
+    destination: &Path,
+    metadata: &ListsMetadata,
+    lists: &DistanceSpectrumCollection,
+) -> Result<(), String> {
+    info!("Opending target file: {:?}", destination);
+    let comment = CString::new(serde_json::to_string(metadata).strerr()?).strerr()?;
+    let header = libflate::gzip::HeaderBuilder::new()
+        .comment(comment)
+        .finish();
+    let mut writer = csv::Writer::from_writer(AutoFinishUnchecked::new(
+        libflate::gzip::Encoder::with_options(
+            BufWriter::new(File::create(destination).strerr()?),
+            libflate::gzip::EncodeOptions::new().header(header),
+        )
+        .strerr()?,
+    ));
+    writer
+        .write_record(&["list", "distance", "count"])
+        .strerr()?;
+
+    info!(
+        "Number of: decoding failures: {}, decoding successes: {}, decoding failure rate: {}",
+        lists.num_failures,
+        lists.num_successes,
+        lists.num_successes as f64 / lists.num_failures as f64
+    );
+    for (name, list) in lists.lists().iter() {
+        write_ds(list.iter().copied(), name, &mut writer);
     }
 
     Ok(())
 }
 
+/// Reads the lists written by [`write_lists`], and the metadata unless the file predates it
+pub fn read_lists(
+    source: &Path,
+    max_distance: usize,
+) -> Result<(Option<ListsMetadata>, DistanceSpectrumCollection), String> {
+    info!("Reading distance spectrum lists from {:?}", source);
+    let reader =
+        libflate::gzip::Decoder::new(BufReader::new(File::open(source).strerr()?)).strerr()?;
+    let metadata = match reader.header().comment() {
+        Some(comment) => {
+            Some(serde_json::from_slice::<ListsMetadata>(comment.as_bytes()).strerr()?)
+        }
+        None => None,
+    };
+
+    let mut lists = DistanceSpectrumCollection::new(vec![], max_distance);
+    for record in csv::Reader::from_reader(reader).deserialize() {
+        let (list, distance, count): (String, usize, u32) = record.strerr()?;
+        let entry = lists
+            .list_mut(&list)
+            .and_then(|list| list.get_mut(distance))
+            .ok_or_else(|| {
+                format!(
+                    "Distance {} of list {} is out of range, is {:?} from another parameter set?",
+                    distance, list, source
+                )
+            })?;
+        *entry += count;
+    }
+    if let Some(metadata) = &metadata {
+        lists.num_failures = metadata.num_failures;
+        lists.num_successes = metadata.num_successes;
+    }
+    Ok((metadata, lists))
+}
+
 pub fn write_ds<W: Write, I: IntoIterator<Item = u32>>(
     values: I,
     name: &str,
@@ -506,13 +1072,7 @@ pub fn read_keypair<KEM: Kem>(
         Err(err) => match err.kind() {
             std::io::ErrorKind::NotFound if create_if_missing => {
                 let (pk, sk) = get_keypair::<KEM>()?;
-
-                info!("Writing random key-pair to {:?}", key_file);
-                let writer = File::create(key_file).strerr()?;
-
-                // Serialize to file
-                bincode::serialize_into(writer, &(&pk, &sk)).strerr()?;
-
+                write_keypair::<KEM>(key_file, &pk, &sk)?;
                 Ok((pk, sk))
             }
             _ => Err(err.to_string()),
@@ -520,30 +1080,374 @@ pub fn read_keypair<KEM: Kem>(
     }
 }
 
+pub fn write_keypair<KEM: Kem>(
+    key_file: &Path,
+    pk: &KEM::PublicKey,
+    sk: &KEM::SecretKey,
+) -> Result<(), String> {
+    info!("Writing random key-pair to {:?}", key_file);
+    let writer = File::create(key_file).strerr()?;
+
+    // Serialize to file
+    bincode::serialize_into(writer, &(pk, sk)).strerr()
+}
+
+pub fn run<BIKE: 'static + Bike + RejectionSamplingStats + std::marker::Send>(
+    opt: BikeAttackOptions,
+) -> Result<(), String> {
+    let chain_length = opt.chain_length;
+    // Fails unless the chain is complete, so scripts keep the checkpoint of a partial chain
+    match run_with_result::<BIKE>(opt)? {
+        Some(metadata) if metadata.num_failures >= chain_length => Ok(()),
+        Some(metadata) => Err(format!(
+            "Stopped at chain length {} of {}, resume from the checkpoint to complete it",
+            metadata.num_failures, chain_length
+        )),
+        None => Err("Stopped before a genesis pattern was found".to_string()),
+    }
+}
+
+/// Returns the metadata of the written lists, or nothing if the attack was interrupted before a
+/// genesis pattern was found
 #[logfn_inputs(Trace)]
-pub fn run<BIKE: 'static + Bike + std::marker::Send>(opt: BikeAttackOptions) -> Result<(), String> {
+pub fn run_with_result<BIKE: 'static + Bike + RejectionSamplingStats + std::marker::Send>(
+    mut opt: BikeAttackOptions,
+) -> Result<Option<ListsMetadata>, String> {
     info!("Launching rejection sampling attack on BIKE secret key.");
+    opt.key_seed.apply();
+    accounting::reset();
+
+    let checkpoint = match &opt.checkpoint {
+        Some(path) if opt.resume => Some(Checkpoint::<BIKE>::read(path)?),
+        _ => None,
+    };
 
-    let (mut pk, mut sk) = if let Some(key_file) = &opt.reuse_key_pair {
+    let (pk, sk) = if let Some(checkpoint) = &checkpoint {
+        if let Some(key_file) = &opt.reuse_key_pair {
+            let (pk, _) = read_keypair::<BIKE>(key_file, false)?;
+            if pk.as_slice() != checkpoint.pk.as_slice() {
+                return Err(format!(
+                    "The key pair in {:?} is not the one of the checkpoint",
+                    key_file
+                ));
+            }
+        }
+        (checkpoint.pk.clone(), checkpoint.sk.clone())
+    } else if let Some(key_file) = &opt.reuse_key_pair {
         read_keypair::<BIKE>(key_file, true)?
     } else {
         get_keypair::<BIKE>()?
     };
 
+    if opt.cores.is_empty() && opt.oracle == OracleKind::Timing {
+        // Measurements on shared cores disturb each other
+        opt.cores = measurement_cores(opt.threads)?;
+    }
+    if opt.graded && opt.oracle != OracleKind::Bgf {
+        return Err(
+            "Only the bgf oracle grades its answers, --graded needs --oracle bgf".to_string(),
+        );
+    }
+    if opt.graded && opt.combinators.cache {
+        return Err(
+            "Cached answers have no grade, --graded cannot be used with --cache".to_string(),
+        );
+    }
+    if opt.graded && opt.combinators.majority.map_or(false, |vote| vote.n > 1) {
+        return Err(
+            "Voted answers have no grade, --graded cannot be used with --majority".to_string(),
+        );
+    }
+    if !opt.cores.is_empty() && opt.cores.len() < opt.threads {
+        return Err(format!(
+            "Got {} cores to pin {} threads to",
+            opt.cores.len(),
+            opt.threads
+        ));
+    }
+
+    match opt.oracle {
+        OracleKind::Ideal => {
+            start::<BIKE, BikeIdealizedOracle<BIKE>>(opt, pk, sk, checkpoint, (1, 1, 1))
+        }
+        OracleKind::Timing => {
+            let profiling = (opt.num_profiling, opt.num_decaps, opt.delta_iter);
+            start::<BIKE, TimingOracle<BIKE>>(opt, pk, sk, checkpoint, profiling)
+        }
+        OracleKind::Bgf => start::<BIKE, BgfOracle<BIKE>>(opt, pk, sk, checkpoint, (1, 1, 1)),
+    }
+}
+
+/// Constructs the oracle, with `profiling` as the number of profiling decapsulations, the number
+/// of decapsulations per decision and the granularity, and starts the attack
+fn start<BIKE, ORACLE>(
+    opt: BikeAttackOptions,
+    mut pk: BIKE::PublicKey,
+    mut sk: BIKE::SecretKey,
+    checkpoint: Option<Checkpoint<BIKE>>,
+    (num_profiling, num_decaps, delta_iter): (i32, i32, u32),
+) -> Result<Option<ListsMetadata>, String>
+where
+    BIKE: 'static + Bike + RejectionSamplingStats + Send,
+    ORACLE: DecodingFailureOracle<BIKE> + 'static,
+{
     // pt ← Plaintext such that H(pt) is easily distinguishable by timing attack
-    let (inner, pt) = construct_decoding_failure_oracle::<BIKE, BikeIdealizedOracle<BIKE>>(
-        &opt.plaintext_db,
-        1,
-        1,
-        1,
-        &mut pk,
-        &mut sk,
-    )?;
+    // The profiling is done on the first core, under the same conditions as the measurements
+    let plaintext_db = opt.plaintext_db.clone();
+    let delta_by = opt.delta_by;
+    let (inner, pt, pk, sk) = run_on_core(opt.cores.first().copied(), move || {
+        accounting::set_phase(Phase::Profiling);
+        let (inner, pt) = construct_decoding_failure_oracle::<BIKE, ORACLE>(
+            &plaintext_db,
+            num_profiling,
+            num_decaps,
+            delta_iter,
+            delta_by,
+            &mut pk,
+            &mut sk,
+        )?;
+        Ok((inner, pt, pk, sk))
+    })?;
+
+    if let Some(checkpoint) = &checkpoint {
+        if pt.as_slice() != checkpoint.pt.as_slice() {
+            return Err(
+                "The plaintext database no longer gives the plaintext of the checkpoint"
+                    .to_string(),
+            );
+        }
+    }
 
     // generate oracle
-    let oracle = EpsilonWrappedOracle::wrap(inner, 0.0);
+    let oracle = NoisyOracle::wrap(inner, NoiseModel::default());
+
+    attack::<BIKE, _>(opt, pk, sk, pt, oracle, checkpoint)
+}
 
-    attack::<BIKE, _>(opt, pk, sk, pt, oracle)?;
+#[cfg(test)]
+mod tests {
+    use liboqs_rs_bindings::{bike::BikeL1, Kem, KemBuf, KemWithRejectionSampling};
 
-    Ok(())
+    use super::{
+        iter_ds_diff, key_fingerprint, read_lists, write_lists, Checkpoint, DSDiff,
+        DistanceSpectrumCollection, ListsMetadata, WeightChange, WeightControl,
+    };
+    use crate::attack::rejection_sampling::{
+        accounting::{PhaseCounts, QueryCounts},
+        combinators::{CombinatorOptions, ConfirmationPolicy, Vote},
+        decision_trace::Confusion,
+        noise::NoiseModel,
+        oracle::{random_split_error_pattern, ErrorSplit},
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_error_split() {
+        assert_eq!("e1".parse(), Ok(ErrorSplit::E0Weight(0)));
+        assert_eq!("balanced".parse(), Ok(ErrorSplit::Balanced));
+        assert_eq!("uniform".parse(), Ok(ErrorSplit::Uniform));
+        assert_eq!("12".parse(), Ok(ErrorSplit::E0Weight(12)));
+        assert!("e0".parse::<ErrorSplit>().is_err());
+
+        let r = 101;
+        let mut rng = StdRng::seed_from_u64(1);
+        let e0_weight = |e: &[u32]| e.iter().filter(|&&p| (p as usize) < r).count();
+        for (split, expected) in [
+            (ErrorSplit::E0Weight(0), 0),
+            (ErrorSplit::E0Weight(7), 7),
+            (ErrorSplit::Balanced, 7),
+        ] {
+            let e = random_split_error_pattern(r, split, 15, &mut rng).unwrap();
+            assert_eq!(e.len(), 15);
+            assert_eq!(e0_weight(&e), expected);
+            assert!(e.iter().all(|&p| (p as usize) < 2 * r));
+        }
+        let e = random_split_error_pattern(r, ErrorSplit::Uniform, 150, &mut rng).unwrap();
+        assert_eq!(e.len(), 150);
+        assert!(e0_weight(&e) > 0 && e0_weight(&e) < 150);
+        assert!(random_split_error_pattern(r, ErrorSplit::E0Weight(16), 15, &mut rng).is_err());
+        assert!(random_split_error_pattern(r, ErrorSplit::E0Weight(0), 102, &mut rng).is_err());
+
+        // A mutation stays in the block of the removed position, unless the split is uniform
+        assert_eq!(ErrorSplit::Balanced.mutation_range(r, 100), 0..r);
+        assert_eq!(ErrorSplit::E0Weight(0).mutation_range(r, 101), r..2 * r);
+        assert_eq!(ErrorSplit::Uniform.mutation_range(r, 5), 0..2 * r);
+
+        // Resizing a balanced pattern keeps half of the positions in e0, rounded down
+        let balanced = [1, 2, 102, 103, 104];
+        assert_eq!(ErrorSplit::Balanced.resize_range(r, &balanced, true), 0..r);
+        assert_eq!(ErrorSplit::Balanced.resize_range(r, &balanced, false), r..2 * r);
+        let even = [1, 2, 102, 103];
+        assert_eq!(ErrorSplit::Balanced.resize_range(r, &even, true), r..2 * r);
+        assert_eq!(ErrorSplit::Balanced.resize_range(r, &even, false), 0..r);
+        assert_eq!(ErrorSplit::E0Weight(2).resize_range(r, &even, true), r..2 * r);
+        assert_eq!(ErrorSplit::E0Weight(2).resize_range(r, &even, false), r..2 * r);
+        assert_eq!(ErrorSplit::Uniform.resize_range(r, &even, true), 0..2 * r);
+    }
+
+    #[test]
+    fn test_iter_ds_diff() {
+        let r = 12323;
+        // Two positions in e0 and two in e1, moving the first position of e1
+        let error_pattern = vec![1, r - 2, r + 5, r + 20];
+        let mut diffs: Vec<(bool, usize, u32)> = iter_ds_diff(&error_pattern, r, r + 5, r + 9)
+            .map(|diff| match diff {
+                DSDiff::Add(block, distance) => (true, block, distance),
+                DSDiff::Del(block, distance) => (false, block, distance),
+            })
+            .collect();
+        diffs.sort_unstable();
+        assert_eq!(diffs, vec![(false, 1, 15), (true, 1, 11)]);
+
+        // Moving a position from e1 to e0 only changes distances in e0 by adding
+        let diffs: Vec<(bool, usize, u32)> = iter_ds_diff(&error_pattern, r, r + 5, 4)
+            .map(|diff| match diff {
+                DSDiff::Add(block, distance) => (true, block, distance),
+                DSDiff::Del(block, distance) => (false, block, distance),
+            })
+            .filter(|&(_, block, _)| block == 0)
+            .collect();
+        assert_eq!(diffs, vec![(true, 0, 3), (true, 0, 6)]);
+    }
+
+    #[test]
+    fn test_weight_control() {
+        let control = WeightControl {
+            target_dfr: 0.01,
+            window: 10000,
+            min_weight: 140,
+            max_weight: 150,
+        };
+        // 100 ± 20 failures are expected
+        assert_eq!(control.adapt(145, 100), 145);
+        assert_eq!(control.adapt(145, 119), 145);
+        assert_eq!(control.adapt(145, 79), 146);
+        assert_eq!(control.adapt(145, 121), 144);
+        assert_eq!(control.adapt(150, 0), 150);
+        assert_eq!(control.adapt(140, 10000), 140);
+    }
+
+    fn lists_metadata() -> ListsMetadata {
+        ListsMetadata {
+            algorithm: "BikeL1".to_string(),
+            oracle: "IDEAL".to_string(),
+            key: key_fingerprint(&[1, 2, 3]),
+            hamming_weight: 149,
+            split: ErrorSplit::Balanced,
+            noise: NoiseModel::symmetric(0.01),
+            target_dfr: Some(0.001),
+            num_failures: 2,
+            num_successes: 5,
+            weight_changes: vec![
+                vec![WeightChange {
+                    num_failures: 1,
+                    num_successes: 3,
+                    dfr: 0.0,
+                    weight: 150,
+                }],
+                vec![],
+            ],
+            confusion: Some(Confusion {
+                true_positives: 2,
+                false_positives: 0,
+                true_negatives: 4,
+                false_negatives: 1,
+                ignored: 3,
+            }),
+            queries: QueryCounts {
+                chain: PhaseCounts {
+                    encaps: 7,
+                    decaps: 7,
+                    queries: 7,
+                },
+                ..Default::default()
+            },
+            combinators: CombinatorOptions {
+                cache: true,
+                confirmation: ConfirmationPolicy::Majority(Vote { k: 2, n: 3 }),
+                ..Default::default()
+            },
+            graded: true,
+        }
+    }
+
+    #[test]
+    fn test_lists_metadata() {
+        let mut lists = DistanceSpectrumCollection::new(vec![], 10);
+        lists.f[3] = 7;
+        lists.b[9] = 1;
+        lists.g0[2] = 4;
+        lists.num_failures = 2;
+        lists.num_successes = 5;
+        let metadata = lists_metadata();
+
+        let path = std::env::temp_dir().join("oqs-afw-test-lists-metadata.csv.gz");
+        write_lists(&path, &metadata, &lists).unwrap();
+        let (read_metadata, read) = read_lists(&path, 10).unwrap();
+        // Distance 9 does not exist with a shorter r
+        assert!(read_lists(&path, 9).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read_metadata, Some(metadata));
+        assert_eq!(read.lists(), lists.lists());
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let mut pk = <BikeL1 as Kem>::PublicKey::new();
+        pk.as_mut_slice()[0] = 1;
+        let mut sk = <BikeL1 as Kem>::SecretKey::new();
+        sk.as_mut_slice()[1] = 2;
+        let mut pt = <BikeL1 as KemWithRejectionSampling>::Plaintext::new();
+        pt.as_mut_slice()[2] = 3;
+        let mut chain = DistanceSpectrumCollection::new(vec![4, 8, 15], 10);
+        chain.a[5] = 16;
+        chain.num_failures = 23;
+        chain.num_successes = 42;
+        let checkpoint = Checkpoint::<BikeL1> {
+            pk,
+            sk,
+            pt,
+            metadata: lists_metadata(),
+            genesis: vec![4, 8, 16],
+            chains: vec![chain, DistanceSpectrumCollection::new(vec![], 10)],
+        };
+
+        let path = std::env::temp_dir().join(format!(
+            "oqs-afw-test-checkpoint-{}.checkpoint",
+            std::process::id()
+        ));
+        checkpoint.write(&path).unwrap();
+        // Overwriting replaces the checkpoint, without leaving the temporary file behind
+        checkpoint.write(&path).unwrap();
+        let read = Checkpoint::<BikeL1>::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!path.with_extension("checkpoint.tmp").exists());
+
+        assert_eq!(read.pk.as_slice(), checkpoint.pk.as_slice());
+        assert_eq!(read.sk.as_slice(), checkpoint.sk.as_slice());
+        assert_eq!(read.pt.as_slice(), checkpoint.pt.as_slice());
+        assert_eq!(read.metadata, checkpoint.metadata);
+        assert_eq!(read.genesis, checkpoint.genesis);
+        assert_eq!(read.chain_length(), 23);
+        for (read, chain) in read.chains.iter().zip(&checkpoint.chains) {
+            assert_eq!(read.error_pattern, chain.error_pattern);
+            assert_eq!(read.lists(), chain.lists());
+            assert_eq!(read.num_successes, chain.num_successes);
+        }
+
+        // The counts differ between runs, the options of the attack must not
+        let mut resumed = lists_metadata();
+        resumed.num_failures = 0;
+        resumed.queries = QueryCounts::default();
+        assert!(read.metadata.compatible(&resumed));
+        resumed.graded = false;
+        assert!(!read.metadata.compatible(&resumed));
+        let mut resumed = lists_metadata();
+        resumed.combinators.cache = false;
+        assert!(!read.metadata.compatible(&resumed));
+        let mut resumed = lists_metadata();
+        resumed.noise = NoiseModel::default();
+        assert!(!read.metadata.compatible(&resumed));
+    }
 }
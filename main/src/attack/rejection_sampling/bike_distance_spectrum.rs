@@ -1,13 +1,16 @@
 use liboqs_rs_bindings as oqs;
 use log::info;
 use log_derive::logfn_inputs;
-use oqs::{bike::Bike, KemBuf};
+use oqs::bike::Bike;
 use std::{fs::File, io::BufWriter, path::PathBuf};
 use structopt::StructOpt;
 
 use crate::{
-    attack::rejection_sampling::bike_attack::{read_keypair, write_ds},
-    utils::{mutbit, StrErr},
+    attack::rejection_sampling::{
+        bike_attack::{read_keypair, write_ds},
+        gf2x::read_bike_secret_key,
+    },
+    utils::{KeySeedOptions, StrErr},
 };
 
 #[derive(Debug, StructOpt)]
@@ -22,36 +25,17 @@ pub struct BikeDistanceSpectrumOptions {
     /// containing the distance spectrum of the secret key in the specified key-file
     #[structopt(short("f"), long)]
     pub destination: PathBuf,
+    #[structopt(flatten)]
+    pub key_seed: KeySeedOptions,
 }
 
 #[logfn_inputs(Trace)]
 pub fn run<BIKE: 'static + Bike + std::marker::Send>(
     opt: BikeDistanceSpectrumOptions,
 ) -> Result<(), String> {
-    let (_, mut sk) = read_keypair::<BIKE>(&opt.key_file, opt.create_key_if_missing)?;
-    let params = BIKE::params::<usize>();
-    let max_distance = (params.PARAM_R + 1 )/ 2; // div_ceil
-    let mut distances = vec![0; max_distance + 1];
-    let r_bytes = (params.PARAM_R + 7 )/ 8; // div_ceil
-    let sk_offset = params.PARAM_SK_OFFSET + r_bytes; // first or second parity check matrix
-    let sk_len = r_bytes;
-    let range = sk_offset..sk_offset + sk_len;
-
-    let sk_bytes = &mut sk.as_mut_slice()[range];
-
-    for i in 0..params.PARAM_R {
-        let ib = mutbit(sk_bytes, i as u64)?;
-        if ib.get() {
-            for j in (i + 1)..params.PARAM_R {
-                let jb = mutbit(sk_bytes, j as u64)?;
-                if jb.get() {
-                    let dist = i.abs_diff(j);
-                    let dist = std::cmp::min(dist, params.PARAM_R - dist);
-                    distances[dist] += 1;
-                }
-            }
-        }
-    }
+    opt.key_seed.apply();
+    let (_, sk) = read_keypair::<BIKE>(&opt.key_file, opt.create_key_if_missing)?;
+    let key = read_bike_secret_key::<BIKE>(&sk)?;
 
     info!("Opening target file: {:?}", opt.destination);
     let mut writer =
@@ -60,7 +44,8 @@ pub fn run<BIKE: 'static + Bike + std::marker::Send>(
         .write_record(&["list", "distance", "count"])
         .strerr()?;
 
-    write_ds(distances, "DS(h1)", &mut writer);
+    write_ds(key.h1.distance_spectrum(), "DS(h1)", &mut writer);
+    write_ds(key.h0.distance_spectrum(), "DS(h0)", &mut writer);
 
     Ok(())
 }
@@ -1,7 +1,6 @@
 use std::{
     marker::PhantomData,
-    ops::Range,
-    path::{Path, PathBuf},
+    path::PathBuf,
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc,
@@ -10,14 +9,23 @@ use std::{
 
 use liboqs_rs_bindings as oqs;
 use log::info;
-use log_derive::{logfn, logfn_inputs};
+use log_derive::logfn_inputs;
 use oqs::{bike::Bike, KemBuf};
-use rand::thread_rng;
 use structopt::StructOpt;
 
 use crate::{
-    attack::rejection_sampling::{attack::get_keypair, plaintexts::PlaintextDb},
-    utils::{thread_work, ClonableProgressManager, ProgressBars},
+    attack::rejection_sampling::{
+        accounting::{self, Operation},
+        attack::get_keypair,
+        bike_attack::read_keypair,
+        experiment::{root_seed, thread_work},
+        oracle::{
+            construct_decoding_failure_oracle, random_split_error_pattern, DecodingFailureOracle,
+            ErrorSplit,
+        },
+        rejections::{RejectionCounter, RejectionSamplingStats},
+    },
+    utils::{ClonableProgressManager, KeySeedOptions, ProgressBars},
 };
 
 #[derive(Debug, StructOpt)]
@@ -33,12 +41,18 @@ pub struct BikeErrorWeightSearchOptions {
     /// The number of decoding attempts, per search step.
     #[structopt(short, long)]
     for_every: u32,
+    /// How to split the error weight between e0 and e1: "e1" for all of it in e1, "balanced" for half of it in
+    /// each, "uniform" for uniformly over both, or the number of positions in e0
+    #[structopt(long, default_value("e1"))]
+    split: ErrorSplit,
     /// Location of plaintext database generated by the "attack rejection-sampling collect-plaintexts" command
     #[structopt(short("d"), long("db"))]
     pub plaintext_db: PathBuf,
+    #[structopt(flatten)]
+    pub key_seed: KeySeedOptions,
 }
 
-#[derive(Debug, StructOpt)]
+#[derive(Debug, Clone, StructOpt)]
 pub struct BikeErrorWeightTestOptions {
     ///
     #[structopt(short, long)]
@@ -49,36 +63,32 @@ pub struct BikeErrorWeightTestOptions {
     ///
     #[structopt(short, long, default_value("1"))]
     threads: usize,
+    /// How to split the error weight between e0 and e1: "e1" for all of it in e1, "balanced" for half of it in
+    /// each, "uniform" for uniformly over both, or the number of positions in e0
+    #[structopt(long, default_value("e1"))]
+    split: ErrorSplit,
     /// Location of plaintext database generated by the "attack rejection-sampling collect-plaintexts" command
     #[structopt(short("d"), long("db"))]
     pub plaintext_db: PathBuf,
+    /// Location of serialized key pair file. Will be created with a random key if it does not exists.
+    #[structopt(short("k"), long)]
+    pub reuse_key_pair: Option<PathBuf>,
+    #[structopt(flatten)]
+    pub key_seed: KeySeedOptions,
 }
 
-pub type SparseErrorPattern = Vec<u32>;
-
-pub trait DecodingFailureOracle<BIKE: Bike>: std::fmt::Debug + Clone + Send {
-    const NAME: &'static str;
-    fn generate(
-        num_profiling: i32,
-        num_decaps: i32,
-        delta_iter: u32,
-        pt: &mut BIKE::Plaintext,
-        pt_delta: &mut BIKE::Plaintext,
-        pk: &mut BIKE::PublicKey,
-        sk: &mut BIKE::SecretKey,
-    ) -> Result<Option<Self>, String>
-    where
-        Self: Sized;
-
-    fn decoding_failure(
-        &mut self,
-        ct: &mut BIKE::Ciphertext,
-        sk: &mut BIKE::SecretKey,
-    ) -> Result<Option<bool>, String>;
-}
-
+/// Detects decoding failures from the number of rejection sampling iterations of the
+/// re-encryption in the decapsulation, which `decaps_intermediaries` returns. A decoding failure
+/// re-encrypts another plaintext than the encapsulated one, which takes another number of
+/// iterations, like the timing oracle measures.
+///
+/// The number is not a number of decoder iterations, the BGF decoder always runs all of them, so
+/// beyond being equal to the one of the encapsulated plaintext or not it does not tell how close a
+/// ciphertext is to a decoding failure. The bgf oracle grades its answers from the internals of
+/// its simulated decoder instead, see `bike-attack --graded`.
 #[derive(Debug, Clone)]
 pub struct BikeIdealizedOracle<BIKE: Bike> {
+    /// The number of rejection sampling iterations of the encapsulated plaintext
     success_iterations: u32,
     _pd: PhantomData<BIKE>,
 }
@@ -102,8 +112,10 @@ impl<BIKE: Bike + std::marker::Send> DecodingFailureOracle<BIKE> for BikeIdealiz
         let mut ct = BIKE::Ciphertext::new();
 
         BIKE::encaps_with_plaintext(&mut ct, &mut ss, pk, pt)?;
+        accounting::count(Operation::Encaps, 1);
 
         let (success_iterations, _success) = BIKE::decaps_intermediaries(&mut ct, &mut ss, sk)?;
+        accounting::count(Operation::Decaps, 1);
 
         Ok(Some(Self {
             success_iterations,
@@ -119,31 +131,12 @@ impl<BIKE: Bike + std::marker::Send> DecodingFailureOracle<BIKE> for BikeIdealiz
         let mut ss = BIKE::SharedSecret::new();
 
         let (iterations, _success) = BIKE::decaps_intermediaries(ct, &mut ss, sk)?;
+        accounting::count(Operation::Decaps, 1);
 
         Ok(Some(self.success_iterations != iterations))
     }
 }
 
-pub fn random_sparse_error_pattern(
-    range: Range<usize>,
-    amount: usize,
-) -> Result<SparseErrorPattern, String> {
-    let mut rng = thread_rng();
-
-    let length = range.end - range.start;
-    let shift = range.start;
-
-    // e_0
-    let indexes = rand::seq::index::sample(&mut rng, length, amount);
-
-    // Shift indices to range
-    Ok(indexes
-        .into_vec()
-        .into_iter()
-        .map(|i| (i + shift) as u32)
-        .collect())
-}
-
 fn bin_search_approprate_error_weight<BIKE: Bike, ORACLE: DecodingFailureOracle<BIKE>>(
     opt: BikeErrorWeightSearchOptions,
     mut oracle: ORACLE,
@@ -154,13 +147,13 @@ fn bin_search_approprate_error_weight<BIKE: Bike, ORACLE: DecodingFailureOracle<
     let maximum_weight = BIKE::params::<u32>().PARAM_T * 10; //Arbitrarily selected
     let minimum_weight: u32 = BIKE::params().PARAM_T;
 
-    let params = BIKE::params();
-    let error_range = params.PARAM_R..params.PARAM_N;
+    let r = BIKE::params::<usize>().PARAM_R;
     info!("Found a decoding_Failure oracle!");
 
     info!("Starting binary search to find the appropriate error weight");
     let mut ct = BIKE::Ciphertext::new();
     let mut ss = BIKE::SharedSecret::new();
+    let mut rng = root_seed().purpose("error-patterns").rng();
 
     let mut high = maximum_weight;
     let mut low = minimum_weight;
@@ -177,7 +170,7 @@ fn bin_search_approprate_error_weight<BIKE: Bike, ORACLE: DecodingFailureOracle<
         let mut attempts = 0;
         'inner: for i in 1..=opt.for_every {
             attempts = i;
-            let rsep = random_sparse_error_pattern(error_range.clone(), middle as usize)?;
+            let rsep = random_split_error_pattern(r, opt.split, middle as usize, &mut rng)?;
             BIKE::encaps_with_plaintext_and_error_pattern(&mut ct, &mut ss, &mut pk, &pt, &rsep)?;
             if let Some(measurment) = oracle.decoding_failure(&mut ct, &mut sk)? {
                 decoding_failures += measurment as u32
@@ -210,105 +203,8 @@ fn bin_search_approprate_error_weight<BIKE: Bike, ORACLE: DecodingFailureOracle<
     Ok(weight)
 }
 
-#[logfn(Trace)]
-fn add_se(iter: u32) -> u32 {
-    iter + (((iter + 3) / 4) * 1000)
-}
-
-#[logfn(Trace)]
-fn remove_se(iter: u32) -> u32 {
-    iter % 1000
-}
-
-#[cfg(test)]
-mod tests {
-
-    use super::{add_se, remove_se};
-
-    #[test]
-    fn test_add_se() {
-        assert_eq!(add_se(172), 43172);
-        assert_eq!(add_se(173), 44173);
-        assert_eq!(add_se(174), 44174);
-        assert_eq!(add_se(175), 44175);
-        assert_eq!(add_se(176), 44176);
-        assert_eq!(add_se(177), 45177);
-    }
-
-    #[test]
-    fn test_remove_se() {
-        assert_eq!(remove_se(43172), 172);
-        assert_eq!(remove_se(44173), 173);
-        assert_eq!(remove_se(44174), 174);
-        assert_eq!(remove_se(44175), 175);
-        assert_eq!(remove_se(44176), 176);
-        assert_eq!(remove_se(45177), 177);
-    }
-}
-
-pub fn construct_decoding_failure_oracle<BIKE: Bike, ORACLE: DecodingFailureOracle<BIKE>>(
-    db_location: &Path,
-    num_profiling: i32,
-    num_decaps: i32,
-    delta_iter: u32,
-    pk: &mut BIKE::PublicKey,
-    sk: &mut BIKE::SecretKey,
-) -> Result<(ORACLE, BIKE::Plaintext), String> {
-    if delta_iter == 0 {
-        return Err(format!("Delta iter ({}) must be grater than 0", delta_iter));
-    }
-    info!(
-        "Constructing a decoding failure oracle of type: {}",
-        ORACLE::NAME
-    );
-    info!("Searching for best candidate plaintext in database...");
-    let mut ptdb = PlaintextDb::<BIKE>::new(db_location, 0)?;
-    let (min, mut min_pt) = ptdb
-        .get_min_max(false)?
-        .ok_or_else(|| "No more plaintexts in db!".to_string())?;
-    let min = remove_se(min);
-    info!(
-        "Found candidate with {} iterations in the rejection sampling!",
-        min
-    );
-
-    let mut selected_iter = min + delta_iter;
-    let oracle = loop {
-        let mut higher = 0;
-        let mut pt_delta = loop {
-            if let Some((iter, pt_delta)) =
-                ptdb.get_next_iter(add_se(selected_iter + higher), false)?
-            {
-                let iter = remove_se(iter);
-                info!(
-                    "Attempting to construct a oracle with granularity of {} PRNG samplings (using a delta plaintext with {} PRNG samplings)!",
-                    iter - min,
-                    iter
-                );
-                selected_iter = iter;
-                break pt_delta;
-            }
-            higher += 1;
-        };
-
-        if let Some(oracle) = ORACLE::generate(
-            num_profiling,
-            num_decaps,
-            delta_iter,
-            &mut min_pt,
-            &mut pt_delta,
-            pk,
-            sk,
-        )? {
-            break oracle;
-        }
-    };
-    info!("decoding failure oracle constructed!");
-    Ok((oracle, min_pt))
-}
-
 #[logfn_inputs(Trace)]
-pub fn run_search<BIKE: Bike + std::marker::Send>(
+pub fn run_search<BIKE: Bike + RejectionSamplingStats + std::marker::Send>(
     opt: BikeErrorWeightSearchOptions,
 ) -> Result<(), String> {
     if opt.below <= opt.above {
@@ -317,12 +213,14 @@ pub fn run_search<BIKE: Bike + std::marker::Send>(
 
     info!("Launching procedure to find a error weight suitable to launch a rejection sampling attack on BIKE secret key.");
 
+    opt.key_seed.apply();
     let (mut pk, mut sk) = get_keypair::<BIKE>()?;
     let (oracle, pt) = construct_decoding_failure_oracle::<BIKE, BikeIdealizedOracle<BIKE>>(
         &opt.plaintext_db,
         1,
         1,
         1,
+        RejectionCounter::Iterations,
         &mut pk,
         &mut sk,
     )?;
@@ -334,11 +232,22 @@ pub fn run_search<BIKE: Bike + std::marker::Send>(
     Ok(())
 }
 
-#[logfn_inputs(Trace)]
-pub fn run_test<BIKE: 'static + Bike + std::marker::Send>(
+pub fn run_test<BIKE: 'static + Bike + RejectionSamplingStats + std::marker::Send>(
     opt: BikeErrorWeightTestOptions,
 ) -> Result<(), String> {
-    let (mut pk, mut sk) = get_keypair::<BIKE>()?;
+    run_test_with_result::<BIKE>(opt).map(|_| ())
+}
+
+/// Returns the number of decoding failures and the number of tests
+#[logfn_inputs(Trace)]
+pub fn run_test_with_result<BIKE: 'static + Bike + RejectionSamplingStats + std::marker::Send>(
+    opt: BikeErrorWeightTestOptions,
+) -> Result<(u64, u64), String> {
+    opt.key_seed.apply();
+    let (mut pk, mut sk) = match &opt.reuse_key_pair {
+        Some(key_file) => read_keypair::<BIKE>(key_file, true)?,
+        None => get_keypair::<BIKE>()?,
+    };
 
     info!("Launching procedure to test decoding failure rate of {} with weight {} by using {} decapsulation calls", BIKE::NAME, opt.weight, opt.tests);
 
@@ -347,6 +256,7 @@ pub fn run_test<BIKE: 'static + Bike + std::marker::Send>(
         1,
         1,
         1,
+        RejectionCounter::Iterations,
         &mut pk,
         &mut sk,
     )?;
@@ -365,18 +275,20 @@ pub fn run_test<BIKE: 'static + Bike + std::marker::Send>(
     let sk = sk.clone();
     let target_weight = opt.weight;
     let target_tests = opt.tests;
+    let split = opt.split;
     let (decoding_failures, total_tests) = thread_work(
         opt.threads,
+        root_seed().purpose("error-patterns"),
         (oracle, pt, pk, sk),
-        move |_tid, sync: Arc<(AtomicU64, AtomicU64)>, (mut oracle, pt, mut pk, mut sk)| {
+        move |_tid, sync: Arc<(AtomicU64, AtomicU64)>, (mut oracle, pt, mut pk, mut sk), seed| {
+            let mut rng = seed.rng();
             let mut decoding_failures = 0;
             let mut tests = 0;
             let mut ct = BIKE::Ciphertext::new();
             let mut ss = BIKE::SharedSecret::new();
-            let params = BIKE::params();
-            let error_range = params.PARAM_R..params.PARAM_N;
+            let r = BIKE::params::<usize>().PARAM_R;
             while sync.1.load(Ordering::Relaxed) < target_tests {
-                let rsep = random_sparse_error_pattern(error_range.clone(), target_weight)?;
+                let rsep = random_split_error_pattern(r, split, target_weight, &mut rng)?;
                 BIKE::encaps_with_plaintext_and_error_pattern(
                     &mut ct, &mut ss, &mut pk, &pt, &rsep,
                 )?;
@@ -414,5 +326,5 @@ pub fn run_test<BIKE: 'static + Bike + std::marker::Send>(
         (decoding_failures as f64 / total_tests as f64).to_string()
     );
 
-    Ok(())
+    Ok((decoding_failures, total_tests))
 }
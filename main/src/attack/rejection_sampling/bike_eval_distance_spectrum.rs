@@ -0,0 +1,377 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use liboqs_rs_bindings as oqs;
+use log::{info, warn};
+use log_derive::logfn_inputs;
+use oqs::bike::Bike;
+use structopt::StructOpt;
+
+use crate::{
+    attack::rejection_sampling::{
+        accounting::QueryCounts,
+        bike_reconstruct::{read_lists, score_distances, LISTS},
+    },
+    utils::StrErr,
+};
+
+#[derive(Debug, StructOpt)]
+pub struct BikeEvalDistanceSpectrumOptions {
+    /// The CSV file with the true distance spectrum, written by the "attack rejection-sampling bike-distance-spectrum" command
+    #[structopt(short("k"), long)]
+    pub distance_spectrum: PathBuf,
+    /// The GZIP compressed CSV files with the F, G, A and B lists, written by the "attack rejection-sampling bike-attack" command.
+    /// The lists are accumulated in the given order, and evaluated after each file.
+    #[structopt(short("s"), long, required(true), min_values(1))]
+    pub sources: Vec<PathBuf>,
+    /// The chain length of each source without metadata, i.e. written before the lists embedded it.
+    /// The chain length of the other sources is read from their metadata.
+    #[structopt(short("c"), long)]
+    pub chain_length: Option<u32>,
+    /// The epsilon the sources were generated with, only used to label the results
+    #[structopt(short("e"), long)]
+    pub epsilon: Option<f64>,
+    /// The number of highest scoring distances to check for true distances.
+    /// Defaults to the number of distinct distances in the true distance spectrum.
+    #[structopt(short("n"), long)]
+    pub top_k: Option<usize>,
+    /// Where to write the resulting CSV file, e.g. "bike-ds-eval.csv", with one row per series, list and accumulated
+    /// chain length
+    #[structopt(short("f"), long)]
+    pub destination: PathBuf,
+    /// The block of the error whose lists to evaluate, 0 for the distances of h0 or 1 for those of h1
+    #[structopt(short("b"), long, default_value("1"))]
+    pub block: usize,
+    /// Lists of the same key from another series of runs to compare the sources to, e.g. without --graded. They are
+    /// accumulated and evaluated like the sources, and the oracle queries both need until the top scores are all true
+    /// distances are compared.
+    #[structopt(long)]
+    pub baseline: Vec<PathBuf>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Evaluation {
+    /// The area under the ROC curve
+    auc: f64,
+    /// The threshold maximizing the true positive rate minus the false positive rate,
+    /// distances scoring at least the threshold are classified as present
+    threshold: f64,
+    tpr: f64,
+    fpr: f64,
+    /// The number of true distances among the top k scores
+    in_top_k: usize,
+    /// The rank of the lowest scoring true distance
+    lowest_true_rank: usize,
+}
+
+/// Reads the distances present in the distance spectrum of h0 or h1, indexed by distance
+fn read_distance_spectrum(
+    path: &Path,
+    max_distance: usize,
+    block: usize,
+) -> Result<Vec<bool>, String> {
+    info!(
+        "Reading the true distance spectrum of h{} from {:?}",
+        block, path
+    );
+    let name = format!("DS(h{})", block);
+    let reader = BufReader::new(File::open(path).strerr()?);
+    let mut truth = vec![false; max_distance];
+    let mut found = false;
+    for record in csv::Reader::from_reader(reader).deserialize() {
+        let (list, distance, count): (String, usize, u32) = record.strerr()?;
+        if list != name {
+            continue;
+        }
+        found = true;
+        match truth.get_mut(distance) {
+            Some(present) => *present |= count > 0,
+            // Older versions of bike-distance-spectrum wrote one distance too many
+            None if count == 0 => {}
+            None => {
+                return Err(format!(
+                    "Distance {} is out of range, is {:?} from another parameter set?",
+                    distance, path
+                ))
+            }
+        }
+    }
+    if !found {
+        return Err(format!(
+            "{:?} has no {}, it was written by an older version of bike-distance-spectrum",
+            path, name
+        ));
+    }
+    Ok(truth)
+}
+
+/// Evaluates how well `scores` separate the present distances in `truth` from the absent ones,
+/// leaving out distance 0
+fn evaluate(scores: &[f64], truth: &[bool], top_k: usize) -> Evaluation {
+    let mut ranking: Vec<usize> = (1..scores.len()).collect();
+    ranking.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+    let positives = ranking.iter().filter(|&&dist| truth[dist]).count();
+    let negatives = ranking.len() - positives;
+
+    let mut evaluation = Evaluation {
+        auc: 0.0,
+        threshold: f64::INFINITY,
+        tpr: 0.0,
+        fpr: 0.0,
+        in_top_k: ranking.iter().take(top_k).filter(|&&d| truth[d]).count(),
+        lowest_true_rank: ranking.iter().rposition(|&d| truth[d]).map_or(0, |r| r + 1),
+    };
+    // Walk down the ranking, with equal scores as one step of the ROC curve
+    let (mut tp, mut fp) = (0, 0);
+    let mut best = 0.0;
+    let mut start = 0;
+    while start < ranking.len() {
+        let score = scores[ranking[start]];
+        let end = start
+            + ranking[start..]
+                .iter()
+                .take_while(|&&d| scores[d] == score)
+                .count();
+        let (new_tp, new_fp) = ranking[start..end].iter().fold((tp, fp), |(tp, fp), &d| {
+            if truth[d] {
+                (tp + 1, fp)
+            } else {
+                (tp, fp + 1)
+            }
+        });
+        // Trapezoid between the two points of the curve
+        evaluation.auc += (new_fp - fp) as f64 * (tp + new_tp) as f64 / 2.0;
+        tp = new_tp;
+        fp = new_fp;
+
+        let tpr = tp as f64 / positives.max(1) as f64;
+        let fpr = fp as f64 / negatives.max(1) as f64;
+        if tpr - fpr > best {
+            best = tpr - fpr;
+            evaluation.threshold = score;
+            evaluation.tpr = tpr;
+            evaluation.fpr = fpr;
+        }
+        start = end;
+    }
+    evaluation.auc /= (positives.max(1) * negatives.max(1)) as f64;
+    evaluation
+}
+
+/// Where the top scores of a series of sources are all true distances from on
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Recovery {
+    chain_length: u64,
+    /// The oracle queries of the sources up to that chain length, unknown if a source predates
+    /// the query counts in its metadata
+    queries: Option<u64>,
+}
+
+/// Accumulates the lists of `sources` in order and evaluates them after each, writing one row per
+/// list to `writer`. Returns where the top scores are all true distances from, if they stay so.
+fn evaluate_series<W: Write>(
+    writer: &mut csv::Writer<W>,
+    series: &str,
+    sources: &[PathBuf],
+    opt: &BikeEvalDistanceSpectrumOptions,
+    truth: &[bool],
+    top_k: usize,
+) -> Result<Option<Recovery>, String> {
+    let max_distance = truth.len();
+    let num_truth = truth.iter().filter(|&&t| t).count();
+    let epsilon = opt.epsilon.map(|e| e.to_string()).unwrap_or_default();
+
+    let mut lists: HashMap<String, Vec<f64>> = HashMap::new();
+    let mut recovery = None;
+    let mut chain_length = 0;
+    let mut queries = Some(0);
+    for source in sources {
+        let (metadata, source_lists) = read_lists(source, max_distance, opt.block)?;
+        match (&metadata, opt.chain_length) {
+            (Some(metadata), _) => chain_length += metadata.num_failures as u64,
+            (None, Some(length)) => chain_length += length as u64,
+            (None, None) => {
+                return Err(format!(
+                    "{:?} has no metadata, its chain length has to be given with --chain-length",
+                    source
+                ))
+            }
+        };
+        queries = match metadata {
+            Some(metadata) if metadata.queries != QueryCounts::default() => {
+                queries.map(|queries| queries + metadata.queries.total().queries)
+            }
+            _ => None,
+        };
+        for (name, list) in source_lists {
+            let acc = lists.entry(name).or_insert_with(|| vec![0.0; max_distance]);
+            acc.iter_mut()
+                .zip(list)
+                .for_each(|(dest, src)| *dest += src);
+        }
+
+        let mut scored: Vec<(&str, Vec<f64>)> = LISTS
+            .iter()
+            .filter_map(|(name, sign)| {
+                let list = lists.get(*name)?;
+                Some((*name, list.iter().map(|count| sign * count).collect()))
+            })
+            .collect();
+        scored.push(("score", score_distances(&lists, max_distance)));
+
+        for (name, scores) in scored {
+            let evaluation = evaluate(&scores, truth, top_k);
+            writer
+                .write_record(&[
+                    series.to_string(),
+                    epsilon.clone(),
+                    chain_length.to_string(),
+                    queries.map(|q| q.to_string()).unwrap_or_default(),
+                    name.to_string(),
+                    evaluation.auc.to_string(),
+                    evaluation.threshold.to_string(),
+                    evaluation.tpr.to_string(),
+                    evaluation.fpr.to_string(),
+                    top_k.to_string(),
+                    evaluation.in_top_k.to_string(),
+                    evaluation.lowest_true_rank.to_string(),
+                ])
+                .strerr()?;
+            if name != "score" {
+                continue;
+            }
+
+            info!(
+                "{}, chain length {}: AUC {:.4}, best threshold {:.3} (TPR {:.4}, FPR {:.4}), {} of the top {} distances are true, the lowest ranked true distance has rank {}",
+                series,
+                chain_length,
+                evaluation.auc,
+                evaluation.threshold,
+                evaluation.tpr,
+                evaluation.fpr,
+                evaluation.in_top_k,
+                top_k,
+                evaluation.lowest_true_rank
+            );
+            // Separated from here on, unless a later chain length falls back
+            if evaluation.in_top_k == top_k.min(num_truth) {
+                recovery.get_or_insert(Recovery {
+                    chain_length,
+                    queries,
+                });
+            } else {
+                recovery = None;
+            }
+        }
+    }
+
+    let epsilon = opt.epsilon.map_or_else(
+        || "an unknown epsilon".to_string(),
+        |e| format!("epsilon {}", e),
+    );
+    match recovery {
+        Some(recovery) => info!(
+            "{}: with {}, the top {} scores are all true distances from a chain length of {}, after {} queries",
+            series,
+            epsilon,
+            top_k,
+            recovery.chain_length,
+            recovery
+                .queries
+                .map_or_else(|| "an unknown number of".to_string(), |q| q.to_string())
+        ),
+        None => warn!(
+            "{}: with {}, the top {} scores still contain false distances at a chain length of {}",
+            series,
+            epsilon,
+            top_k,
+            chain_length
+        ),
+    }
+    Ok(recovery)
+}
+
+#[logfn_inputs(Trace)]
+pub fn run<BIKE: Bike>(opt: BikeEvalDistanceSpectrumOptions) -> Result<(), String> {
+    let max_distance = (BIKE::params::<usize>().PARAM_R + 1) / 2; // div_ceil
+    let truth = read_distance_spectrum(&opt.distance_spectrum, max_distance, opt.block)?;
+    let num_truth = truth.iter().filter(|&&t| t).count();
+    let top_k = opt.top_k.unwrap_or(num_truth);
+    info!(
+        "The true distance spectrum has {} distinct distances, checking the top {} scores",
+        num_truth, top_k
+    );
+
+    info!("Opening target file: {:?}", opt.destination);
+    let mut writer =
+        csv::Writer::from_writer(BufWriter::new(File::create(&opt.destination).strerr()?));
+    writer
+        .write_record(&[
+            "series",
+            "epsilon",
+            "chain_length",
+            "queries",
+            "list",
+            "auc",
+            "threshold",
+            "tpr",
+            "fpr",
+            "top_k",
+            "in_top_k",
+            "lowest_true_rank",
+        ])
+        .strerr()?;
+
+    let recovery = evaluate_series(&mut writer, "sources", &opt.sources, &opt, &truth, top_k)?;
+    if opt.baseline.is_empty() {
+        writer.flush().strerr()?;
+        return Ok(());
+    }
+    let baseline = evaluate_series(&mut writer, "baseline", &opt.baseline, &opt, &truth, top_k)?;
+    writer.flush().strerr()?;
+
+    match (
+        recovery.and_then(|r| r.queries),
+        baseline.and_then(|b| b.queries),
+    ) {
+        (Some(queries), Some(baseline)) => info!(
+            "The sources needed {} queries to the {} of the baseline, {:.3} times as many",
+            queries,
+            baseline,
+            queries as f64 / baseline as f64
+        ),
+        _ => warn!(
+            "The queries to recovery cannot be compared, as the sources or the baseline did not recover the distance spectrum, or predate the query counts"
+        ),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::evaluate;
+
+    #[test]
+    fn test_evaluate() {
+        let truth = [false, true, false, true, false];
+
+        let separated = evaluate(&[9.0, 4.0, 1.0, 3.0, 2.0], &truth, 2);
+        assert_eq!(separated.auc, 1.0);
+        assert_eq!(separated.threshold, 3.0);
+        assert_eq!(separated.in_top_k, 2);
+        assert_eq!(separated.lowest_true_rank, 2);
+
+        // One of the four pairs of a true and a false distance is ordered wrong, then tied
+        let mixed = evaluate(&[0.0, 4.0, 3.0, 2.0, 1.0], &truth, 2);
+        assert_eq!(mixed.auc, 0.75);
+        assert_eq!(mixed.in_top_k, 1);
+        assert_eq!(mixed.lowest_true_rank, 3);
+        let tied = evaluate(&[0.0, 4.0, 2.0, 2.0, 1.0], &truth, 2);
+        assert_eq!(tied.auc, 0.875);
+    }
+}
@@ -0,0 +1,109 @@
+use std::{fs::File, io::BufWriter, path::PathBuf};
+
+use liboqs_rs_bindings as oqs;
+use log::info;
+use log_derive::logfn_inputs;
+use oqs::bike::Bike;
+use structopt::StructOpt;
+
+use crate::{
+    attack::rejection_sampling::bike_attack::{
+        read_lists, write_lists, DistanceSpectrumCollection, ListsMetadata,
+    },
+    utils::StrErr,
+};
+
+#[derive(Debug, StructOpt)]
+pub struct BikeMergeListsOptions {
+    /// The GZIP compressed CSV files with the F, G, A and B lists, written by the "attack rejection-sampling bike-attack" command
+    #[structopt(short("s"), long, required(true), min_values(1))]
+    pub sources: Vec<PathBuf>,
+    /// Where to write the GZIP compressed CSV file with the summed lists, e.g. "bike-ds-merged.csv.gz"
+    #[structopt(short("f"), long)]
+    pub destination: PathBuf,
+    /// Where to write a CSV file with the number of decoding failures and successes of each source
+    #[structopt(short("r"), long)]
+    pub report: Option<PathBuf>,
+}
+
+#[logfn_inputs(Trace)]
+pub fn run<BIKE: Bike>(opt: BikeMergeListsOptions) -> Result<(), String> {
+    let max_distance = (BIKE::params::<usize>().PARAM_R + 1) / 2; // div_ceil
+
+    let mut merged: Option<(ListsMetadata, DistanceSpectrumCollection)> = None;
+    let mut chunks = vec![];
+    for source in &opt.sources {
+        let (metadata, lists) = read_lists(source, max_distance)?;
+        let metadata = metadata.ok_or_else(|| {
+            format!(
+                "{:?} has no metadata, it was written by an older version of bike-attack",
+                source
+            )
+        })?;
+        if metadata.algorithm != BIKE::NAME {
+            return Err(format!(
+                "{:?} is from {}, not {}",
+                source,
+                metadata.algorithm,
+                BIKE::NAME
+            ));
+        }
+        info!(
+            "{:?}: {} decoding failures, {} decoding successes",
+            source, metadata.num_failures, metadata.num_successes
+        );
+        chunks.push((source, metadata.num_failures, metadata.num_successes));
+
+        match merged.as_mut() {
+            None => merged = Some((metadata, lists)),
+            Some((merged_metadata, sum)) => {
+                if !merged_metadata.compatible(&metadata) {
+                    return Err(format!(
+                        "{:?} is from another attack than {:?}: {:?} and {:?}",
+                        source, opt.sources[0], metadata, merged_metadata
+                    ));
+                }
+                sum.merge(&lists);
+                merged_metadata
+                    .weight_changes
+                    .extend(metadata.weight_changes);
+                merged_metadata.queries = merged_metadata.queries + metadata.queries;
+                // Only known if every source was traced
+                merged_metadata.confusion = merged_metadata
+                    .confusion
+                    .zip(metadata.confusion)
+                    .map(|(a, b)| a + b);
+            }
+        }
+    }
+    let (mut metadata, merged) = merged.ok_or_else(|| "No sources to merge".to_string())?;
+    metadata.num_failures = chunks.iter().map(|(_, failures, _)| failures).sum();
+    metadata.num_successes = chunks.iter().map(|(_, _, successes)| successes).sum();
+    info!(
+        "Merged {} files with a total of {} decoding failures and {} decoding successes",
+        opt.sources.len(),
+        metadata.num_failures,
+        metadata.num_successes
+    );
+    write_lists(&opt.destination, &metadata, &merged)?;
+
+    if let Some(report) = &opt.report {
+        info!("Writing report to {:?}", report);
+        let mut writer = csv::Writer::from_writer(BufWriter::new(File::create(report).strerr()?));
+        writer
+            .write_record(&["source", "num_failures", "num_successes"])
+            .strerr()?;
+        for (source, num_failures, num_successes) in chunks {
+            writer
+                .write_record(&[
+                    source.display().to_string(),
+                    num_failures.to_string(),
+                    num_successes.to_string(),
+                ])
+                .strerr()?;
+        }
+        writer.flush().strerr()?;
+    }
+
+    Ok(())
+}
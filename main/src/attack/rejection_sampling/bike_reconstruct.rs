@@ -0,0 +1,364 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use liboqs_rs_bindings as oqs;
+use log::{debug, info, warn};
+use log_derive::logfn_inputs;
+use oqs::bike::Bike;
+use structopt::StructOpt;
+
+use crate::attack::rejection_sampling::{
+    bike_attack::{self, read_keypair, ListsMetadata},
+    gf2x::{
+        bike_column_weight, cyclic_distance, read_bike_public_key, read_bike_secret_key,
+        BikeSecretKey, SparsePoly,
+    },
+};
+
+#[derive(Debug, StructOpt)]
+pub struct BikeReconstructOptions {
+    /// The GZIP compressed CSV file with the F, G, A and B lists, written by the "attack rejection-sampling bike-attack" command
+    #[structopt(short("s"), long)]
+    pub source: PathBuf,
+    /// Location of the serialized key pair file that was attacked, i.e. the "--reuse-key-pair" of bike-attack
+    #[structopt(short("k"), long)]
+    pub key_file: PathBuf,
+    /// The number of highest scoring distances to classify as present in the distance spectrum.
+    /// Defaults to the expected number of distinct distances for the parameter set.
+    #[structopt(short("n"), long)]
+    pub distances: Option<usize>,
+    /// The number of highest scoring distances to try as the distance between the first two positions of h1, or h0
+    #[structopt(short("a"), long, default_value("10"))]
+    pub attempts: usize,
+    /// The number of best connected candidate positions to try as the third position of h1, or h0, per attempt
+    #[structopt(long, default_value("100"))]
+    pub anchors: usize,
+    /// Allow candidate positions with up to this many of their distances to the first three positions missing from the classified distances
+    #[structopt(short("e"), long, default_value("0"))]
+    pub tolerance: usize,
+    /// The part of the secret key to reconstruct from the distances in the same block of the error, 0 for h0 or 1 for h1
+    #[structopt(short("b"), long, default_value("1"))]
+    pub block: usize,
+}
+
+/// The lists written by bike-attack, with the sign of their correlation with the distance spectrum
+pub const LISTS: [(&str, f64); 4] = [("f", 1.0), ("g", 1.0), ("a", -1.0), ("b", 1.0)];
+
+/// Reads the F, G, A and B lists of a block of the error, indexed by distance, and the metadata
+/// unless the file predates it. The lists of e0 are stored with a 0 suffix, e.g. "f0", which is
+/// removed.
+pub fn read_lists(
+    source: &Path,
+    max_distance: usize,
+    block: usize,
+) -> Result<(Option<ListsMetadata>, HashMap<String, Vec<f64>>), String> {
+    if block > 1 {
+        return Err(format!("There is no block {}, only 0 and 1", block));
+    }
+    let (metadata, lists) = bike_attack::read_lists(source, max_distance)?;
+    let lists = lists
+        .lists()
+        .iter()
+        .filter_map(|(name, list)| {
+            let name = match block {
+                0 => name.strip_suffix('0')?,
+                _ if name.ends_with('0') => return None,
+                _ => name,
+            };
+            Some((name.to_string(), list.iter().map(|&c| c as f64).collect()))
+        })
+        .collect();
+    Ok((metadata, lists))
+}
+
+/// Combines the lists as D = F + G + A' + B, where A' is A inverted since failures are less
+/// likely for distances added in decoding successes. Each list is scaled by its mean first, so
+/// the far more frequent decoding successes do not drown out the failures.
+pub fn score_distances(lists: &HashMap<String, Vec<f64>>, max_distance: usize) -> Vec<f64> {
+    let mut scores = vec![0.0; max_distance];
+    for (name, sign) in LISTS.iter() {
+        let list = match lists.get(*name) {
+            Some(list) => list,
+            None => {
+                warn!(
+                    "List {} is missing, it does not contribute to the scores",
+                    name
+                );
+                continue;
+            }
+        };
+        let mean = list[1..].iter().sum::<f64>() / (max_distance - 1) as f64;
+        if mean > 0.0 {
+            for (score, count) in scores.iter_mut().zip(list) {
+                *score += sign * count / mean;
+            }
+        }
+    }
+    scores
+}
+
+/// The expected number of distinct distances in the distance spectrum of a random h1
+fn expected_distances(r: usize, d: usize) -> usize {
+    let distances = ((r - 1) / 2) as f64;
+    let pairs = (d * (d - 1) / 2) as i32;
+    (distances * (1.0 - (1.0 - 1.0 / distances).powi(pairs))).round() as usize
+}
+
+/// Reconstructs supports of weight `d` with pairwise distances `present`, containing the
+/// positions 0 and `delta`, until one is accepted by `accept`.
+///
+/// Candidates are the positions with present distances to 0 and `delta`. The support is a clique
+/// among them, where two positions are adjacent if their distance is present. With the
+/// candidates of a third position added as an anchor, so few candidates remain that the clique is
+/// found by repeatedly removing the candidate with the fewest adjacent candidates. The best
+/// connected candidates are tried as the third anchor first.
+fn reconstruct<F>(
+    r: usize,
+    d: usize,
+    present: &[bool],
+    delta: usize,
+    tolerance: usize,
+    max_anchors: usize,
+    mut accept: F,
+) -> Option<Vec<u32>>
+where
+    F: FnMut(&[u32]) -> bool,
+{
+    let adjacent = |a: usize, b: usize| present[cyclic_distance(r, a, b)];
+    let misses = |p: usize, anchors: &[usize]| anchors.iter().filter(|&&a| !adjacent(p, a)).count();
+    let candidates: Vec<usize> = (1..r)
+        .filter(|&p| p != delta && misses(p, &[0, delta]) <= tolerance)
+        .collect();
+    debug!(
+        "{} candidate positions with delta = {}",
+        candidates.len(),
+        delta
+    );
+
+    let mut thirds: Vec<(usize, usize)> = candidates
+        .iter()
+        .map(|&p| (p, candidates.iter().filter(|&&q| adjacent(p, q)).count()))
+        .collect();
+    thirds.sort_by_key(|&(_, degree)| std::cmp::Reverse(degree));
+    for &(third, _) in thirds.iter().take(max_anchors) {
+        let anchors = [0, delta, third];
+        let remaining: Vec<usize> = candidates
+            .iter()
+            .copied()
+            .filter(|&p| p != third && misses(p, &anchors) <= tolerance)
+            .collect();
+        if remaining.len() < d - anchors.len() {
+            continue;
+        }
+        let mut support = peel(r, present, remaining, d - anchors.len());
+        support.extend(anchors.iter());
+        let support = refine(r, present, support);
+        if accept(&support) {
+            return Some(support);
+        }
+    }
+    None
+}
+
+/// Repeatedly removes the candidate with the fewest adjacent candidates, until `size` remain
+fn peel(r: usize, present: &[bool], mut candidates: Vec<usize>, size: usize) -> Vec<usize> {
+    let adjacent = |a: usize, b: usize| present[cyclic_distance(r, a, b)];
+    let mut degrees: Vec<usize> = candidates
+        .iter()
+        .map(|&p| candidates.iter().filter(|&&q| adjacent(p, q)).count())
+        .collect();
+    while candidates.len() > size {
+        let idx = (0..degrees.len()).min_by_key(|&idx| degrees[idx]).unwrap();
+        let p = candidates.swap_remove(idx);
+        degrees.swap_remove(idx);
+        for (&q, degree) in candidates.iter().zip(degrees.iter_mut()) {
+            if adjacent(p, q) {
+                *degree -= 1;
+            }
+        }
+    }
+    candidates
+}
+
+/// Positions lost to a misclassified distance to an anchor are not candidates, so swap the least
+/// connected position of the support for the most connected position outside of it, for as long
+/// as that improves the support
+fn refine(r: usize, present: &[bool], mut support: Vec<usize>) -> Vec<u32> {
+    let adjacent = |a: usize, b: usize| present[cyclic_distance(r, a, b)];
+    let mut in_support = vec![false; r];
+    support.iter().for_each(|&p| in_support[p] = true);
+    loop {
+        let connections = |p: usize| {
+            support
+                .iter()
+                .filter(|&&q| q != p && adjacent(p, q))
+                .count()
+        };
+        let (idx, worst) = support
+            .iter()
+            .enumerate()
+            .map(|(idx, &p)| (idx, connections(p)))
+            .min_by_key(|&(_, c)| c)
+            .unwrap();
+        let (best, best_connections) = (0..r)
+            .filter(|&p| !in_support[p])
+            .map(|p| (p, connections(p) - adjacent(p, support[idx]) as usize))
+            .max_by_key(|&(_, c)| c)
+            .unwrap();
+        if best_connections <= worst {
+            break;
+        }
+        in_support[support[idx]] = false;
+        in_support[best] = true;
+        support[idx] = best;
+    }
+
+    let mut support: Vec<u32> = support.into_iter().map(|p| p as u32).collect();
+    support.sort_unstable();
+    support
+}
+
+pub fn run<BIKE: Bike>(opt: BikeReconstructOptions) -> Result<(), String> {
+    run_with_result::<BIKE>(opt).map(|_| ())
+}
+
+/// Returns whether a secret key equivalent to the true one was recovered
+#[logfn_inputs(Trace)]
+pub fn run_with_result<BIKE: Bike>(opt: BikeReconstructOptions) -> Result<bool, String> {
+    let params = BIKE::params::<usize>();
+    let r = params.PARAM_R;
+    let d = bike_column_weight(r)?;
+    let max_distance = (r + 1) / 2; // div_ceil
+
+    let (pk, sk) = read_keypair::<BIKE>(&opt.key_file, false)?;
+    let h = read_bike_public_key::<BIKE>(&pk)?;
+    let key = read_bike_secret_key::<BIKE>(&sk)?;
+
+    let (_, lists) = read_lists(&opt.source, max_distance, opt.block)?;
+    let scores = score_distances(&lists, max_distance);
+    let mut ranking: Vec<usize> = (1..max_distance).collect();
+    ranking.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+
+    let num_present = opt.distances.unwrap_or_else(|| expected_distances(r, d));
+    let mut present = vec![false; max_distance];
+    ranking
+        .iter()
+        .take(num_present)
+        .for_each(|&dist| present[dist] = true);
+    info!(
+        "Classified the {} highest scoring distances as present in the distance spectrum",
+        num_present
+    );
+
+    // Only for reporting, the ground truth is not used by the reconstruction
+    let target = if opt.block == 0 { &key.h0 } else { &key.h1 };
+    let truth: Vec<bool> = target.distance_spectrum().iter().map(|&c| c > 0).collect();
+    let num_truth = truth.iter().filter(|&&t| t).count();
+    let correct = (1..max_distance)
+        .filter(|&dist| present[dist] && truth[dist])
+        .count();
+    info!(
+        "{} of the {} classified distances are in the true distance spectrum, which has {} distinct distances",
+        correct, num_present, num_truth
+    );
+    if let Some(rank) = ranking.iter().rposition(|&dist| truth[dist]) {
+        info!("The lowest ranked true distance has rank {}", rank + 1);
+    }
+
+    // h = h1·h0^-1, so a correct h1 gives a sparse h0 and a correct h0 a sparse h1
+    let other = match opt.block {
+        0 => h.clone(),
+        _ => h
+            .inverse()
+            .ok_or_else(|| "The public key is not invertible".to_string())?,
+    };
+    let consistent = |candidate: &SparsePoly| {
+        Some(other.mul_sparse(candidate))
+            .filter(|poly| poly.weight() == d)
+            .map(|poly| poly.to_sparse())
+    };
+    let mut recovered = None;
+    for &delta in ranking.iter().take(opt.attempts) {
+        let accept = |candidate: &[u32]| {
+            // The distance spectrum does not tell a candidate apart from its mirror image
+            let candidate = SparsePoly::new(r, candidate);
+            for found in [candidate.clone(), candidate.mirror()].iter() {
+                if let Some(poly) = consistent(found) {
+                    recovered = Some(match opt.block {
+                        0 => BikeSecretKey {
+                            h0: found.clone(),
+                            h1: poly,
+                        },
+                        _ => BikeSecretKey {
+                            h0: poly,
+                            h1: found.clone(),
+                        },
+                    });
+                    return true;
+                }
+            }
+            false
+        };
+        if reconstruct(r, d, &present, delta, opt.tolerance, opt.anchors, accept).is_some() {
+            info!(
+                "The candidate h{} from delta = {} is consistent with the public key, the secret key is recovered!",
+                opt.block, delta
+            );
+            break;
+        }
+        debug!(
+            "No candidate h{} from delta = {} is consistent with the public key",
+            opt.block, delta
+        );
+    }
+
+    match &recovered {
+        Some(recovered) => match recovered.shift_to(&key) {
+            Some(s) => info!(
+                "Final verdict: the secret key of {} was recovered, up to a cyclic shift by {}",
+                BIKE::NAME,
+                s
+            ),
+            _ => warn!(
+                "Final verdict: the recovered key of {} is equivalent, but not a cyclic shift of the true secret key",
+                BIKE::NAME
+            ),
+        },
+        None => warn!(
+            "Final verdict: failed to reconstruct the secret key of {} from the {} highest scoring distances",
+            BIKE::NAME,
+            opt.attempts
+        ),
+    }
+
+    Ok(recovered.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::reconstruct;
+    use crate::attack::rejection_sampling::gf2x::{cyclic_distance, SparsePoly};
+
+    #[test]
+    fn test_reconstruct() {
+        let (r, d) = (1019, 15);
+        let mut rng = StdRng::seed_from_u64(5);
+        let support: Vec<u32> = rand::seq::index::sample(&mut rng, r, d)
+            .into_iter()
+            .map(|i| i as u32)
+            .collect();
+        let h1 = SparsePoly::new(r, &support);
+        let present: Vec<bool> = h1.distance_spectrum().iter().map(|&c| c > 0).collect();
+        let delta = cyclic_distance(r, support[0] as usize, support[1] as usize);
+
+        let candidate = reconstruct(r, d, &present, delta, 0, 100, |candidate| {
+            let candidate = SparsePoly::new(r, candidate);
+            candidate.shift_to(&h1).is_some() || candidate.mirror().shift_to(&h1).is_some()
+        });
+        assert!(candidate.is_some());
+    }
+}
@@ -0,0 +1,687 @@
+//! Wrappers around decoding failure oracles trading queries for accuracy, which stack with each
+//! other and with the noise of [`NoisyOracle`](super::oracle::NoisyOracle)
+
+use std::{collections::HashMap, fmt, marker::PhantomData, str::FromStr};
+
+use liboqs_rs_bindings as oqs;
+use log::debug;
+use oqs::{KemBuf, KemWithRejectionSampling};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use structopt::StructOpt;
+
+use crate::attack::rejection_sampling::oracle::DecodingFailureOracle;
+
+/// The number of answers a [`CachingOracle`] remembers before it starts over
+const CACHE_CAPACITY: usize = 1 << 20;
+
+/// A k-of-n majority vote, a decoding failure is decided when `k` of the `n` answers detect one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vote {
+    pub k: u32,
+    pub n: u32,
+}
+
+impl FromStr for Vote {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Could not parse {} into a vote like 2/3", s);
+        let (k, n) = s.split_once('/').ok_or_else(err)?;
+        let vote = Vote {
+            k: k.parse().map_err(|_| err())?,
+            n: n.parse().map_err(|_| err())?,
+        };
+        if vote.k == 0 || vote.k > vote.n {
+            return Err(format!("A vote needs 0 < k <= n, got {}", s));
+        }
+        Ok(vote)
+    }
+}
+
+impl fmt::Display for Vote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.k, self.n)
+    }
+}
+
+impl Vote {
+    /// Asks `oracle` about `ct` until the vote is decided, see [`Vote::decide_by`]
+    pub fn decide<KEM, ORACLE>(
+        &self,
+        oracle: &mut ORACLE,
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<bool>, String>
+    where
+        KEM: KemWithRejectionSampling,
+        ORACLE: DecodingFailureOracle<KEM>,
+    {
+        self.decide_by(|| oracle.decoding_failure(ct, sk))
+    }
+
+    /// Repeats `query` until the vote is decided. Stops early once `k` answers detected a decoding
+    /// failure or too few queries are left for it, undecided answers count for neither.
+    pub fn decide_by<F>(&self, mut query: F) -> Result<Option<bool>, String>
+    where
+        F: FnMut() -> Result<Option<bool>, String>,
+    {
+        let (mut failures, mut successes) = (0, 0);
+        for queried in 1..=self.n {
+            match query()? {
+                Some(true) => failures += 1,
+                Some(false) => successes += 1,
+                None => {}
+            }
+            if failures >= self.k {
+                return Ok(Some(true));
+            }
+            if successes > self.n - self.k {
+                return Ok(Some(false));
+            }
+            if failures + self.n - queried < self.k {
+                break;
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// How a detected decoding failure is confirmed before it is added to the chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConfirmationPolicy {
+    /// Every detected decoding failure is accepted
+    None,
+    /// The given number of further queries must all detect the decoding failure
+    Repeat(u32),
+    /// A majority vote of further queries must detect the decoding failure
+    Majority(Vote),
+}
+
+impl Default for ConfirmationPolicy {
+    fn default() -> Self {
+        ConfirmationPolicy::Repeat(1)
+    }
+}
+
+impl FromStr for ConfirmationPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || {
+            format!(
+                "Could not parse {} into either none, repeat, repeat:<n> or majority:<k>/<n>.",
+                s
+            )
+        };
+        match s.split_once(':') {
+            None if s == "none" => Ok(ConfirmationPolicy::None),
+            None if s == "repeat" => Ok(ConfirmationPolicy::Repeat(1)),
+            Some(("repeat", n)) => match n.parse() {
+                Ok(0) => Ok(ConfirmationPolicy::None),
+                Ok(n) => Ok(ConfirmationPolicy::Repeat(n)),
+                Err(_) => Err(err()),
+            },
+            Some(("majority", vote)) => vote.parse().map(ConfirmationPolicy::Majority),
+            _ => Err(err()),
+        }
+    }
+}
+
+impl ConfirmationPolicy {
+    /// Confirms the decoding failure `oracle` detected for `ct`
+    pub fn confirm<KEM, ORACLE>(
+        &self,
+        oracle: &mut ORACLE,
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<bool>, String>
+    where
+        KEM: KemWithRejectionSampling,
+        ORACLE: DecodingFailureOracle<KEM>,
+    {
+        self.confirm_by(|| oracle.decoding_failure(ct, sk))
+    }
+
+    /// Confirms a detected decoding failure by repeating `query`
+    pub fn confirm_by<F>(&self, query: F) -> Result<Option<bool>, String>
+    where
+        F: FnMut() -> Result<Option<bool>, String>,
+    {
+        match self {
+            ConfirmationPolicy::None => Ok(Some(true)),
+            ConfirmationPolicy::Repeat(n) => Vote { k: *n, n: *n }.decide_by(query),
+            ConfirmationPolicy::Majority(vote) => vote.decide_by(query),
+        }
+    }
+}
+
+/// Decides every query with a majority vote of the inner oracle
+#[derive(Debug, Clone)]
+pub struct MajorityOracle<INNER, KEM>
+where
+    INNER: DecodingFailureOracle<KEM>,
+    KEM: KemWithRejectionSampling + Send,
+{
+    inner: INNER,
+    vote: Vote,
+    _kem: PhantomData<KEM>,
+}
+
+impl<INNER, KEM> MajorityOracle<INNER, KEM>
+where
+    INNER: DecodingFailureOracle<KEM>,
+    KEM: KemWithRejectionSampling + Send,
+{
+    pub fn wrap(inner: INNER, vote: Vote) -> Self {
+        MajorityOracle {
+            inner,
+            vote,
+            _kem: PhantomData,
+        }
+    }
+}
+
+impl<INNER, KEM> DecodingFailureOracle<KEM> for MajorityOracle<INNER, KEM>
+where
+    INNER: DecodingFailureOracle<KEM>,
+    KEM: KemWithRejectionSampling + Send,
+{
+    const NAME: &'static str = INNER::NAME;
+
+    fn generate(
+        num_profiling: i32,
+        num_decaps: i32,
+        num_validate: u32,
+        pt: &mut KEM::Plaintext,
+        pt_delta: &mut KEM::Plaintext,
+        pk: &mut KEM::PublicKey,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<Self>, String>
+    where
+        Self: Sized,
+    {
+        let inner = INNER::generate(
+            num_profiling,
+            num_decaps,
+            num_validate,
+            pt,
+            pt_delta,
+            pk,
+            sk,
+        )?;
+        Ok(inner.map(|inner| MajorityOracle::wrap(inner, Vote { k: 1, n: 1 })))
+    }
+
+    fn decoding_failure(
+        &mut self,
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<bool>, String> {
+        self.vote.decide(&mut self.inner, ct, sk)
+    }
+
+    fn needs_ciphertext(&self) -> bool {
+        self.inner.needs_ciphertext()
+    }
+
+    fn error_pattern_decoding_failure(
+        &mut self,
+        e: &[u32],
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<bool>, String> {
+        let inner = &mut self.inner;
+        self.vote
+            .decide_by(|| inner.error_pattern_decoding_failure(e, ct, sk))
+    }
+
+    /// Only a single query keeps its weight, a vote weighs 1
+    fn weighted_decoding_failure(
+        &mut self,
+        e: &[u32],
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<(bool, u32)>, String> {
+        if self.vote.n == 1 {
+            return self.inner.weighted_decoding_failure(e, ct, sk);
+        }
+        Ok(self
+            .error_pattern_decoding_failure(e, ct, sk)?
+            .map(|answer| (answer, 1)))
+    }
+}
+
+/// Repeats undecided queries of the inner oracle, up to the given number of retries
+#[derive(Debug, Clone)]
+pub struct RetryOracle<INNER, KEM>
+where
+    INNER: DecodingFailureOracle<KEM>,
+    KEM: KemWithRejectionSampling + Send,
+{
+    inner: INNER,
+    retries: u32,
+    _kem: PhantomData<KEM>,
+}
+
+impl<INNER, KEM> RetryOracle<INNER, KEM>
+where
+    INNER: DecodingFailureOracle<KEM>,
+    KEM: KemWithRejectionSampling + Send,
+{
+    pub fn wrap(inner: INNER, retries: u32) -> Self {
+        RetryOracle {
+            inner,
+            retries,
+            _kem: PhantomData,
+        }
+    }
+}
+
+impl<INNER, KEM> DecodingFailureOracle<KEM> for RetryOracle<INNER, KEM>
+where
+    INNER: DecodingFailureOracle<KEM>,
+    KEM: KemWithRejectionSampling + Send,
+{
+    const NAME: &'static str = INNER::NAME;
+
+    fn generate(
+        num_profiling: i32,
+        num_decaps: i32,
+        num_validate: u32,
+        pt: &mut KEM::Plaintext,
+        pt_delta: &mut KEM::Plaintext,
+        pk: &mut KEM::PublicKey,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<Self>, String>
+    where
+        Self: Sized,
+    {
+        let inner = INNER::generate(
+            num_profiling,
+            num_decaps,
+            num_validate,
+            pt,
+            pt_delta,
+            pk,
+            sk,
+        )?;
+        Ok(inner.map(|inner| RetryOracle::wrap(inner, 0)))
+    }
+
+    fn decoding_failure(
+        &mut self,
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<bool>, String> {
+        let inner = &mut self.inner;
+        retry(self.retries, || inner.decoding_failure(ct, sk))
+    }
+
+    fn needs_ciphertext(&self) -> bool {
+        self.inner.needs_ciphertext()
+    }
+
+    fn error_pattern_decoding_failure(
+        &mut self,
+        e: &[u32],
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<bool>, String> {
+        let inner = &mut self.inner;
+        retry(self.retries, || {
+            inner.error_pattern_decoding_failure(e, ct, sk)
+        })
+    }
+
+    fn weighted_decoding_failure(
+        &mut self,
+        e: &[u32],
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<(bool, u32)>, String> {
+        let inner = &mut self.inner;
+        retry(self.retries, || inner.weighted_decoding_failure(e, ct, sk))
+    }
+}
+
+/// Repeats `query` while it is undecided, up to `retries` times
+fn retry<T, F>(retries: u32, mut query: F) -> Result<Option<T>, String>
+where
+    F: FnMut() -> Result<Option<T>, String>,
+{
+    for _ in 0..=retries {
+        if let Some(answer) = query()? {
+            return Ok(Some(answer));
+        }
+    }
+    Ok(None)
+}
+
+/// Remembers the decided answers of the inner oracle per ciphertext, by its SHA3-256 hash, or per
+/// error pattern for queries without a ciphertext. Chains revisit error patterns when a mutation
+/// is undone, which gives the same ciphertext again.
+#[derive(Debug, Clone)]
+pub struct CachingOracle<INNER, KEM>
+where
+    INNER: DecodingFailureOracle<KEM>,
+    KEM: KemWithRejectionSampling + Send,
+{
+    inner: INNER,
+    enabled: bool,
+    answers: HashMap<[u8; 32], bool>,
+    capacity: usize,
+    hits: u64,
+    _kem: PhantomData<KEM>,
+}
+
+impl<INNER, KEM> CachingOracle<INNER, KEM>
+where
+    INNER: DecodingFailureOracle<KEM>,
+    KEM: KemWithRejectionSampling + Send,
+{
+    pub fn wrap(inner: INNER, enabled: bool) -> Self {
+        CachingOracle {
+            inner,
+            enabled,
+            answers: HashMap::new(),
+            capacity: CACHE_CAPACITY,
+            hits: 0,
+            _kem: PhantomData,
+        }
+    }
+
+    /// The inner oracle, for queries that must not be answered from the cache, e.g. confirmations
+    pub fn uncached(&mut self) -> &mut INNER {
+        &mut self.inner
+    }
+
+    /// The number of queries answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    fn lookup(&mut self, hash: &[u8; 32]) -> Option<bool> {
+        let answer = self.answers.get(hash).copied();
+        if answer.is_some() {
+            self.hits += 1;
+        }
+        answer
+    }
+
+    fn remember(&mut self, hash: [u8; 32], answer: Option<bool>) {
+        if let Some(answer) = answer {
+            if self.answers.len() >= self.capacity {
+                debug!("The oracle cache is full, starting over");
+                self.answers.clear();
+            }
+            self.answers.insert(hash, answer);
+        }
+    }
+}
+
+impl<INNER, KEM> DecodingFailureOracle<KEM> for CachingOracle<INNER, KEM>
+where
+    INNER: DecodingFailureOracle<KEM>,
+    KEM: KemWithRejectionSampling + Send,
+{
+    const NAME: &'static str = INNER::NAME;
+
+    fn generate(
+        num_profiling: i32,
+        num_decaps: i32,
+        num_validate: u32,
+        pt: &mut KEM::Plaintext,
+        pt_delta: &mut KEM::Plaintext,
+        pk: &mut KEM::PublicKey,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<Self>, String>
+    where
+        Self: Sized,
+    {
+        let inner = INNER::generate(
+            num_profiling,
+            num_decaps,
+            num_validate,
+            pt,
+            pt_delta,
+            pk,
+            sk,
+        )?;
+        Ok(inner.map(|inner| CachingOracle::wrap(inner, true)))
+    }
+
+    fn decoding_failure(
+        &mut self,
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<bool>, String> {
+        if !self.enabled {
+            return self.inner.decoding_failure(ct, sk);
+        }
+        let hash: [u8; 32] = Sha3_256::digest(ct.as_slice()).into();
+        if let Some(answer) = self.lookup(&hash) {
+            return Ok(Some(answer));
+        }
+        let answer = self.inner.decoding_failure(ct, sk)?;
+        self.remember(hash, answer);
+        Ok(answer)
+    }
+
+    fn needs_ciphertext(&self) -> bool {
+        self.inner.needs_ciphertext()
+    }
+
+    fn error_pattern_decoding_failure(
+        &mut self,
+        e: &[u32],
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<bool>, String> {
+        if !self.enabled {
+            return self.inner.error_pattern_decoding_failure(e, ct, sk);
+        }
+        if self.inner.needs_ciphertext() {
+            return self.decoding_failure(ct, sk);
+        }
+        let mut e = e.to_vec();
+        e.sort_unstable();
+        let bytes: Vec<u8> = e.iter().flat_map(|p| p.to_le_bytes()).collect();
+        let hash: [u8; 32] = Sha3_256::digest(&bytes).into();
+        if let Some(answer) = self.lookup(&hash) {
+            return Ok(Some(answer));
+        }
+        let answer = self.inner.error_pattern_decoding_failure(&e, ct, sk)?;
+        self.remember(hash, answer);
+        Ok(answer)
+    }
+
+    /// The cache only remembers the answers, so they weigh 1 while it is enabled
+    fn weighted_decoding_failure(
+        &mut self,
+        e: &[u32],
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<(bool, u32)>, String> {
+        if !self.enabled {
+            return self.inner.weighted_decoding_failure(e, ct, sk);
+        }
+        Ok(self
+            .error_pattern_decoding_failure(e, ct, sk)?
+            .map(|answer| (answer, 1)))
+    }
+}
+
+/// The oracles stacked by [`CombinatorOptions::wrap`], from the outside in
+pub type CombinedOracle<INNER, KEM> =
+    CachingOracle<RetryOracle<MajorityOracle<INNER, KEM>, KEM>, KEM>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, StructOpt, Serialize, Deserialize)]
+pub struct CombinatorOptions {
+    /// Decide every query with a k-of-n majority vote of the oracle, e.g. "2/3"
+    #[structopt(long)]
+    pub majority: Option<Vote>,
+    /// How often to repeat an undecided query, after the majority vote
+    #[structopt(long, default_value("0"))]
+    pub retry_undecided: u32,
+    /// Remember the answers of the oracle per ciphertext, so repeated ciphertexts cost no queries.
+    /// Confirmations are never answered from the cache.
+    #[structopt(long)]
+    pub cache: bool,
+    /// How a detected decoding failure is confirmed before it is accepted: "none", "repeat:<n>" for n further queries
+    /// that must all detect it, or "majority:<k>/<n>" for a majority vote of further queries
+    #[structopt(long, default_value("repeat"))]
+    pub confirmation: ConfirmationPolicy,
+}
+
+impl CombinatorOptions {
+    /// Stacks the majority vote, retries and cache on `oracle`, each is transparent if disabled
+    pub fn wrap<INNER, KEM>(&self, oracle: INNER) -> CombinedOracle<INNER, KEM>
+    where
+        INNER: DecodingFailureOracle<KEM>,
+        KEM: KemWithRejectionSampling + Send,
+    {
+        let vote = self.majority.unwrap_or(Vote { k: 1, n: 1 });
+        CachingOracle::wrap(
+            RetryOracle::wrap(MajorityOracle::wrap(oracle, vote), self.retry_undecided),
+            self.cache,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use liboqs_rs_bindings::{bike::BikeL1, Kem, KemBuf};
+
+    use super::{CachingOracle, ConfirmationPolicy, RetryOracle, Vote};
+    use crate::attack::rejection_sampling::oracle::{
+        tests::{answers, ScriptedOracle},
+        DecodingFailureOracle,
+    };
+
+    const T: Option<bool> = Some(true);
+    const F: Option<bool> = Some(false);
+    const U: Option<bool> = None;
+
+    /// The decision of `vote` on the answers of `script`, with the number of queries it took
+    fn decide(script: &[Option<bool>], vote: Vote) -> (Option<bool>, usize) {
+        confirm(script, ConfirmationPolicy::Majority(vote))
+    }
+
+    /// The confirmation of `policy` on the answers of `script`, with the number of queries it took
+    fn confirm(script: &[Option<bool>], policy: ConfirmationPolicy) -> (Option<bool>, usize) {
+        let mut oracle = ScriptedOracle::new(script);
+        let mut ct = <BikeL1 as Kem>::Ciphertext::new();
+        let mut sk = <BikeL1 as Kem>::SecretKey::new();
+        let answer = policy
+            .confirm::<BikeL1, _>(&mut oracle, &mut ct, &mut sk)
+            .unwrap();
+        (answer, oracle.queries())
+    }
+
+    #[test]
+    fn test_vote() {
+        let vote = Vote { k: 2, n: 3 };
+        assert_eq!(decide(&[T, T, F], vote), (T, 2));
+        assert_eq!(decide(&[F, F, T], vote), (F, 2));
+        assert_eq!(decide(&[T, F, T], vote), (T, 3));
+        assert_eq!(decide(&[F, T, F], vote), (F, 3));
+        // Undecided answers count for neither side, and the vote stops once k is out of reach
+        assert_eq!(decide(&[U, U, T], vote), (U, 2));
+        assert_eq!(decide(&[U, T, U], vote), (U, 3));
+        assert_eq!(decide(&[T, U, T], vote), (T, 3));
+        let unanimous = Vote { k: 3, n: 3 };
+        assert_eq!(decide(&[T, T, T], unanimous), (T, 3));
+        assert_eq!(decide(&[T, F, T], unanimous), (F, 2));
+        assert_eq!(decide(&[U, T, T], unanimous), (U, 1));
+    }
+
+    #[test]
+    fn test_confirmation_policy() {
+        assert_eq!(confirm(&[F], ConfirmationPolicy::None), (T, 0));
+        assert_eq!(confirm(&[T], ConfirmationPolicy::Repeat(1)), (T, 1));
+        assert_eq!(confirm(&[F], ConfirmationPolicy::Repeat(1)), (F, 1));
+        assert_eq!(confirm(&[U], ConfirmationPolicy::Repeat(1)), (U, 1));
+        assert_eq!(confirm(&[T, T, F], ConfirmationPolicy::Repeat(3)), (F, 3));
+        assert_eq!(confirm(&[T, F], ConfirmationPolicy::Repeat(3)), (F, 2));
+        assert_eq!(confirm(&[T], ConfirmationPolicy::Repeat(3)), (T, 3));
+    }
+
+    #[test]
+    fn test_retry_oracle() {
+        let inner = ScriptedOracle::new(&[U, U, T]);
+        let mut oracle = RetryOracle::<_, BikeL1>::wrap(inner.clone(), 2);
+        assert_eq!(answers(&mut oracle, 1), vec![T]);
+        assert_eq!(inner.queries(), 3);
+
+        // The retries are bounded, and start over for the next query
+        let inner = ScriptedOracle::new(&[U, U, U, F]);
+        let mut oracle = RetryOracle::<_, BikeL1>::wrap(inner.clone(), 2);
+        assert_eq!(answers(&mut oracle, 2), vec![U, F]);
+        assert_eq!(inner.queries(), 4);
+
+        let inner = ScriptedOracle::new(&[U, T]);
+        let mut oracle = RetryOracle::<_, BikeL1>::wrap(inner.clone(), 0);
+        assert_eq!(answers(&mut oracle, 2), vec![U, T]);
+        assert_eq!(inner.queries(), 2);
+    }
+
+    #[test]
+    fn test_caching_oracle() {
+        let mut ct = <BikeL1 as Kem>::Ciphertext::new();
+        let mut sk = <BikeL1 as Kem>::SecretKey::new();
+        let mut query = |oracle: &mut CachingOracle<ScriptedOracle, BikeL1>, byte: u8| {
+            ct.as_mut_slice()[0] = byte;
+            oracle.decoding_failure(&mut ct, &mut sk).unwrap()
+        };
+
+        let inner = ScriptedOracle::new(&[T, F, U, F]);
+        let mut oracle = CachingOracle::<_, BikeL1>::wrap(inner.clone(), true);
+        assert_eq!(query(&mut oracle, 0), T);
+        assert_eq!(query(&mut oracle, 1), F);
+        assert_eq!(query(&mut oracle, 0), T);
+        assert_eq!((inner.queries(), oracle.hits()), (2, 1));
+        // Undecided answers are not remembered
+        assert_eq!(query(&mut oracle, 2), U);
+        assert_eq!(query(&mut oracle, 2), F);
+        assert_eq!((inner.queries(), oracle.hits()), (4, 1));
+        // The inner oracle bypasses the cache
+        let answer = answers(oracle.uncached(), 1);
+        assert_eq!(answer, vec![T]);
+        assert_eq!((inner.queries(), oracle.hits()), (5, 1));
+
+        // A full cache starts over
+        let inner = ScriptedOracle::new(&[T, F, F]);
+        let mut oracle = CachingOracle::<_, BikeL1>::wrap(inner.clone(), true);
+        oracle.capacity = 2;
+        assert_eq!(query(&mut oracle, 0), T);
+        assert_eq!(query(&mut oracle, 1), F);
+        assert_eq!(query(&mut oracle, 2), F);
+        assert_eq!(query(&mut oracle, 2), F);
+        assert_eq!(query(&mut oracle, 0), T);
+        assert_eq!((inner.queries(), oracle.hits()), (4, 1));
+
+        // A disabled cache passes every query on
+        let inner = ScriptedOracle::new(&[T, F]);
+        let mut oracle = CachingOracle::<_, BikeL1>::wrap(inner.clone(), false);
+        assert_eq!(query(&mut oracle, 0), T);
+        assert_eq!(query(&mut oracle, 0), F);
+        assert_eq!((inner.queries(), oracle.hits()), (2, 0));
+    }
+
+    #[test]
+    fn test_parse_confirmation_policy() {
+        let parse = |s: &str| s.parse::<ConfirmationPolicy>();
+        assert_eq!(parse("none"), Ok(ConfirmationPolicy::None));
+        assert_eq!(parse("repeat"), Ok(ConfirmationPolicy::Repeat(1)));
+        assert_eq!(parse("repeat:3"), Ok(ConfirmationPolicy::Repeat(3)));
+        assert_eq!(parse("repeat:0"), Ok(ConfirmationPolicy::None));
+        assert_eq!(
+            parse("majority:2/3"),
+            Ok(ConfirmationPolicy::Majority(Vote { k: 2, n: 3 }))
+        );
+        assert!(parse("majority:4/3").is_err());
+        assert!(parse("majority").is_err());
+        assert!(parse("always").is_err());
+    }
+}
@@ -0,0 +1,234 @@
+//! Traces the decisions of a decoding failure oracle during an attack, next to the ground truth
+
+use std::{
+    fmt,
+    fs::File,
+    io::BufWriter,
+    marker::PhantomData,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use csv::Writer;
+use libflate::finish::AutoFinishUnchecked;
+use log::info;
+use oqs::KemWithRejectionSampling;
+use serde::{Deserialize, Serialize};
+
+use liboqs_rs_bindings as oqs;
+
+use crate::{attack::rejection_sampling::oracle::DecodingFailureOracle, utils::StrErr};
+
+/// How the decisions of an attack compare to the truth. A decision is positive when a decoding
+/// failure is detected and confirmed, and negative when no decoding failure is detected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Confusion {
+    pub true_positives: u64,
+    pub false_positives: u64,
+    pub true_negatives: u64,
+    pub false_negatives: u64,
+    /// Queries without a decision, i.e. undecided or not confirmed
+    pub ignored: u64,
+}
+
+impl Confusion {
+    /// The fraction of the decisions without a decoding failure that were positive
+    pub fn false_positive_rate(&self) -> f64 {
+        self.false_positives as f64 / (self.false_positives + self.true_negatives).max(1) as f64
+    }
+
+    /// The fraction of the decisions with a decoding failure that were negative
+    pub fn false_negative_rate(&self) -> f64 {
+        self.false_negatives as f64 / (self.false_negatives + self.true_positives).max(1) as f64
+    }
+}
+
+impl std::ops::Add for Confusion {
+    type Output = Confusion;
+
+    fn add(self, other: Confusion) -> Confusion {
+        Confusion {
+            true_positives: self.true_positives + other.true_positives,
+            false_positives: self.false_positives + other.false_positives,
+            true_negatives: self.true_negatives + other.true_negatives,
+            false_negatives: self.false_negatives + other.false_negatives,
+            ignored: self.ignored + other.ignored,
+        }
+    }
+}
+
+impl fmt::Display for Confusion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TP {} FP {} TN {} FN {} ignored {} (FPR {:.4}, FNR {:.4})",
+            self.true_positives,
+            self.false_positives,
+            self.true_negatives,
+            self.false_negatives,
+            self.ignored,
+            self.false_positive_rate(),
+            self.false_negative_rate()
+        )
+    }
+}
+
+type TraceWriter = Writer<AutoFinishUnchecked<libflate::gzip::Encoder<BufWriter<File>>>>;
+
+/// The trace file and confusion matrix shared by all threads of an attack
+pub struct DecisionTrace {
+    writer: Mutex<TraceWriter>,
+    // In the order of the fields of Confusion
+    counters: [AtomicU64; 5],
+}
+
+impl DecisionTrace {
+    /// Creates the GZIP compressed CSV file the queries are traced to
+    pub fn create(destination: &Path) -> Result<Self, String> {
+        info!("Tracing the oracle decisions to {:?}", destination);
+        let mut writer = csv::Writer::from_writer(AutoFinishUnchecked::new(
+            libflate::gzip::Encoder::new(BufWriter::new(File::create(destination).strerr()?))
+                .strerr()?,
+        ));
+        writer
+            .write_record(&[
+                "chain",
+                "query",
+                "pattern",
+                "del",
+                "add",
+                "answer",
+                "confirmation",
+                "truth",
+            ])
+            .strerr()?;
+        Ok(DecisionTrace {
+            writer: Mutex::new(writer),
+            counters: Default::default(),
+        })
+    }
+
+    pub fn confusion(&self) -> Confusion {
+        let [tp, fp, tn, fn_, ignored] = &self.counters;
+        Confusion {
+            true_positives: tp.load(Ordering::Relaxed),
+            false_positives: fp.load(Ordering::Relaxed),
+            true_negatives: tn.load(Ordering::Relaxed),
+            false_negatives: fn_.load(Ordering::Relaxed),
+            ignored: ignored.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn flush(&self) -> Result<(), String> {
+        self.writer.lock().strerr()?.flush().strerr()
+    }
+}
+
+/// A single query of an attack, identified by its chain, the number of the query in the chain and
+/// the number of the error pattern it mutated
+#[derive(Debug, Clone, Copy)]
+pub struct Query {
+    pub chain: usize,
+    pub query: u64,
+    pub pattern: u32,
+    pub del: u32,
+    pub add: u32,
+}
+
+/// Records the queries of one thread, with the truth computed by the `TRUTH` oracle
+pub struct Tracer<KEM, TRUTH>
+where
+    KEM: KemWithRejectionSampling,
+    TRUTH: DecodingFailureOracle<KEM>,
+{
+    truth: TRUTH,
+    trace: Arc<DecisionTrace>,
+    _kem: PhantomData<KEM>,
+}
+
+// Not derived, as that would require KEM: Clone
+impl<KEM, TRUTH> Clone for Tracer<KEM, TRUTH>
+where
+    KEM: KemWithRejectionSampling,
+    TRUTH: DecodingFailureOracle<KEM>,
+{
+    fn clone(&self) -> Self {
+        Tracer::new(self.truth.clone(), self.trace.clone())
+    }
+}
+
+impl<KEM, TRUTH> Tracer<KEM, TRUTH>
+where
+    KEM: KemWithRejectionSampling,
+    TRUTH: DecodingFailureOracle<KEM>,
+{
+    pub fn new(truth: TRUTH, trace: Arc<DecisionTrace>) -> Self {
+        Tracer {
+            truth,
+            trace,
+            _kem: PhantomData,
+        }
+    }
+
+    /// Records the `answer` and `confirmation` of the oracle to the query with ciphertext `ct`
+    pub fn record(
+        &mut self,
+        query: Query,
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+        answer: Option<bool>,
+        confirmation: Option<bool>,
+    ) -> Result<(), String> {
+        let truth = self
+            .truth
+            .decoding_failure(ct, sk)?
+            .ok_or_else(|| "The truth oracle gave no answer".to_string())?;
+        let idx = match (answer, confirmation, truth) {
+            (Some(true), Some(true), true) => 0,
+            (Some(true), Some(true), false) => 1,
+            (Some(false), _, false) => 2,
+            (Some(false), _, true) => 3,
+            _ => 4,
+        };
+        self.trace.counters[idx].fetch_add(1, Ordering::Relaxed);
+
+        let optional = |answer: Option<bool>| answer.map(|a| a.to_string()).unwrap_or_default();
+        self.trace
+            .writer
+            .lock()
+            .strerr()?
+            .write_record(&[
+                query.chain.to_string(),
+                query.query.to_string(),
+                query.pattern.to_string(),
+                query.del.to_string(),
+                query.add.to_string(),
+                optional(answer),
+                optional(confirmation),
+                truth.to_string(),
+            ])
+            .strerr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Confusion;
+
+    #[test]
+    fn test_confusion_rates() {
+        let confusion = Confusion {
+            true_positives: 9,
+            false_positives: 3,
+            true_negatives: 97,
+            false_negatives: 1,
+            ignored: 5,
+        };
+        assert_eq!(confusion.false_positive_rate(), 0.03);
+        assert_eq!(confusion.false_negative_rate(), 0.1);
+        assert_eq!(Confusion::default().false_positive_rate(), 0.0);
+    }
+}
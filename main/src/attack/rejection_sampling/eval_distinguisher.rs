@@ -0,0 +1,212 @@
+use liboqs_rs_bindings as oqs;
+
+use log::info;
+use log_derive::logfn_inputs;
+use oqs::KemBuf;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+use structopt::StructOpt;
+
+use crate::{
+    attack::rejection_sampling::{
+        attack::get_keypair,
+        bike_attack::read_keypair,
+        experiment::root_seed,
+        noise::ErrorRates,
+        oracle::{
+            construct_decoding_failure_oracle, random_sparse_error_pattern, DecodingFailureKem,
+            DecodingFailureOracle, TimingOracle,
+        },
+        rejections::RejectionCounter,
+    },
+    utils::{ClonableProgressManager, KeySeedOptions, ProgressBars, StrErr},
+};
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct EvalDistinguisherOptions {
+    /// The hamming weight of the error patterns to use.
+    #[structopt(short("w"), long)]
+    hamming_weight: usize,
+    /// The number of decapsulations to measure, in the profiling phase.
+    #[structopt(short("p"), long, default_value("1000"))]
+    pub num_profiling: i32,
+    /// The number of decapsulations to measure, in the profiling phase.
+    #[structopt(short("s"), long, default_value("100"))]
+    pub num_decaps: i32,
+    /// The minimum granularity of the constructed decoder
+    #[structopt(short("i"), long, default_value("10"))]
+    pub delta_iter: u32,
+    /// Which rejection sampling counter the granularity is measured in, either iterations or seedexpanders
+    #[structopt(long, default_value("iterations"))]
+    pub delta_by: RejectionCounter,
+    /// The number of distinguisher decisions to attempt when evauluating the distinguisher.
+    #[structopt(short, long)]
+    trials: u64,
+    /// Location of plaintext database generated by the "attack rejection-sampling collect-plaintexts" command
+    #[structopt(short("d"), long("db"))]
+    pub plaintext_db: PathBuf,
+    /// Where to write the resulting JSON file, e.g. "bike-l1-distinguisher.json", which simulations can take their
+    /// noise model from with "--noise-from"
+    #[structopt(short("f"), long)]
+    pub destination: Option<PathBuf>,
+    /// Location of serialized key pair file. Will be created with a random key if it does not exists.
+    #[structopt(short("k"), long)]
+    pub reuse_key_pair: Option<PathBuf>,
+    #[structopt(flatten)]
+    pub key_seed: KeySeedOptions,
+}
+
+/// The measured error rates of a distinguisher
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvalDistinguisherResult {
+    pub algorithm: String,
+    pub num_decaps: i32,
+    pub trials: u64,
+    pub num_decoding_failures: u64,
+    pub num_false_positives: u64,
+    pub num_false_negatives: u64,
+    pub num_undecided: u64,
+    /// The false positive and false negative rates are relative to the decided trials without and
+    /// with a decoding failure respectively
+    pub rates: ErrorRates,
+}
+
+pub fn run<KEM: DecodingFailureKem>(opt: EvalDistinguisherOptions) -> Result<(), String> {
+    run_with_result::<KEM>(opt).map(|_| ())
+}
+
+#[logfn_inputs(Trace)]
+pub fn run_with_result<KEM: DecodingFailureKem>(
+    opt: EvalDistinguisherOptions,
+) -> Result<EvalDistinguisherResult, String> {
+    let error_range = KEM::error_range();
+
+    opt.key_seed.apply();
+    let (mut pk, mut sk) = match &opt.reuse_key_pair {
+        Some(key_file) => read_keypair::<KEM>(key_file, true)?,
+        None => get_keypair::<KEM>()?,
+    };
+
+    let (mut ideal_oracle, pt_ideal) =
+        construct_decoding_failure_oracle::<KEM, KEM::IdealizedOracle>(
+            &opt.plaintext_db,
+            1,
+            1,
+            1,
+            opt.delta_by,
+            &mut pk,
+            &mut sk,
+        )?;
+    let (mut oracle, pt) = construct_decoding_failure_oracle::<KEM, TimingOracle<KEM>>(
+        &opt.plaintext_db,
+        opt.num_profiling,
+        opt.num_decaps,
+        opt.delta_iter,
+        opt.delta_by,
+        &mut pk,
+        &mut sk,
+    )?;
+
+    // Should be taken from the same db and thus be the same
+    assert_eq!(pt.as_slice(), pt_ideal.as_slice());
+
+    let pm = ClonableProgressManager::<u8>::create();
+
+    pm.add(
+        0,
+        opt.trials * opt.num_decaps as u64,
+        "Testing oracle".to_string(),
+        " [{elapsed_precise}] {msg} {wide_bar} {pos}/{len} ({per_sec}) ETA [{eta_precise}]",
+    );
+    pm.start([]);
+
+    let mut ct = KEM::Ciphertext::new();
+    let mut ss = KEM::SharedSecret::new();
+    let mut rng = root_seed().purpose("error-patterns").rng();
+    let mut num_false_positives = 0;
+    let mut num_false_negatives = 0;
+    let mut num_undecided = 0;
+    let mut num_decoding_failures = 0;
+    let mut num_decided_failures = 0;
+    for i in 0..opt.trials {
+        // Create a new ciphertext
+        let rsep = random_sparse_error_pattern(error_range.clone(), opt.hamming_weight, &mut rng)?;
+        KEM::encaps_with_error_pattern(&mut ct, &mut ss, &mut pk, &pt, &rsep)?;
+        let truth = ideal_oracle
+            .decoding_failure(&mut ct, &mut sk)?
+            .expect("Idealized oracle should never fail to give a result!")
+            as u64;
+        num_decoding_failures += truth;
+        if let Some(detected) = oracle.decoding_failure(&mut ct, &mut sk)? {
+            num_decided_failures += truth;
+            let positive = detected as u64;
+            num_false_positives += positive & (truth ^ 1); // 1 & (0 ^ 1)
+            num_false_negatives += (positive ^ 1) & truth; // (0 ^ 1) & 1
+        } else {
+            num_undecided += 1;
+        }
+
+        let trial_num = i + 1;
+        let fp_rate = num_false_positives as f64 / trial_num as f64;
+        let fn_rate = num_false_negatives as f64 / trial_num as f64;
+        pm.set_message(
+            0,
+            format!(
+                "{} trials, {} false pos. (=>{:.4}), {} false neg. (=>{:.4})",
+                trial_num, num_false_positives, fp_rate, num_false_negatives, fn_rate
+            ),
+        );
+        pm.set_position(0, i * opt.num_decaps as u64);
+        pm.tick();
+    }
+
+    pm.stop();
+
+    let failures = (num_false_positives + num_false_negatives) as f64;
+    let fp_rate = (num_false_positives as f64 / opt.trials as f64).to_string();
+    let fn_rate = (num_false_negatives as f64 / opt.trials as f64).to_string();
+    let epsilon = (failures / (opt.trials as f64)).to_string();
+    let dfr = (num_decoding_failures as f64 / opt.trials as f64).to_string();
+
+    info!("Number of trials without decisions: {}", num_undecided);
+    info!(
+        "Number of false positives: {} (fp rate: {})",
+        num_false_positives, fp_rate
+    );
+    info!(
+        "Number of false positives: {} (fp rate: {})",
+        num_false_negatives, fn_rate
+    );
+    info!("Final verdict: {} decoding failure distinguisher with {} decapsulations per decision results in a epsilon value of {}, with a DFR of {}", KEM::NAME, opt.num_decaps, epsilon, dfr);
+
+    let num_decided_successes = opt.trials - num_undecided - num_decided_failures;
+    let result = EvalDistinguisherResult {
+        algorithm: KEM::NAME.to_string(),
+        num_decaps: opt.num_decaps,
+        trials: opt.trials,
+        num_decoding_failures,
+        num_false_positives,
+        num_false_negatives,
+        num_undecided,
+        rates: ErrorRates {
+            false_positive: num_false_positives as f64 / num_decided_successes.max(1) as f64,
+            false_negative: num_false_negatives as f64 / num_decided_failures.max(1) as f64,
+            undecided: num_undecided as f64 / opt.trials as f64,
+        },
+    };
+    if let Some(destination) = &opt.destination {
+        info!(
+            "Writing the error rates {:?} to {:?}",
+            result.rates, destination
+        );
+        let mut writer = BufWriter::new(File::create(destination).strerr()?);
+        serde_json::to_writer_pretty(&mut writer, &result).strerr()?;
+        writeln!(writer).strerr()?;
+    }
+
+    Ok(result)
+}
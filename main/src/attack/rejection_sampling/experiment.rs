@@ -0,0 +1,140 @@
+//! The state of a single experiment, e.g. the attack against one key of a batch: the seed its
+//! random streams are derived from and the counts of its operations. Every thread works for the
+//! experiment it entered, so several experiments can run in the same process at once.
+
+use std::{cell::RefCell, sync::Arc};
+
+use crate::{
+    attack::rejection_sampling::accounting::Counts,
+    utils::{self, RngSeed},
+};
+
+#[derive(Debug, Clone)]
+pub struct Experiment {
+    seed: RngSeed,
+    counts: Arc<Counts>,
+}
+
+thread_local! {
+    // The experiment this thread works for, the process wide root seed and counts are used while None
+    static CURRENT: RefCell<Option<Experiment>> = const { RefCell::new(None) };
+}
+
+impl Experiment {
+    /// A new experiment with all counts at zero, deriving its random streams from `seed`
+    pub fn new(seed: RngSeed) -> Self {
+        Experiment {
+            seed,
+            counts: Arc::new(Counts::default()),
+        }
+    }
+
+    /// Runs `f` on this thread, working for this experiment
+    pub fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        enter(Some(self.clone()), f)
+    }
+
+    pub(super) fn counts(&self) -> &Counts {
+        &self.counts
+    }
+}
+
+fn enter<T>(experiment: Option<Experiment>, f: impl FnOnce() -> T) -> T {
+    let outer = CURRENT.with(|current| current.replace(experiment));
+    let result = f();
+    CURRENT.with(|current| current.replace(outer));
+    result
+}
+
+/// Gives access to the experiment this thread works for, if any
+pub(super) fn with_current<T>(f: impl FnOnce(Option<&Experiment>) -> T) -> T {
+    CURRENT.with(|current| f(current.borrow().as_ref()))
+}
+
+/// The seed all random streams of the current experiment are derived from, the root seed of the
+/// process outside of an experiment
+pub fn root_seed() -> RngSeed {
+    with_current(|experiment| experiment.map_or_else(RngSeed::root, |e| e.seed))
+}
+
+/// Like [`utils::thread_work`], with every thread working for the experiment of the calling thread
+pub fn thread_work<I, T, ARG, F, FS>(
+    num_threads: usize,
+    seed: RngSeed,
+    arg: ARG,
+    work: F,
+    watch_stop: FS,
+) -> Result<Vec<T>, String>
+where
+    I: Default + Send + Sync + 'static,
+    T: Send + 'static,
+    ARG: Clone + Send + 'static,
+    F: Fn(usize, Arc<I>, ARG, RngSeed) -> Result<Option<T>, String>,
+    F: Send + Clone + 'static,
+    FS: Fn(&Arc<I>) -> bool,
+{
+    let experiment = with_current(|experiment| experiment.cloned());
+    utils::thread_work(
+        num_threads,
+        seed,
+        arg,
+        move |tid, sync, arg, seed| enter(experiment.clone(), || work(tid, sync, arg, seed)),
+        watch_stop,
+    )
+}
+
+/// Like [`utils::run_on_core`], with the thread working for the experiment of the calling thread
+pub fn run_on_core<T, F>(core: Option<usize>, work: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+{
+    let experiment = with_current(|experiment| experiment.cloned());
+    utils::run_on_core(core, move || enter(experiment, work))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use super::{root_seed, thread_work, Experiment};
+    use crate::{
+        attack::rejection_sampling::accounting::{self, Operation, Phase, QueryCounts},
+        utils::RngSeed,
+    };
+
+    #[test]
+    fn test_experiments() {
+        let first = Experiment::new(RngSeed::root().purpose("first"));
+        let second = Experiment::new(RngSeed::root().purpose("second"));
+        assert_eq!(first.run(root_seed), RngSeed::root().purpose("first"));
+        assert_eq!(root_seed(), RngSeed::root());
+
+        // The threads of an experiment count for it, and only for it
+        let counts = first.run(|| {
+            accounting::set_phase(Phase::Chain);
+            accounting::count(Operation::Query, 1);
+            thread_work(
+                3,
+                root_seed(),
+                (),
+                |_, done: Arc<AtomicBool>, _, _| {
+                    accounting::set_phase(Phase::Confirmation);
+                    accounting::count(Operation::Decaps, 2);
+                    done.store(true, Ordering::Relaxed);
+                    Ok(Some(()))
+                },
+                |done| done.load(Ordering::Relaxed),
+            )
+            .unwrap();
+            QueryCounts::snapshot()
+        });
+        assert_eq!(counts.chain.queries, 1);
+        assert_eq!(counts.confirmation.decaps, 6);
+        assert_eq!(counts.total().encaps, 0);
+        assert_eq!(second.run(QueryCounts::snapshot), QueryCounts::default());
+    }
+}
@@ -0,0 +1,402 @@
+//! Polynomials of the cyclic ring GF(2)[x]/(x^r - 1), which the BIKE keys are elements of
+
+use liboqs_rs_bindings as oqs;
+use oqs::{bike::Bike, KemBuf};
+
+/// The distance between positions `a` and `b` of a cyclic vector of length `r`
+pub fn cyclic_distance(r: usize, a: usize, b: usize) -> usize {
+    let d = a.abs_diff(b);
+    std::cmp::min(d, r - d)
+}
+
+/// A sparse polynomial in GF(2)[x]/(x^r - 1), given by its sorted support
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparsePoly {
+    r: usize,
+    support: Vec<u32>,
+}
+
+impl SparsePoly {
+    /// Positions that occur twice cancel out
+    pub fn new(r: usize, support: &[u32]) -> Self {
+        Poly::from_support(r, support).to_sparse()
+    }
+
+    pub fn r(&self) -> usize {
+        self.r
+    }
+
+    pub fn support(&self) -> &[u32] {
+        &self.support
+    }
+
+    pub fn weight(&self) -> usize {
+        self.support.len()
+    }
+
+    /// x^s·self
+    pub fn shift(&self, s: usize) -> SparsePoly {
+        let mut support: Vec<u32> = self
+            .support
+            .iter()
+            .map(|&i| ((i as usize + s) % self.r) as u32)
+            .collect();
+        support.sort_unstable();
+        SparsePoly { r: self.r, support }
+    }
+
+    /// self(x^-1), which has the same distance spectrum
+    pub fn mirror(&self) -> SparsePoly {
+        let mut support: Vec<u32> = self
+            .support
+            .iter()
+            .map(|&i| ((self.r - i as usize) % self.r) as u32)
+            .collect();
+        support.sort_unstable();
+        SparsePoly { r: self.r, support }
+    }
+
+    /// The shift s such that x^s·self = `other`, if any
+    pub fn shift_to(&self, other: &SparsePoly) -> Option<usize> {
+        let first = *self.support.first()? as usize;
+        other
+            .support
+            .iter()
+            .map(|&t| (t as usize + self.r - first) % self.r)
+            .find(|&s| self.shift(s) == *other)
+    }
+
+    pub fn to_dense(&self) -> Poly {
+        Poly::from_support(self.r, &self.support)
+    }
+
+    /// The multiplicity of every distance 0..=r/2 between two positions of the support
+    pub fn distance_spectrum(&self) -> Vec<u32> {
+        let mut distances = vec![0; (self.r + 1) / 2]; // div_ceil
+        for (i, &a) in self.support.iter().enumerate() {
+            for &b in &self.support[i + 1..] {
+                distances[cyclic_distance(self.r, a as usize, b as usize)] += 1;
+            }
+        }
+        distances
+    }
+}
+
+/// A dense polynomial in GF(2)[x]/(x^r - 1), the coefficient of x^i is bit i of the words
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Poly {
+    r: usize,
+    words: Vec<u64>,
+}
+
+impl Poly {
+    pub fn zero(r: usize) -> Self {
+        Poly {
+            r,
+            words: vec![0; (r + 63) / 64], // div_ceil
+        }
+    }
+
+    pub fn one(r: usize) -> Self {
+        Poly::from_support(r, &[0])
+    }
+
+    pub fn from_support(r: usize, support: &[u32]) -> Self {
+        let mut poly = Poly::zero(r);
+        support.iter().for_each(|&i| poly.flip(i as usize % r));
+        poly
+    }
+
+    /// Reads the liboqs layout, where the coefficient of x^i is bit i % 8 of byte i / 8
+    pub fn from_bytes(r: usize, bytes: &[u8]) -> Result<Self, String> {
+        check_bytes(r, bytes.len())?;
+        let mut poly = Poly::zero(r);
+        for i in (0..r).filter(|i| bytes[i / 8] & (1 << (i % 8)) != 0) {
+            poly.flip(i);
+        }
+        Ok(poly)
+    }
+
+    /// Writes the liboqs layout to the first (r + 7) / 8 bytes, see [`Poly::from_bytes`]
+    pub fn write_bytes(&self, bytes: &mut [u8]) -> Result<(), String> {
+        check_bytes(self.r, bytes.len())?;
+        let r_bytes = (self.r + 7) / 8; // div_ceil
+        bytes[..r_bytes].iter_mut().for_each(|b| *b = 0);
+        for i in self.support() {
+            bytes[i as usize / 8] |= 1 << (i % 8);
+        }
+        Ok(())
+    }
+
+    pub fn r(&self) -> usize {
+        self.r
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    pub fn flip(&mut self, i: usize) {
+        self.words[i / 64] ^= 1 << (i % 64);
+    }
+
+    pub fn weight(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    pub fn support(&self) -> Vec<u32> {
+        (0..self.r)
+            .filter(|&i| self.get(i))
+            .map(|i| i as u32)
+            .collect()
+    }
+
+    pub fn to_sparse(&self) -> SparsePoly {
+        SparsePoly {
+            r: self.r,
+            support: self.support(),
+        }
+    }
+
+    /// x^s·self
+    pub fn shift(&self, s: usize) -> Poly {
+        self.to_sparse().shift(s).to_dense()
+    }
+
+    pub fn distance_spectrum(&self) -> Vec<u32> {
+        self.to_sparse().distance_spectrum()
+    }
+
+    pub fn mul(&self, other: &Poly) -> Poly {
+        self.mul_sparse(&other.to_sparse())
+    }
+
+    pub fn mul_sparse(&self, sparse: &SparsePoly) -> Poly {
+        let mut product = Poly::zero(self.r);
+        for i in self.support() {
+            for &j in sparse.support() {
+                product.flip((i + j) as usize % self.r);
+            }
+        }
+        product
+    }
+
+    /// The multiplicative inverse, or None if the polynomial is not invertible.
+    ///
+    /// Uses the extended Euclidean algorithm with x^r + 1 as the modulus.
+    pub fn inverse(&self) -> Option<Poly> {
+        let mut u = self.words.clone();
+        let mut v = Poly::zero(self.r + 1).words;
+        v[0] = 1;
+        v[self.r / 64] ^= 1 << (self.r % 64);
+        let mut g1 = vec![1];
+        let mut g2 = vec![0];
+        loop {
+            match (degree(&u)?, degree(&v)?) {
+                (0, _) => break,
+                (du, dv) if du < dv => {
+                    std::mem::swap(&mut u, &mut v);
+                    std::mem::swap(&mut g1, &mut g2);
+                }
+                (du, dv) => {
+                    add_shifted(&mut u, &v, du - dv);
+                    add_shifted(&mut g1, &g2, du - dv);
+                }
+            }
+        }
+
+        let mut inverse = Poly::zero(self.r);
+        for i in (0..g1.len() * 64).filter(|&i| g1[i / 64] & (1 << (i % 64)) != 0) {
+            inverse.flip(i % self.r);
+        }
+        Some(inverse)
+    }
+}
+
+fn check_bytes(r: usize, len: usize) -> Result<(), String> {
+    let r_bytes = (r + 7) / 8; // div_ceil
+    if len < r_bytes {
+        return Err(format!(
+            "A polynomial of length {} needs {} bytes, got {}",
+            r, r_bytes, len
+        ));
+    }
+    Ok(())
+}
+
+fn degree(a: &[u64]) -> Option<usize> {
+    a.iter()
+        .enumerate()
+        .rev()
+        .find(|(_, &w)| w != 0)
+        .map(|(i, w)| i * 64 + 63 - w.leading_zeros() as usize)
+}
+
+/// a ← a + x^shift·b
+fn add_shifted(a: &mut Vec<u64>, b: &[u64], shift: usize) {
+    let (words, bits) = (shift / 64, shift % 64);
+    if a.len() < b.len() + words + 1 {
+        a.resize(b.len() + words + 1, 0);
+    }
+    for (i, &w) in b.iter().enumerate() {
+        a[i + words] ^= w << bits;
+        if bits != 0 {
+            a[i + words + 1] ^= w >> (64 - bits);
+        }
+    }
+}
+
+/// The column weight d of h0 and h1, which is not part of the parameters exposed by the bindings
+pub fn bike_column_weight(r: usize) -> Result<usize, String> {
+    match r {
+        12323 => Ok(71),
+        24659 => Ok(103),
+        40973 => Ok(137),
+        _ => Err(format!("Unknown BIKE parameter set with r = {}", r)),
+    }
+}
+
+/// The BIKE secret key (h0, h1), with h = h1·h0^-1 as the public key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BikeSecretKey {
+    pub h0: SparsePoly,
+    pub h1: SparsePoly,
+}
+
+impl BikeSecretKey {
+    /// Whether `h` is the public key of this secret key, i.e. h·h0 = h1
+    pub fn matches(&self, h: &Poly) -> bool {
+        h.mul_sparse(&self.h0) == self.h1.to_dense()
+    }
+
+    /// The public key h = h1·h0^-1, or None if h0 is not invertible
+    pub fn public_key(&self) -> Option<Poly> {
+        Some(self.h0.to_dense().inverse()?.mul_sparse(&self.h1))
+    }
+
+    /// The equivalent key (x^s·h0, x^s·h1)
+    pub fn shift(&self, s: usize) -> BikeSecretKey {
+        BikeSecretKey {
+            h0: self.h0.shift(s),
+            h1: self.h1.shift(s),
+        }
+    }
+
+    /// The shift s such that x^s·self = `other`, if any
+    pub fn shift_to(&self, other: &BikeSecretKey) -> Option<usize> {
+        self.h1
+            .shift_to(&other.h1)
+            .filter(|&s| self.h0.shift(s) == other.h0)
+    }
+}
+
+/// Reads h from the start of the public key
+pub fn read_bike_public_key<BIKE: Bike>(pk: &BIKE::PublicKey) -> Result<Poly, String> {
+    Poly::from_bytes(BIKE::params::<usize>().PARAM_R, pk.as_slice())
+}
+
+pub fn write_bike_public_key<BIKE: Bike>(pk: &mut BIKE::PublicKey, h: &Poly) -> Result<(), String> {
+    h.write_bytes(pk.as_mut_slice())
+}
+
+/// Reads the dense h0 and h1, which follow each other at PARAM_SK_OFFSET in the secret key
+pub fn read_bike_secret_key<BIKE: Bike>(sk: &BIKE::SecretKey) -> Result<BikeSecretKey, String> {
+    let params = BIKE::params::<usize>();
+    let r = params.PARAM_R;
+    let bytes = secret_key_bytes(sk.as_slice(), r, params.PARAM_SK_OFFSET)?;
+    let r_bytes = (r + 7) / 8; // div_ceil
+    Ok(BikeSecretKey {
+        h0: Poly::from_bytes(r, bytes)?.to_sparse(),
+        h1: Poly::from_bytes(r, &bytes[r_bytes..])?.to_sparse(),
+    })
+}
+
+/// Writes the dense h0 and h1 of the secret key. When PARAM_SK_OFFSET is exactly the size of
+/// the lists of indices of h0 and h1 (as 32 bit little endian integers) before them, the lists are
+/// written as well.
+pub fn write_bike_secret_key<BIKE: Bike>(
+    sk: &mut BIKE::SecretKey,
+    key: &BikeSecretKey,
+) -> Result<(), String> {
+    let params = BIKE::params::<usize>();
+    let r = params.PARAM_R;
+    let offset = params.PARAM_SK_OFFSET;
+    let r_bytes = (r + 7) / 8; // div_ceil
+    let sk = sk.as_mut_slice();
+    secret_key_bytes(sk, r, offset)?;
+    key.h0.to_dense().write_bytes(&mut sk[offset..])?;
+    key.h1.to_dense().write_bytes(&mut sk[offset + r_bytes..])?;
+
+    let indices = key.h0.support().iter().chain(key.h1.support());
+    if offset == 4 * (key.h0.weight() + key.h1.weight()) {
+        for (chunk, i) in sk[..offset].chunks_exact_mut(4).zip(indices) {
+            chunk.copy_from_slice(&i.to_le_bytes());
+        }
+    }
+    Ok(())
+}
+
+fn secret_key_bytes(sk: &[u8], r: usize, offset: usize) -> Result<&[u8], String> {
+    let r_bytes = (r + 7) / 8; // div_ceil
+    if sk.len() < offset + 2 * r_bytes {
+        return Err(format!(
+            "The secret key is too short to contain h0 and h1 at offset {}",
+            offset
+        ));
+    }
+    Ok(&sk[offset..offset + 2 * r_bytes])
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{BikeSecretKey, Poly, SparsePoly};
+
+    fn random_sparse(r: usize, weight: usize, rng: &mut StdRng) -> SparsePoly {
+        let support: Vec<u32> = rand::seq::index::sample(rng, r, weight)
+            .into_iter()
+            .map(|i| i as u32)
+            .collect();
+        SparsePoly::new(r, &support)
+    }
+
+    #[test]
+    fn test_inverse() {
+        let r = 1019;
+        let mut rng = StdRng::seed_from_u64(1);
+        for weight in [1, 15, 301].iter() {
+            let poly = random_sparse(r, *weight, &mut rng);
+            let inverse = poly.to_dense().inverse().unwrap();
+            assert_eq!(inverse.mul_sparse(&poly), Poly::one(r));
+        }
+
+        // Polynomials of even weight are divisible by x + 1, which divides x^r + 1
+        assert_eq!(Poly::from_support(r, &[0, 5]).inverse(), None);
+    }
+
+    #[test]
+    fn test_key_pair() {
+        let r = 1019;
+        let mut rng = StdRng::seed_from_u64(2);
+        let key = BikeSecretKey {
+            h0: random_sparse(r, 15, &mut rng),
+            h1: random_sparse(r, 15, &mut rng),
+        };
+        let h = key.public_key().unwrap();
+        assert!(key.matches(&h));
+
+        let mut bytes = vec![0; (r + 7) / 8];
+        h.write_bytes(&mut bytes).unwrap();
+        assert_eq!(Poly::from_bytes(r, &bytes).unwrap(), h);
+
+        // Shifted keys are equivalent, and the distance spectrum does not see shifts or mirroring
+        let shifted = key.shift(300);
+        assert!(shifted.matches(&h));
+        assert_eq!(key.shift_to(&shifted), Some(300));
+        assert_eq!(
+            shifted.h1.mirror().distance_spectrum(),
+            key.h1.distance_spectrum()
+        );
+    }
+}
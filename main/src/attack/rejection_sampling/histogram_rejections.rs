@@ -1,9 +1,9 @@
-use crate::utils::StrErr;
+use crate::{attack::rejection_sampling::experiment::root_seed, utils::StrErr};
 use liboqs_rs_bindings as oqs;
 use log::info;
 use log_derive::logfn_inputs;
 use oqs::{KemBuf, KemWithRejectionSampling};
-use rand::{thread_rng, Fill};
+use rand::Fill;
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
@@ -14,7 +14,7 @@ pub struct HistogramRejectionsOptions {
 
 #[logfn_inputs(Debug)]
 pub fn run<KEM: KemWithRejectionSampling>(opt: HistogramRejectionsOptions) -> Result<(), String> {
-    let mut rng = thread_rng();
+    let mut rng = root_seed().purpose("plaintexts").rng();
     let mut plaintext = KEM::Plaintext::new();
     let mut rejections = Vec::new();
     let mut modified = false;
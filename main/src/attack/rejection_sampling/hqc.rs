@@ -0,0 +1,513 @@
+//! A model of the HQC-RMRS internals (round 3 submission, as shipped by liboqs), needed to craft
+//! the ciphertexts of the HQC attack and to simulate its decoder.
+//!
+//! The bindings only expose HQC as an opaque KEM, so the concatenated Reed-Muller/Reed-Solomon
+//! code, the key expansion and the byte layouts are reimplemented here. [`expand_secret_key`]
+//! verifies the model against the public key, so a mismatch with the linked liboqs version is
+//! reported instead of silently producing garbage.
+
+use liboqs_rs_bindings as oqs;
+use oqs::{
+    hqc::{Hqc128, Hqc192, Hqc256},
+    KemBuf,
+};
+use sha3::{
+    digest::{ExtendableOutput, Update, XofReader},
+    Shake256,
+};
+
+use super::rejections::RejectionSamplingStats;
+
+/// Size of the secret and public key seeds
+pub const SEED_BYTES: usize = 40;
+/// Size of the SHA512 hash d = H(m) appended to the ciphertext
+const HASH_BYTES: usize = 64;
+/// Domain separator used when initializing the seedexpander
+const SEEDEXPANDER_DOMAIN: u8 = 2;
+/// Primitive polynomial of GF(2^8), x^8 + x^4 + x^3 + x^2 + 1
+const GF_POLY: u16 = 0x11D;
+/// Length of a single (non duplicated) Reed-Muller RM(1,7) codeword, in bits
+pub const RM_BITS: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HqcParameters {
+    /// Length of the ambient space, vectors are polynomials in GF(2)[x]/(x^n - 1)
+    pub n: usize,
+    /// Length of the Reed-Solomon code, in bytes
+    pub n1: usize,
+    /// Length of the duplicated Reed-Muller code, in bits
+    pub n2: usize,
+    /// Length of the plaintext, in bytes
+    pub k: usize,
+    /// Number of Reed-Solomon symbol errors that can be corrected
+    pub delta: usize,
+    /// Hamming weight of the secret vectors x and y
+    pub omega: usize,
+}
+
+impl HqcParameters {
+    pub fn n1n2(&self) -> usize {
+        self.n1 * self.n2
+    }
+
+    pub fn vec_n_bytes(&self) -> usize {
+        (self.n + 7) / 8 // div_ceil
+    }
+
+    pub fn vec_n1n2_bytes(&self) -> usize {
+        (self.n1n2() + 7) / 8 // div_ceil
+    }
+
+    fn rejection_threshold(&self) -> u32 {
+        (1u32 << 24) / self.n as u32 * self.n as u32
+    }
+}
+
+pub trait Hqc: RejectionSamplingStats {
+    const PARAMS: HqcParameters;
+}
+
+impl Hqc for Hqc128 {
+    const PARAMS: HqcParameters = HqcParameters {
+        n: 17669,
+        n1: 46,
+        n2: 384,
+        k: 16,
+        delta: 15,
+        omega: 66,
+    };
+}
+
+impl Hqc for Hqc192 {
+    const PARAMS: HqcParameters = HqcParameters {
+        n: 35851,
+        n1: 56,
+        n2: 640,
+        k: 24,
+        delta: 16,
+        omega: 100,
+    };
+}
+
+impl Hqc for Hqc256 {
+    const PARAMS: HqcParameters = HqcParameters {
+        n: 57637,
+        n1: 90,
+        n2: 640,
+        k: 32,
+        delta: 29,
+        omega: 131,
+    };
+}
+
+pub fn get_bit(bytes: &[u8], bitnum: usize) -> bool {
+    bytes[bitnum / 8] & (1 << (bitnum % 8)) != 0
+}
+
+pub fn flip_bit(bytes: &mut [u8], bitnum: usize) {
+    bytes[bitnum / 8] ^= 1 << (bitnum % 8);
+}
+
+/// Positions of all set bits among the first `len` bits
+pub fn support(bytes: &[u8], len: usize) -> Vec<u32> {
+    (0..len)
+        .filter(|&i| get_bit(bytes, i))
+        .map(|i| i as u32)
+        .collect()
+}
+
+/// Multiplies `dense` with the polynomial with support `sparse` in GF(2)[x]/(x^n - 1)
+pub fn mul_sparse(n: usize, dense: &[u8], sparse: &[u32]) -> Vec<u8> {
+    let mut res = vec![0; (n + 7) / 8]; // div_ceil
+    for i in support(dense, n) {
+        for &j in sparse {
+            flip_bit(&mut res, (i as usize + j as usize) % n);
+        }
+    }
+    res
+}
+
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut res = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            res ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= (GF_POLY & 0xff) as u8;
+        }
+        b >>= 1;
+    }
+    res
+}
+
+/// Coefficients (lowest degree first) of the generator polynomial prod_{i=1}^{2δ}(x - α^i)
+fn rs_generator(delta: usize) -> Vec<u8> {
+    let mut g = vec![1u8];
+    let mut alpha_i = 1u8;
+    for _ in 0..2 * delta {
+        alpha_i = gf_mul(alpha_i, 2);
+        let mut next = vec![0u8; g.len() + 1];
+        for (i, &c) in g.iter().enumerate() {
+            next[i + 1] ^= c;
+            next[i] ^= gf_mul(c, alpha_i);
+        }
+        g = next;
+    }
+    g
+}
+
+/// Systematic Reed-Solomon encoding, the parity symbols are placed before the message
+pub fn rs_encode(params: &HqcParameters, msg: &[u8]) -> Vec<u8> {
+    let g = rs_generator(params.delta);
+    let parity = params.n1 - params.k;
+    let mut cdw = vec![0u8; params.n1];
+    for i in 0..params.k {
+        let gate_value = msg[params.k - 1 - i] ^ cdw[parity - 1];
+        for j in (1..parity).rev() {
+            cdw[j] = cdw[j - 1] ^ gf_mul(gate_value, g[j]);
+        }
+        cdw[0] = gf_mul(gate_value, g[0]);
+    }
+    cdw[parity..].copy_from_slice(&msg[..params.k]);
+    cdw
+}
+
+/// Bit `j` of the RM(1,7) codeword of `symbol`: bit 7 flips the entire codeword, while bits
+/// 0 to 6 select which bits of `j` are summed.
+pub fn rm_bit(symbol: u8, j: usize) -> bool {
+    let parity = ((symbol & 0x7f) as usize & j).count_ones() % 2 == 1;
+    parity != (symbol & 0x80 != 0)
+}
+
+/// Writes the duplicated Reed-Muller codeword of `symbol` to the `block`:th block of `word`
+pub fn rm_encode_block(params: &HqcParameters, word: &mut [u8], block: usize, symbol: u8) {
+    for i in 0..params.n2 {
+        let bitnum = block * params.n2 + i;
+        if get_bit(word, bitnum) != rm_bit(symbol, i % RM_BITS) {
+            flip_bit(word, bitnum);
+        }
+    }
+}
+
+/// Encodes a plaintext with the concatenated Reed-Muller/Reed-Solomon code
+pub fn encode(params: &HqcParameters, msg: &[u8]) -> Vec<u8> {
+    let mut word = vec![0u8; params.vec_n1n2_bytes()];
+    for (block, symbol) in rs_encode(params, msg).into_iter().enumerate() {
+        rm_encode_block(params, &mut word, block, symbol);
+    }
+    word
+}
+
+/// Maximum likelihood decoding of a single duplicated Reed-Muller block through a Hadamard
+/// transform. Like liboqs, the first of several equally likely symbols is selected.
+fn rm_decode_block(params: &HqcParameters, word: &[u8], block: usize) -> u8 {
+    let mut transform = [0i32; RM_BITS];
+    for i in 0..params.n2 {
+        transform[i % RM_BITS] += 1 - 2 * get_bit(word, block * params.n2 + i) as i32;
+    }
+    let mut half = 1;
+    while half < RM_BITS {
+        for start in (0..RM_BITS).step_by(2 * half) {
+            for j in start..start + half {
+                let (a, b) = (transform[j], transform[j + half]);
+                transform[j] = a + b;
+                transform[j + half] = a - b;
+            }
+        }
+        half *= 2;
+    }
+    let mut peak = 0;
+    for i in 1..RM_BITS {
+        if transform[i].abs() > transform[peak].abs() {
+            peak = i;
+        }
+    }
+    peak as u8 | if transform[peak] < 0 { 0x80 } else { 0 }
+}
+
+/// The Reed-Solomon symbols as seen by the Reed-Solomon decoder, after Reed-Muller decoding
+pub fn rm_decode(params: &HqcParameters, word: &[u8]) -> Vec<u8> {
+    (0..params.n1)
+        .map(|block| rm_decode_block(params, word, block))
+        .collect()
+}
+
+/// Whether `word` decodes to `msg`, i.e. if no more than δ symbols are wrong after the
+/// Reed-Muller decoding
+pub fn decodes_to(params: &HqcParameters, word: &[u8], msg: &[u8]) -> bool {
+    let errors = rm_decode(params, word)
+        .into_iter()
+        .zip(rs_encode(params, msg))
+        .filter(|(a, b)| a != b)
+        .count();
+    errors <= params.delta
+}
+
+/// SHAKE256 based seedexpander, used to expand the key seeds
+struct SeedExpander(<Shake256 as ExtendableOutput>::Reader);
+
+impl SeedExpander {
+    fn new(seed: &[u8]) -> Self {
+        let mut shake = Shake256::default();
+        shake.update(seed);
+        shake.update(&[SEEDEXPANDER_DOMAIN]);
+        SeedExpander(shake.finalize_xof())
+    }
+
+    /// Output is always squeezed in multiples of 8 bytes, the excess is discarded
+    fn expand(&mut self, len: usize) -> Vec<u8> {
+        let mut output = vec![0u8; len];
+        let remainder = len % 8;
+        self.0.read(&mut output[..len - remainder]);
+        if remainder != 0 {
+            let mut tmp = [0u8; 8];
+            self.0.read(&mut tmp);
+            output[len - remainder..].copy_from_slice(&tmp[..remainder]);
+        }
+        output
+    }
+
+    fn random_vector(&mut self, params: &HqcParameters) -> Vec<u8> {
+        let mut v = self.expand(params.vec_n_bytes());
+        *v.last_mut().unwrap() &= 0xff >> ((8 - params.n % 8) % 8);
+        v
+    }
+
+    /// The rejection sampling of fixed weight vectors, targeted by the attack
+    fn fixed_weight_vector(&mut self, params: &HqcParameters, weight: usize) -> Vec<u32> {
+        let size = 3 * weight;
+        let mut rand_bytes = self.expand(size);
+        let mut j = 0;
+        let mut support = Vec::with_capacity(weight);
+        while support.len() < weight {
+            let candidate = loop {
+                if j == size {
+                    rand_bytes = self.expand(size);
+                    j = 0;
+                }
+                let candidate = (rand_bytes[j] as u32) << 16
+                    | (rand_bytes[j + 1] as u32) << 8
+                    | rand_bytes[j + 2] as u32;
+                j += 3;
+                if candidate < params.rejection_threshold() {
+                    break candidate % params.n as u32;
+                }
+            };
+            if !support.contains(&candidate) {
+                support.push(candidate);
+            }
+        }
+        support.sort_unstable();
+        support
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HqcSecretKey {
+    pub x: Vec<u32>,
+    pub y: Vec<u32>,
+}
+
+fn check_len<B: KemBuf>(what: &str, expected: usize) -> Result<(), String> {
+    if B::len() != expected {
+        return Err(format!(
+            "The {} is {} bytes long, expected {} bytes according to the HQC-RMRS model",
+            what,
+            B::len(),
+            expected
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the public vectors h and s
+pub fn expand_public_key<HQC: Hqc>(pk: &HQC::PublicKey) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let params = HQC::PARAMS;
+    check_len::<HQC::PublicKey>("public key", SEED_BYTES + params.vec_n_bytes())?;
+    let pk = pk.as_slice();
+    let h = SeedExpander::new(&pk[..SEED_BYTES]).random_vector(&params);
+    Ok((h, pk[SEED_BYTES..].to_vec()))
+}
+
+/// If `y` is the secret key of the public key, returns x = s + h·y
+pub fn check_public_key<HQC: Hqc>(
+    pk: &HQC::PublicKey,
+    y: &[u32],
+) -> Result<Option<Vec<u32>>, String> {
+    let params = HQC::PARAMS;
+    let (h, mut s) = expand_public_key::<HQC>(pk)?;
+    for (s, hy) in s.iter_mut().zip(mul_sparse(params.n, &h, y)) {
+        *s ^= hy;
+    }
+    let x = support(&s, params.n);
+    Ok(if x.len() == params.omega {
+        Some(x)
+    } else {
+        None
+    })
+}
+
+/// Expands the secret vectors x and y from the seed stored in the secret key
+pub fn expand_secret_key<HQC: Hqc>(sk: &HQC::SecretKey) -> Result<HqcSecretKey, String> {
+    let params = HQC::PARAMS;
+    check_len::<HQC::SecretKey>("secret key", 2 * SEED_BYTES + params.vec_n_bytes())?;
+    let mut expander = SeedExpander::new(&sk.as_slice()[..SEED_BYTES]);
+    let x = expander.fixed_weight_vector(&params, params.omega);
+    let y = expander.fixed_weight_vector(&params, params.omega);
+
+    let mut pk = HQC::PublicKey::new();
+    pk.as_mut_slice()
+        .copy_from_slice(&sk.as_slice()[SEED_BYTES..]);
+    if check_public_key::<HQC>(&pk, &y)?.as_ref() != Some(&x) {
+        return Err(format!(
+            "The expanded {} secret key does not match its public key, the linked liboqs is not compatible with the HQC-RMRS model",
+            HQC::NAME
+        ));
+    }
+    Ok(HqcSecretKey { x, y })
+}
+
+/// Creates a ciphertext (u, v), the hash d is left as zeros since it is only verified after the
+/// re-encryption
+pub fn ciphertext<HQC: Hqc>(u: &[u8], v: &[u8]) -> Result<HQC::Ciphertext, String> {
+    let params = HQC::PARAMS;
+    check_len::<HQC::Ciphertext>(
+        "ciphertext",
+        params.vec_n_bytes() + params.vec_n1n2_bytes() + HASH_BYTES,
+    )?;
+    let mut ct = HQC::Ciphertext::new();
+    let (ct_u, rest) = ct.as_mut_slice().split_at_mut(params.vec_n_bytes());
+    ct_u.copy_from_slice(u);
+    rest[..params.vec_n1n2_bytes()].copy_from_slice(v);
+    rest[params.vec_n1n2_bytes()..]
+        .iter_mut()
+        .for_each(|b| *b = 0);
+    Ok(ct)
+}
+
+/// Computes v - u·y, the word given to the decoder during decapsulation
+pub fn decryption_word<HQC: Hqc>(ct: &HQC::Ciphertext, y: &[u32]) -> Result<Vec<u8>, String> {
+    let params = HQC::PARAMS;
+    check_len::<HQC::Ciphertext>(
+        "ciphertext",
+        params.vec_n_bytes() + params.vec_n1n2_bytes() + HASH_BYTES,
+    )?;
+    let (u, rest) = ct.as_slice().split_at(params.vec_n_bytes());
+    let mut word = rest[..params.vec_n1n2_bytes()].to_vec();
+    for (w, uy) in word.iter_mut().zip(mul_sparse(params.n, u, y)) {
+        *w ^= uy;
+    }
+    Ok(word)
+}
+
+/// Flips the positions `e` of v, which adds them as errors to the word given to the decoder
+pub fn add_error_to_v<HQC: Hqc>(ct: &mut HQC::Ciphertext, e: &[u32]) -> Result<(), String> {
+    let params = HQC::PARAMS;
+    check_len::<HQC::Ciphertext>(
+        "ciphertext",
+        params.vec_n_bytes() + params.vec_n1n2_bytes() + HASH_BYTES,
+    )?;
+    let v = &mut ct.as_mut_slice()[params.vec_n_bytes()..][..params.vec_n1n2_bytes()];
+    for &i in e {
+        flip_bit(v, i as usize);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn test_rs_generator() {
+        assert_eq!(
+            rs_generator(Hqc128::PARAMS.delta),
+            vec![
+                89, 69, 153, 116, 176, 117, 111, 75, 73, 233, 242, 233, 65, 210, 21, 139, 103, 173,
+                67, 118, 105, 210, 174, 110, 74, 69, 228, 82, 255, 181, 1
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode() {
+        let params = Hqc128::PARAMS;
+        let mut rng = StdRng::seed_from_u64(1);
+        let msg: Vec<u8> = (0..params.k).map(|_| rng.gen()).collect();
+        let mut word = encode(&params, &msg);
+        assert_eq!(rm_decode(&params, &word), rs_encode(&params, &msg));
+
+        // Sparse noise is corrected by the Reed-Muller code
+        let noise = rand::seq::index::sample(&mut rng, params.n1n2(), 2 * params.omega);
+        for i in noise.iter() {
+            flip_bit(&mut word, i);
+        }
+        assert!(decodes_to(&params, &word, &msg));
+
+        // Up to δ broken blocks are corrected by the Reed-Solomon code
+        let symbols = rs_encode(&params, &msg);
+        for (block, symbol) in symbols.iter().enumerate().take(params.delta) {
+            rm_encode_block(&params, &mut word, block, !symbol);
+        }
+        assert!(decodes_to(&params, &word, &msg));
+        rm_encode_block(&params, &mut word, params.delta, !symbols[params.delta]);
+        assert!(!decodes_to(&params, &word, &msg));
+    }
+
+    /// Compares the model to liboqs over many keys and plaintexts. The bindings expose no
+    /// intermediaries of the HQC decapsulation, so the model is checked against what liboqs
+    /// exposes: the expanded keys must reproduce its public keys, the ciphertexts it accepts must
+    /// decode to their plaintexts, and error patterns must change the decoder input bit by bit.
+    fn matches_liboqs<HQC: Hqc>(keys: usize, samples: usize) {
+        let params = HQC::PARAMS;
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut ct = HQC::Ciphertext::new();
+        let mut ss = HQC::SharedSecret::new();
+        let mut decapsulated = HQC::SharedSecret::new();
+        for key in 0..keys {
+            let (mut pk, mut sk) = HQC::keypair().unwrap();
+            let HqcSecretKey { x, y } = expand_secret_key::<HQC>(&sk).unwrap();
+            assert_eq!(check_public_key::<HQC>(&pk, &y).unwrap(), Some(x));
+            for sample in 0..samples {
+                let mut pt = HQC::Plaintext::new();
+                rng.fill(pt.as_mut_slice());
+                HQC::encaps_with_plaintext(&mut ct, &mut ss, &mut pk, &mut pt).unwrap();
+                HQC::decaps(&mut ct, &mut decapsulated, &mut sk).unwrap();
+                assert_eq!(decapsulated.as_slice(), ss.as_slice());
+                let word = decryption_word::<HQC>(&ct, &y).unwrap();
+                assert!(
+                    decodes_to(&params, &word, pt.as_slice()),
+                    "{} key {} sample {}",
+                    HQC::NAME,
+                    key,
+                    sample
+                );
+
+                let weight = rng.gen_range(1..4 * params.omega);
+                let e: Vec<u32> = rand::seq::index::sample(&mut rng, params.n1n2(), weight)
+                    .iter()
+                    .map(|i| i as u32)
+                    .collect();
+                let mut tampered = ct.clone();
+                add_error_to_v::<HQC>(&mut tampered, &e).unwrap();
+                let mut expected = word.clone();
+                for &i in &e {
+                    flip_bit(&mut expected, i as usize);
+                }
+                assert_eq!(decryption_word::<HQC>(&tampered, &y).unwrap(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hqc_model_matches_liboqs() {
+        matches_liboqs::<Hqc128>(20, 50);
+        matches_liboqs::<Hqc192>(5, 20);
+        matches_liboqs::<Hqc256>(5, 20);
+    }
+}
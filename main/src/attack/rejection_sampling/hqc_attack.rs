@@ -0,0 +1,667 @@
+use std::{
+    collections::BTreeSet,
+    ops::Range,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use liboqs_rs_bindings as oqs;
+use log::{debug, info, warn};
+use log_derive::logfn_inputs;
+use oqs::KemBuf;
+use rand::{seq::SliceRandom, Rng};
+use structopt::StructOpt;
+
+use crate::{
+    attack::rejection_sampling::{
+        accounting::{self, BudgetOptions, Operation, Phase, QueryCounts},
+        attack::{get_keypair, record_decaps_to},
+        bike_attack::read_keypair,
+        combinators::{CachingOracle, CombinatorOptions, ConfirmationPolicy},
+        experiment::{root_seed, thread_work},
+        hqc::{
+            ciphertext, decodes_to, decryption_word, encode, expand_secret_key, flip_bit, rm_bit,
+            rm_encode_block, rs_encode, Hqc, HqcParameters, RM_BITS,
+        },
+        oracle::{DecodingFailureOracle, OracleKind},
+        plaintexts::PlaintextDb,
+        rejections::RejectionCounter,
+    },
+    utils::{ClonableProgressManager, KeySeedOptions, ProgressBars, Rec, Recorder, RngSeed},
+};
+
+#[derive(Debug, StructOpt)]
+pub struct HqcAttackOptions {
+    /// The number of threads to use
+    #[structopt(short("t"), long)]
+    threads: usize,
+    /// The oracle used to decide if a ciphertext decrypts to the selected plaintext, either ideal or timing
+    #[structopt(short, long, default_value("ideal"))]
+    oracle: OracleKind,
+    /// The number of decapsulations to measure, in the profiling phase of the timing oracle.
+    #[structopt(short("p"), long, default_value("1000"))]
+    pub num_profiling: i32,
+    /// The number of decapsulations to measure, per decision of the timing oracle.
+    #[structopt(short("s"), long, default_value("100"))]
+    pub num_decaps: i32,
+    /// Location of plaintext database generated by the "attack rejection-sampling collect-plaintexts" command
+    #[structopt(short("d"), long("db"))]
+    pub plaintext_db: PathBuf,
+    /// Location of serialized key pair file. Will be created with a random key if it does not exists.
+    #[structopt(short("k"), long)]
+    pub reuse_key_pair: Option<PathBuf>,
+    #[structopt(flatten)]
+    pub combinators: CombinatorOptions,
+    #[structopt(flatten)]
+    pub budget: BudgetOptions,
+    #[structopt(flatten)]
+    pub key_seed: KeySeedOptions,
+}
+
+pub trait HqcOracle<HQC: Hqc>: std::fmt::Debug + Clone + Send {
+    const NAME: &'static str;
+    fn generate(
+        num_profiling: i32,
+        num_decaps: i32,
+        pt: &mut HQC::Plaintext,
+        pk: &mut HQC::PublicKey,
+        sk: &mut HQC::SecretKey,
+    ) -> Result<Option<Self>, String>
+    where
+        Self: Sized;
+
+    /// Whether the decapsulation of `ct` decrypts to the plaintext the oracle was generated for
+    fn decrypts_to_plaintext(
+        &mut self,
+        ct: &mut HQC::Ciphertext,
+        sk: &mut HQC::SecretKey,
+    ) -> Result<Option<bool>, String>;
+}
+
+/// Answers by running the decoder of the HQC model on v - u·y, with y expanded from the secret key
+#[derive(Debug, Clone)]
+pub struct HqcIdealizedOracle {
+    y: Vec<u32>,
+    pt: Vec<u8>,
+}
+
+impl<HQC: Hqc> HqcOracle<HQC> for HqcIdealizedOracle {
+    const NAME: &'static str = "IDEAL";
+
+    fn generate(
+        _num_profiling: i32,
+        _num_decaps: i32,
+        pt: &mut HQC::Plaintext,
+        pk: &mut HQC::PublicKey,
+        sk: &mut HQC::SecretKey,
+    ) -> Result<Option<Self>, String>
+    where
+        Self: Sized,
+    {
+        let y = expand_secret_key::<HQC>(sk)?.y;
+
+        // An honest encapsulation must decrypt to its plaintext, otherwise the code model is off
+        let mut ct = HQC::Ciphertext::new();
+        let mut ss = HQC::SharedSecret::new();
+        HQC::encaps_with_plaintext(&mut ct, &mut ss, pk, pt)?;
+        accounting::count(Operation::Encaps, 1);
+        let word = decryption_word::<HQC>(&ct, &y)?;
+        if !decodes_to(&HQC::PARAMS, &word, pt.as_slice()) {
+            return Err(format!(
+                "An honest {} ciphertext does not decode to its plaintext, the linked liboqs is not compatible with the HQC-RMRS model",
+                HQC::NAME
+            ));
+        }
+
+        Ok(Some(Self {
+            y,
+            pt: pt.as_slice().to_vec(),
+        }))
+    }
+
+    fn decrypts_to_plaintext(
+        &mut self,
+        ct: &mut HQC::Ciphertext,
+        _sk: &mut HQC::SecretKey,
+    ) -> Result<Option<bool>, String> {
+        // Stands in for the decapsulation of a real oracle
+        accounting::count(Operation::Decaps, 1);
+        let word = decryption_word::<HQC>(ct, &self.y)?;
+        Ok(Some(decodes_to(&HQC::PARAMS, &word, &self.pt)))
+    }
+}
+
+/// A decoding failure is a ciphertext that does not decrypt to the plaintext of the oracle
+impl<HQC: Hqc> DecodingFailureOracle<HQC> for HqcIdealizedOracle {
+    const NAME: &'static str = "IDEAL";
+
+    fn generate(
+        num_profiling: i32,
+        num_decaps: i32,
+        _num_validate: u32,
+        pt: &mut HQC::Plaintext,
+        _pt_delta: &mut HQC::Plaintext,
+        pk: &mut HQC::PublicKey,
+        sk: &mut HQC::SecretKey,
+    ) -> Result<Option<Self>, String>
+    where
+        Self: Sized,
+    {
+        <Self as HqcOracle<HQC>>::generate(num_profiling, num_decaps, pt, pk, sk)
+    }
+
+    fn decoding_failure(
+        &mut self,
+        ct: &mut HQC::Ciphertext,
+        sk: &mut HQC::SecretKey,
+    ) -> Result<Option<bool>, String> {
+        Ok(
+            <Self as HqcOracle<HQC>>::decrypts_to_plaintext(self, ct, sk)?
+                .map(|decrypts| !decrypts),
+        )
+    }
+}
+
+/// Detects decoding failures with an [`HqcOracle`], so that the combinators can be stacked on it.
+/// Counts every query made to the wrapped oracle.
+#[derive(Debug, Clone)]
+struct FailureOracle<ORACLE>(ORACLE);
+
+impl<HQC, ORACLE> DecodingFailureOracle<HQC> for FailureOracle<ORACLE>
+where
+    HQC: Hqc,
+    ORACLE: HqcOracle<HQC>,
+{
+    const NAME: &'static str = ORACLE::NAME;
+
+    fn generate(
+        num_profiling: i32,
+        num_decaps: i32,
+        _num_validate: u32,
+        pt: &mut HQC::Plaintext,
+        _pt_delta: &mut HQC::Plaintext,
+        pk: &mut HQC::PublicKey,
+        sk: &mut HQC::SecretKey,
+    ) -> Result<Option<Self>, String>
+    where
+        Self: Sized,
+    {
+        Ok(ORACLE::generate(num_profiling, num_decaps, pt, pk, sk)?.map(FailureOracle))
+    }
+
+    fn decoding_failure(
+        &mut self,
+        ct: &mut HQC::Ciphertext,
+        sk: &mut HQC::SecretKey,
+    ) -> Result<Option<bool>, String> {
+        accounting::count(Operation::Query, 1);
+        Ok(self
+            .0
+            .decrypts_to_plaintext(ct, sk)?
+            .map(|decrypts| !decrypts))
+    }
+}
+
+/// Decapsulations that decrypt to the selected plaintext, with many rejections, are slower
+#[derive(Debug, Clone)]
+pub struct HqcTimingOracle {
+    num_decaps: i32,
+    threshold: u64,
+}
+
+impl<HQC: Hqc> HqcOracle<HQC> for HqcTimingOracle {
+    const NAME: &'static str = "TIMING";
+
+    fn generate(
+        num_profiling: i32,
+        num_decaps: i32,
+        pt: &mut HQC::Plaintext,
+        _pk: &mut HQC::PublicKey,
+        sk: &mut HQC::SecretKey,
+    ) -> Result<Option<Self>, String>
+    where
+        Self: Sized,
+    {
+        let params = HQC::PARAMS;
+        let symbols = rs_encode(&params, pt.as_slice());
+        let success = encode(&params, pt.as_slice());
+        // With one more broken block than the Reed-Solomon code can correct
+        let mut failure = success.clone();
+        for (block, symbol) in symbols.iter().enumerate().take(params.delta + 1) {
+            rm_encode_block(&params, &mut failure, block, !symbol);
+        }
+
+        let mut bounds = vec![];
+        for (name, v) in [("decrypting", success), ("non-decrypting", failure)].iter() {
+            info!(
+                "Starting {} decapsulations of the {} ciphertext",
+                num_profiling, name
+            );
+            let mut ct = query_ciphertext::<HQC>(0, v)?;
+            let recorder = record_decaps_to::<HQC, _>(
+                Recorder::saveall(name, None),
+                &mut ct,
+                sk,
+                num_profiling,
+            )?;
+            match recorder.nth_lowest_value((num_profiling / 100) as u64) {
+                Some(bound) => {
+                    info!("1% lowest measurment of {} ciphertext: {}", name, bound);
+                    bounds.push(bound)
+                }
+                None => {
+                    warn!("No recordings!");
+                    return Ok(None);
+                }
+            }
+        }
+
+        if bounds[0] <= bounds[1] {
+            warn!("Decrypting to the selected plaintext is not slower than decrypting to another plaintext!");
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            num_decaps,
+            threshold: (bounds[0] + bounds[1]) / 2,
+        }))
+    }
+
+    fn decrypts_to_plaintext(
+        &mut self,
+        ct: &mut HQC::Ciphertext,
+        sk: &mut HQC::SecretKey,
+    ) -> Result<Option<bool>, String> {
+        let recorder =
+            record_decaps_to::<HQC, _>(Recorder::saveall("_", None), ct, sk, self.num_decaps)?;
+        Ok(recorder
+            .nth_lowest_value((self.num_decaps / 100) as u64)
+            .map(|val| val >= self.threshold))
+    }
+}
+
+/// Creates the ciphertext (x^shift, v), which is decoded as v - x^shift·y
+fn query_ciphertext<HQC: Hqc>(shift: usize, v: &[u8]) -> Result<HQC::Ciphertext, String> {
+    let mut u = vec![0u8; HQC::PARAMS.vec_n_bytes()];
+    flip_bit(&mut u, shift);
+    ciphertext::<HQC>(&u, v)
+}
+
+/// Recovers which bits of the `target` block of the decoder input are flipped by the secret key,
+/// i.e. the support of x^shift·y within the block.
+///
+/// Exactly δ other blocks are broken, so that the plaintext is only recovered if the target
+/// block is decoded correctly. Bits are then flipped in the target block, inside the set D where
+/// its codeword differs from a competing codeword, until the decoder is exactly at the decision
+/// boundary between the two. At the boundary, toggling a single position of D decides the
+/// decoding, and the direction reveals if the secret key flips that position.
+fn recover_block<Q>(
+    params: &HqcParameters,
+    symbols: &[u8],
+    target: usize,
+    rng: &mut impl Rng,
+    mut query: Q,
+) -> Result<Vec<usize>, String>
+where
+    Q: FnMut(&[u8]) -> Result<bool, String>,
+{
+    let mut base = vec![0u8; params.vec_n1n2_bytes()];
+    for (block, &symbol) in symbols.iter().enumerate() {
+        rm_encode_block(params, &mut base, block, symbol);
+    }
+    // Only break blocks of the systematic part, so that a failed decoding never leaves the
+    // plaintext untouched
+    (params.n1 - params.k..params.n1)
+        .filter(|&block| block != target)
+        .take(params.delta)
+        .for_each(|block| rm_encode_block(params, &mut base, block, !symbols[block]));
+
+    let with_errors = |errors: &[usize], toggle: Option<usize>| {
+        let mut v = base.clone();
+        for &i in errors.iter().chain(toggle.iter()) {
+            flip_bit(&mut v, target * params.n2 + i);
+        }
+        v
+    };
+
+    let mut flipped = vec![];
+    // D is where the codewords of the target symbol and the target symbol ^ difference differ,
+    // these two differences cover all positions of the block
+    for &difference in [0x01u8, 0x81].iter() {
+        let mut d: Vec<usize> = (0..params.n2)
+            .filter(|&i| rm_bit(difference, i % RM_BITS))
+            .collect();
+        d.shuffle(rng);
+
+        // Binary search for a set of errors in D that is exactly at the decision boundary: the
+        // first `lo` positions of D are decoded correctly, but adding d[lo] is one error too
+        // many. This also implies that d[lo] is not flipped by the secret key.
+        let (mut lo, mut hi) = (0, d.len());
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            if query(&with_errors(&d[..mid], None))? {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        debug!(
+            "Block {}: decision boundary found with {} of {} errors",
+            target,
+            lo,
+            d.len()
+        );
+
+        for (i, &p) in d.iter().enumerate().filter(|&(i, _)| i != lo) {
+            // Add p if it is outside the boundary set, remove it if it is inside
+            let inside = i < lo;
+            if query(&with_errors(&d[..lo], Some(p)))? != inside {
+                flipped.push(p);
+            }
+        }
+    }
+    flipped.sort_unstable();
+    Ok(flipped)
+}
+
+#[derive(Debug, Clone)]
+struct BlockJob {
+    target: usize,
+    shift: usize,
+    keep: Range<usize>,
+}
+
+#[derive(Debug, Default)]
+struct JobQueue {
+    next: AtomicUsize,
+    done: AtomicUsize,
+    failed: AtomicBool,
+    /// Set when the budget is used up, the blocks being recovered are finished
+    exhausted: AtomicBool,
+}
+
+/// Whether `ct` decrypts to the plaintext of the oracle. As in the BIKE chain, a decoding failure
+/// is only accepted once `confirmation_policy` confirmed it.
+fn decides<HQC, ORACLE>(
+    oracle: &mut CachingOracle<ORACLE, HQC>,
+    confirmation_policy: ConfirmationPolicy,
+    ct: &mut HQC::Ciphertext,
+    sk: &mut HQC::SecretKey,
+) -> Result<bool, String>
+where
+    HQC: Hqc + Send,
+    ORACLE: DecodingFailureOracle<HQC>,
+{
+    match oracle.decoding_failure(ct, sk)? {
+        Some(true) => {
+            accounting::set_phase(Phase::Confirmation);
+            let confirmation = confirmation_policy.confirm(oracle.uncached(), ct, sk)?;
+            accounting::set_phase(Phase::Chain);
+            Ok(confirmation != Some(true))
+        }
+        Some(false) => Ok(true),
+        None => Err(format!(
+            "The {} oracle could not decide, consider retrying with --retry-undecided",
+            ORACLE::NAME
+        )),
+    }
+}
+
+fn attack<HQC, ORACLE>(
+    opt: &HqcAttackOptions,
+    sk: HQC::SecretKey,
+    pt: HQC::Plaintext,
+    oracle: ORACLE,
+) -> Result<Vec<u32>, String>
+where
+    HQC: Hqc + Send + 'static,
+    ORACLE: HqcOracle<HQC> + 'static,
+{
+    let params = HQC::PARAMS;
+    let symbols = rs_encode(&params, pt.as_slice());
+
+    // Every block of the decoder input, and the positions of y truncated from v
+    let mut jobs: Vec<_> = (0..params.n1)
+        .map(|target| BlockJob {
+            target,
+            shift: 0,
+            keep: 0..params.n2,
+        })
+        .collect();
+    let tail = params.n - params.n1n2();
+    if tail > 0 {
+        jobs.push(BlockJob {
+            target: 0,
+            shift: tail,
+            keep: 0..tail,
+        });
+    }
+    let jobs = Arc::new(jobs);
+    let num_jobs = jobs.len();
+
+    let pm = ClonableProgressManager::create();
+    pm.add(
+        0u8,
+        num_jobs as u64,
+        "Recovering blocks of y",
+        " [{elapsed_precise}] {bar:40} {pos:>7}/{len:7} [{eta_precise}] {wide_msg} ",
+    );
+    pm.start(None);
+
+    let recovered =
+        thread_work(
+            opt.threads,
+            root_seed().purpose("hqc-attack"),
+            (sk, oracle, jobs, symbols, opt.budget, opt.combinators),
+            move |_tid,
+                  queue: Arc<JobQueue>,
+                  (mut sk, oracle, jobs, symbols, budget, combinators),
+                  seed: RngSeed| {
+                accounting::set_phase(Phase::Chain);
+                let mut oracle = combinators.wrap::<_, HQC>(FailureOracle(oracle));
+                let mut rng = seed.rng();
+                let (mut positions, mut blocks) = (vec![], 0);
+                while let Some(job) = jobs.get(queue.next.fetch_add(1, Ordering::Relaxed)) {
+                    if let Some(reason) = budget.exhausted() {
+                        if !queue.exhausted.swap(true, Ordering::Relaxed) {
+                            warn!("Stopping the attack, as {}", reason);
+                        }
+                        break;
+                    }
+                    let flipped = recover_block(&params, &symbols, job.target, &mut rng, |v| {
+                        let mut ct = query_ciphertext::<HQC>(job.shift, v)?;
+                        decides(&mut oracle, combinators.confirmation, &mut ct, &mut sk)
+                    });
+                    let flipped = match flipped {
+                        Ok(flipped) => flipped,
+                        Err(err) => {
+                            queue.failed.store(true, Ordering::Relaxed);
+                            return Err(err);
+                        }
+                    };
+                    debug!(
+                        "Block {} (shift {}) is flipped at {:?}",
+                        job.target, job.shift, flipped
+                    );
+                    positions.extend(flipped.into_iter().filter(|i| job.keep.contains(i)).map(
+                        |i| ((job.target * params.n2 + i + params.n - job.shift) % params.n) as u32,
+                    ));
+                    queue.done.fetch_add(1, Ordering::Relaxed);
+                    blocks += 1;
+                }
+                if combinators.cache {
+                    debug!("{} queries were answered from the cache", oracle.hits());
+                }
+                Ok(Some((positions, blocks)))
+            },
+            |queue| {
+                let done = queue.done.load(Ordering::Relaxed);
+                pm.set_position(0, done as u64);
+                pm.tick();
+                done >= num_jobs
+                    || queue.failed.load(Ordering::Relaxed)
+                    || queue.exhausted.load(Ordering::Relaxed)
+            },
+        )?;
+    pm.stop();
+
+    let done: usize = recovered.iter().map(|(_, blocks)| blocks).sum();
+    if done < num_jobs {
+        warn!(
+            "Recovered {} of {} blocks, the candidate y is incomplete",
+            done, num_jobs
+        );
+    }
+    Ok(recovered
+        .into_iter()
+        .flat_map(|(positions, _)| positions)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect())
+}
+
+#[logfn_inputs(Trace)]
+pub fn run<HQC: 'static + Hqc + Send>(opt: HqcAttackOptions) -> Result<(), String> {
+    info!(
+        "Launching rejection sampling attack on {} secret key.",
+        HQC::NAME
+    );
+    opt.key_seed.apply();
+    accounting::reset();
+
+    if HQC::Plaintext::len() != HQC::PARAMS.k {
+        return Err(format!(
+            "The plaintext is {} bytes long, expected {} bytes according to the HQC-RMRS model",
+            HQC::Plaintext::len(),
+            HQC::PARAMS.k
+        ));
+    }
+
+    let (mut pk, mut sk) = if let Some(key_file) = &opt.reuse_key_pair {
+        read_keypair::<HQC>(key_file, true)?
+    } else {
+        get_keypair::<HQC>()?
+    };
+
+    // pt ← Plaintext such that decrypting to it is easily distinguishable by timing attack
+    info!("Searching for best candidate plaintext in database...");
+    let mut ptdb = PlaintextDb::<HQC>::new(&opt.plaintext_db, 0)?;
+    ptdb.verify_implementation(true)?;
+    let (rejections, mut pt) = ptdb
+        .get_min_max(true, RejectionCounter::Iterations)?
+        .ok_or_else(|| "No more plaintexts in db!".to_string())?;
+    info!(
+        "Found candidate with {} iterations in the rejection sampling!",
+        rejections.iterations
+    );
+    debug_assert_eq!(
+        HQC::split_rejections(HQC::num_rejections(&mut pt)?)?.iterations,
+        rejections.iterations
+    );
+
+    let y = match opt.oracle {
+        OracleKind::Ideal => {
+            let oracle =
+                generate_oracle::<HQC, HqcIdealizedOracle>(&opt, &mut pt, &mut pk, &mut sk)?;
+            attack::<HQC, _>(&opt, sk.clone(), pt, oracle)?
+        }
+        OracleKind::Timing => {
+            let oracle = generate_oracle::<HQC, HqcTimingOracle>(&opt, &mut pt, &mut pk, &mut sk)?;
+            attack::<HQC, _>(&opt, sk.clone(), pt, oracle)?
+        }
+        OracleKind::Bgf => return Err("The bgf oracle only simulates the BIKE decoder".to_string()),
+    };
+    info!("Recovered a candidate y of weight {}", y.len());
+    info!("Cost of the attack: {}", QueryCounts::snapshot());
+
+    match super::hqc::check_public_key::<HQC>(&pk, &y)? {
+        Some(_) => {
+            info!("The candidate y is consistent with the public key, the secret key is recovered!")
+        }
+        None => warn!("The candidate y is not consistent with the public key, the attack failed!"),
+    }
+
+    let secret = expand_secret_key::<HQC>(&sk)?;
+    let missing = secret.y.iter().filter(|i| !y.contains(i)).count();
+    let wrong = y.iter().filter(|i| !secret.y.contains(i)).count();
+    info!(
+        "Compared to the true secret key: {} positions of y were missed and {} were wrongly included",
+        missing, wrong
+    );
+    if missing + wrong == 0 {
+        info!(
+            "Final verdict: the secret key of {} was recovered!",
+            HQC::NAME
+        );
+    } else {
+        warn!(
+            "Final verdict: failed to recover the secret key of {}",
+            HQC::NAME
+        );
+    }
+
+    Ok(())
+}
+
+fn generate_oracle<HQC, ORACLE>(
+    opt: &HqcAttackOptions,
+    pt: &mut HQC::Plaintext,
+    pk: &mut HQC::PublicKey,
+    sk: &mut HQC::SecretKey,
+) -> Result<ORACLE, String>
+where
+    HQC: Hqc,
+    ORACLE: HqcOracle<HQC>,
+{
+    info!("Constructing a decryption oracle of type: {}", ORACLE::NAME);
+    loop {
+        if let Some(oracle) = ORACLE::generate(opt.num_profiling, opt.num_decaps, pt, pk, sk)? {
+            info!("Decryption oracle constructed!");
+            return Ok(oracle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use liboqs_rs_bindings::hqc::Hqc128;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::recover_block;
+    use crate::attack::rejection_sampling::hqc::{decodes_to, flip_bit, rs_encode, Hqc};
+
+    #[test]
+    fn test_recover_block() {
+        let params = Hqc128::PARAMS;
+        let mut rng = StdRng::seed_from_u64(3);
+        let msg: Vec<u8> = (0..params.k).map(|_| rng.gen()).collect();
+        let symbols = rs_encode(&params, &msg);
+
+        // Three flips in block 1, and a random y elsewhere
+        let mut y: Vec<usize> = vec![params.n2 + 7, params.n2 + 128, params.n2 + 300];
+        y.extend(
+            rand::seq::index::sample(&mut rng, params.n1n2(), params.omega - y.len())
+                .into_iter()
+                .filter(|&i| i / params.n2 != 1),
+        );
+
+        for &target in [0, 1, params.n1 - params.k, params.n1 - 1].iter() {
+            let recovered = recover_block(&params, &symbols, target, &mut rng, |v| {
+                let mut word = v.to_vec();
+                y.iter().for_each(|&i| flip_bit(&mut word, i));
+                Ok(decodes_to(&params, &word, &msg))
+            })
+            .unwrap();
+            let mut expected: Vec<_> = y
+                .iter()
+                .filter(|&&i| i / params.n2 == target)
+                .map(|&i| i % params.n2)
+                .collect();
+            expected.sort_unstable();
+            assert_eq!(recovered, expected, "block {}", target);
+        }
+    }
+}
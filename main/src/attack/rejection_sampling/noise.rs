@@ -0,0 +1,268 @@
+//! Noise models for simulating a measured distinguisher with an idealized oracle
+
+use std::{fs::File, io::BufReader, path::PathBuf};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+
+use crate::{
+    attack::rejection_sampling::eval_distinguisher::EvalDistinguisherResult, utils::StrErr,
+};
+
+/// The probabilities of the answers of an oracle deviating from the truth
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ErrorRates {
+    /// Of answering decoding failure when there is none
+    pub false_positive: f64,
+    /// Of answering no decoding failure when there is one
+    pub false_negative: f64,
+    /// Of not deciding at all
+    pub undecided: f64,
+}
+
+impl ErrorRates {
+    fn validate(&self) -> Result<(), String> {
+        for (name, p) in [
+            ("false positive", self.false_positive),
+            ("false negative", self.false_negative),
+            ("undecided", self.undecided),
+        ] {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(format!(
+                    "The {} rate must be between 0.0 and 1.0, got {}",
+                    name, p
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A second state with other error rates, e.g. while the machine is busy. The state changes
+/// before every query with the given probabilities, so bursts last 1 / leave queries on average.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BurstModel {
+    /// The probability of entering a burst
+    pub enter: f64,
+    /// The probability of leaving a burst
+    pub leave: f64,
+    pub rates: ErrorRates,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct NoiseModel {
+    pub rates: ErrorRates,
+    pub burst: Option<BurstModel>,
+}
+
+impl NoiseModel {
+    /// Flips every answer with probability `epsilon`
+    pub fn symmetric(epsilon: f64) -> Self {
+        NoiseModel {
+            rates: ErrorRates {
+                false_positive: epsilon,
+                false_negative: epsilon,
+                undecided: 0.0,
+            },
+            burst: None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.rates.validate()?;
+        if let Some(burst) = &self.burst {
+            burst.rates.validate()?;
+            if !(0.0..=1.0).contains(&burst.enter) || !(0.0..=1.0).contains(&burst.leave) {
+                return Err(format!(
+                    "The burst probabilities must be between 0.0 and 1.0, got {} and {}",
+                    burst.enter, burst.leave
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct NoiseOptions {
+    /// The probability of making a bad distinguisher decision, between 0.0 and 1.0.
+    /// Simulates a noisy distinguisher on top of the oracle, with equal false positive and false negative rates.
+    #[structopt(short, long, default_value("0.0"))]
+    pub epsilon: f64,
+    /// The probability of detecting a decoding failure when there is none, instead of epsilon
+    #[structopt(long)]
+    pub false_positive_rate: Option<f64>,
+    /// The probability of missing a decoding failure, instead of epsilon
+    #[structopt(long)]
+    pub false_negative_rate: Option<f64>,
+    /// The probability of the distinguisher not deciding
+    #[structopt(long, default_value("0.0"))]
+    pub undecided_rate: f64,
+    /// Take the error rates from a result file written by the "attack rejection-sampling eval-distinguisher" command,
+    /// instead of epsilon and the rates above
+    #[structopt(
+        long,
+        conflicts_with_all(&["epsilon", "false-positive-rate", "false-negative-rate", "undecided-rate"])
+    )]
+    pub noise_from: Option<PathBuf>,
+    /// The probability of a burst starting before a decision, during which the burst rates are used
+    #[structopt(long, default_value("0.0"))]
+    pub burst_enter: f64,
+    /// The probability of a burst ending before a decision
+    #[structopt(long, default_value("1.0"))]
+    pub burst_leave: f64,
+    /// The false positive rate during a burst
+    #[structopt(long, default_value("0.0"))]
+    pub burst_false_positive_rate: f64,
+    /// The false negative rate during a burst
+    #[structopt(long, default_value("0.0"))]
+    pub burst_false_negative_rate: f64,
+    /// The probability of the distinguisher not deciding during a burst
+    #[structopt(long, default_value("0.0"))]
+    pub burst_undecided_rate: f64,
+}
+
+impl NoiseOptions {
+    pub fn model(&self) -> Result<NoiseModel, String> {
+        let rates = match &self.noise_from {
+            Some(path) => {
+                info!("Reading distinguisher error rates from {:?}", path);
+                let reader = BufReader::new(File::open(path).strerr()?);
+                let result: EvalDistinguisherResult = serde_json::from_reader(reader).strerr()?;
+                result.rates
+            }
+            None => {
+                let mut rates = NoiseModel::symmetric(self.epsilon).rates;
+                rates.false_positive = self.false_positive_rate.unwrap_or(rates.false_positive);
+                rates.false_negative = self.false_negative_rate.unwrap_or(rates.false_negative);
+                rates.undecided = self.undecided_rate;
+                rates
+            }
+        };
+        let burst = if self.burst_enter > 0.0 {
+            Some(BurstModel {
+                enter: self.burst_enter,
+                leave: self.burst_leave,
+                rates: ErrorRates {
+                    false_positive: self.burst_false_positive_rate,
+                    false_negative: self.burst_false_negative_rate,
+                    undecided: self.burst_undecided_rate,
+                },
+            })
+        } else {
+            None
+        };
+        let model = NoiseModel { rates, burst };
+        model.validate()?;
+        info!("Simulating distinguisher noise: {:?}", model);
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use structopt::StructOpt;
+
+    use super::{BurstModel, ErrorRates, NoiseModel, NoiseOptions};
+    use crate::attack::rejection_sampling::eval_distinguisher::EvalDistinguisherResult;
+
+    fn model(args: &[&str]) -> Result<NoiseModel, String> {
+        NoiseOptions::from_iter_safe(std::iter::once("noise").chain(args.iter().copied()))
+            .map_err(|err| err.to_string())?
+            .model()
+    }
+
+    #[test]
+    fn test_noise_model() {
+        assert_eq!(model(&[]).unwrap(), NoiseModel::default());
+        assert_eq!(
+            model(&["--epsilon", "0.1"]).unwrap(),
+            NoiseModel::symmetric(0.1)
+        );
+        // The explicit rates override epsilon
+        let asymmetric = model(&[
+            "--epsilon",
+            "0.1",
+            "--false-positive-rate",
+            "0.3",
+            "--undecided-rate",
+            "0.2",
+        ])
+        .unwrap();
+        assert_eq!(
+            asymmetric.rates,
+            ErrorRates {
+                false_positive: 0.3,
+                false_negative: 0.1,
+                undecided: 0.2,
+            }
+        );
+        assert_eq!(asymmetric.burst, None);
+
+        let bursty = model(&[
+            "--burst-enter",
+            "0.01",
+            "--burst-false-negative-rate",
+            "0.5",
+        ]);
+        assert_eq!(
+            bursty.unwrap().burst,
+            Some(BurstModel {
+                enter: 0.01,
+                leave: 1.0,
+                rates: ErrorRates {
+                    false_positive: 0.0,
+                    false_negative: 0.5,
+                    undecided: 0.0,
+                },
+            })
+        );
+
+        assert!(model(&["--epsilon", "1.5"]).is_err());
+        assert!(model(&["--false-negative-rate", "-0.1"]).is_err());
+        assert!(model(&["--burst-enter", "0.1", "--burst-leave", "2"]).is_err());
+        assert!(model(&["--burst-enter", "0.1", "--burst-undecided-rate", "1.1"]).is_err());
+    }
+
+    #[test]
+    fn test_noise_from() {
+        let rates = ErrorRates {
+            false_positive: 0.02,
+            false_negative: 0.25,
+            undecided: 0.125,
+        };
+        let result = EvalDistinguisherResult {
+            algorithm: "BikeL1".to_string(),
+            num_decaps: 100,
+            trials: 1000,
+            num_decoding_failures: 400,
+            num_false_positives: 12,
+            num_false_negatives: 100,
+            num_undecided: 125,
+            rates,
+        };
+        let path = std::env::temp_dir().join(format!(
+            "oqs-afw-test-noise-from-{}.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, serde_json::to_string(&result).unwrap()).unwrap();
+        let path = path.to_str().unwrap();
+
+        let measured = model(&["--noise-from", path]);
+        // The measured rates cannot be combined with epsilon or the explicit rates
+        let conflicting = [
+            model(&["--noise-from", path, "--epsilon", "0.1"]),
+            model(&["--noise-from", path, "--false-positive-rate", "0.5"]),
+            model(&["--noise-from", path, "--false-negative-rate", "0.5"]),
+            model(&["--noise-from", path, "--undecided-rate", "0.5"]),
+        ];
+        let missing = model(&["--noise-from", "/nonexistent/eval-distinguisher.json"]);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(measured.unwrap().rates, rates);
+        for result in conflicting.iter() {
+            assert!(result.is_err());
+        }
+        assert!(missing.is_err());
+    }
+}
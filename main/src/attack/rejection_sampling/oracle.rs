@@ -0,0 +1,783 @@
+use std::{marker::PhantomData, ops::Range, path::Path, str::FromStr};
+
+use liboqs_rs_bindings as oqs;
+use log::{debug, info, warn};
+use oqs::{
+    bike::{Bike, BikeL1, BikeL3, BikeL5},
+    hqc::{Hqc128, Hqc192, Hqc256},
+    KemBuf, KemWithRejectionSampling,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    attack::rejection_sampling::{
+        accounting::{self, Operation},
+        attack::record_decaps_to,
+        bike_error_weight::BikeIdealizedOracle,
+        experiment::root_seed,
+        hqc::{add_error_to_v, Hqc},
+        hqc_attack::HqcIdealizedOracle,
+        noise::NoiseModel,
+        plaintexts::PlaintextDb,
+        rejections::{RejectionCounter, RejectionSamplingStats},
+    },
+    utils::{pb_add, ClonableProgressManager, FrameworkRng, ProgressBars, Rec, Recorder, RngSeed},
+};
+
+/// The positions of the set bits of an error, for BIKE in the concatenation of the blocks e0 and e1
+pub type SparseErrorPattern = Vec<u32>;
+
+/// How the weight of a BIKE error pattern is split between the blocks e0 and e1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorSplit {
+    /// A fixed number of positions in e0, the rest in e1
+    E0Weight(usize),
+    /// Half of the positions in e0, rounded down, the rest in e1
+    Balanced,
+    /// Uniformly over both blocks, so the weight in e0 varies and mutations can move positions between the blocks
+    Uniform,
+}
+
+impl Default for ErrorSplit {
+    /// The entire weight in e1, as in the original attack
+    fn default() -> Self {
+        ErrorSplit::E0Weight(0)
+    }
+}
+
+impl FromStr for ErrorSplit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "e1" => Ok(ErrorSplit::E0Weight(0)),
+            "balanced" => Ok(ErrorSplit::Balanced),
+            "uniform" => Ok(ErrorSplit::Uniform),
+            _ => s.parse().map(ErrorSplit::E0Weight).map_err(|_| {
+                format!(
+                    "Could not parse {} into either e1, balanced, uniform or the weight in e0.",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+impl ErrorSplit {
+    /// The range a position replacing `del` in a mutation is drawn from, for blocks of length `r`
+    pub fn mutation_range(&self, r: usize, del: u32) -> Range<usize> {
+        match self {
+            ErrorSplit::Uniform => 0..2 * r,
+            _ => {
+                let start = del as usize / r * r;
+                start..start + r
+            }
+        }
+    }
+
+    /// The range a position is added to or removed from when the weight of `error_pattern` grows or
+    /// shrinks by one, for blocks of length `r`, so the split is kept
+    pub fn resize_range(&self, r: usize, error_pattern: &[u32], grow: bool) -> Range<usize> {
+        let e0_weight = error_pattern.iter().filter(|&&p| (p as usize) < r).count();
+        let new_weight = if grow {
+            error_pattern.len() + 1
+        } else {
+            error_pattern.len() - 1
+        };
+        let in_e0 = match self {
+            ErrorSplit::E0Weight(_) => false,
+            ErrorSplit::Balanced if grow => e0_weight < new_weight / 2,
+            ErrorSplit::Balanced => e0_weight > new_weight / 2,
+            ErrorSplit::Uniform => return 0..2 * r,
+        };
+        if in_e0 {
+            0..r
+        } else {
+            r..2 * r
+        }
+    }
+}
+
+/// The decoding failure oracles an attack can be run with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OracleKind {
+    /// Decides from the decoder intermediaries, i.e. with perfect knowledge
+    Ideal,
+    /// Decides from the decapsulation time
+    Timing,
+    /// Decides with a native simulation of the BIKE decoder, without decapsulations
+    Bgf,
+}
+
+impl FromStr for OracleKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ideal" => Ok(OracleKind::Ideal),
+            "timing" => Ok(OracleKind::Timing),
+            "bgf" => Ok(OracleKind::Bgf),
+            _ => Err(format!(
+                "Could not parse {} into either ideal, timing or bgf.",
+                s
+            )),
+        }
+    }
+}
+
+pub trait DecodingFailureOracle<KEM: KemWithRejectionSampling>:
+    std::fmt::Debug + Clone + Send
+{
+    const NAME: &'static str;
+    fn generate(
+        num_profiling: i32,
+        num_decaps: i32,
+        delta_iter: u32,
+        pt: &mut KEM::Plaintext,
+        pt_delta: &mut KEM::Plaintext,
+        pk: &mut KEM::PublicKey,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<Self>, String>
+    where
+        Self: Sized;
+
+    fn decoding_failure(
+        &mut self,
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<bool>, String>;
+
+    /// Whether [`Self::error_pattern_decoding_failure`] needs the ciphertext, oracles that decide
+    /// from the error pattern alone spare the caller the encapsulation
+    fn needs_ciphertext(&self) -> bool {
+        true
+    }
+
+    /// Whether the ciphertext carrying the error pattern `e` causes a decoding failure. `ct` is
+    /// only encapsulated with `e` if [`Self::needs_ciphertext`].
+    fn error_pattern_decoding_failure(
+        &mut self,
+        _e: &[u32],
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<bool>, String> {
+        self.decoding_failure(ct, sk)
+    }
+
+    /// Like [`Self::error_pattern_decoding_failure`], with a weight for the answer: how clearly the
+    /// oracle decided it. Oracles that do not grade their answers weigh all of them 1.
+    fn weighted_decoding_failure(
+        &mut self,
+        e: &[u32],
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<(bool, u32)>, String> {
+        Ok(self
+            .error_pattern_decoding_failure(e, ct, sk)?
+            .map(|answer| (answer, 1)))
+    }
+}
+
+/// KEMs with a ground truth decoding failure oracle, for which ciphertexts carrying an additional
+/// sparse error pattern can be created
+pub trait DecodingFailureKem: RejectionSamplingStats + Send + 'static {
+    /// Decides decoding failures from the intermediaries of the decoder
+    type IdealizedOracle: DecodingFailureOracle<Self>;
+
+    /// The positions an error pattern can be placed at
+    fn error_range() -> Range<usize>;
+
+    /// Encapsulates `pt`, with the error pattern `e` added to what the decoder sees
+    fn encaps_with_error_pattern(
+        ct: &mut Self::Ciphertext,
+        ss: &mut Self::SharedSecret,
+        pk: &mut Self::PublicKey,
+        pt: &Self::Plaintext,
+        e: &[u32],
+    ) -> Result<(), String>;
+}
+
+macro_rules! bike_decoding_failures {
+    ($($bike:ident),*) => {
+        $(
+            impl DecodingFailureKem for $bike {
+                type IdealizedOracle = BikeIdealizedOracle<$bike>;
+
+                fn error_range() -> Range<usize> {
+                    let params = Self::params();
+                    params.PARAM_R..params.PARAM_N
+                }
+
+                fn encaps_with_error_pattern(
+                    ct: &mut Self::Ciphertext,
+                    ss: &mut Self::SharedSecret,
+                    pk: &mut Self::PublicKey,
+                    pt: &Self::Plaintext,
+                    e: &[u32],
+                ) -> Result<(), String> {
+                    Self::encaps_with_plaintext_and_error_pattern(ct, ss, pk, pt, e)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! hqc_decoding_failures {
+    ($($hqc:ident),*) => {
+        $(
+            impl DecodingFailureKem for $hqc {
+                type IdealizedOracle = HqcIdealizedOracle;
+
+                fn error_range() -> Range<usize> {
+                    0..Self::PARAMS.n1n2()
+                }
+
+                fn encaps_with_error_pattern(
+                    ct: &mut Self::Ciphertext,
+                    ss: &mut Self::SharedSecret,
+                    pk: &mut Self::PublicKey,
+                    pt: &Self::Plaintext,
+                    e: &[u32],
+                ) -> Result<(), String> {
+                    Self::encaps_with_plaintext(ct, ss, pk, &mut pt.clone())?;
+                    add_error_to_v::<Self>(ct, e)
+                }
+            }
+        )*
+    };
+}
+
+bike_decoding_failures!(BikeL1, BikeL3, BikeL5);
+hqc_decoding_failures!(Hqc128, Hqc192, Hqc256);
+
+/// Decides decoding failures from the decapsulation time. A decoding failure re-encrypts another
+/// plaintext than the selected one, which takes more rejections in the sampling and is slower.
+#[derive(Debug, Clone)]
+pub struct TimingOracle<KEM>
+where
+    KEM: KemWithRejectionSampling + Send,
+{
+    _kem: PhantomData<KEM>,
+    num_decaps: i32,
+    threshold: u64,
+}
+
+impl<KEM> DecodingFailureOracle<KEM> for TimingOracle<KEM>
+where
+    KEM: KemWithRejectionSampling + Send,
+{
+    const NAME: &'static str = "TIMING";
+
+    fn generate(
+        num_profiling: i32,
+        num_decaps: i32,
+        _num_validate: u32,
+        pt: &mut KEM::Plaintext,
+        pt_delta: &mut KEM::Plaintext,
+        pk: &mut KEM::PublicKey,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<Self>, String>
+    where
+        Self: Sized,
+    {
+        let pm = ClonableProgressManager::<u8>::create();
+        let total_length = num_profiling * 2; //TODO calculate this value
+        let mut total_pos: u64 = 0;
+
+        pm.add(
+            0,
+            total_length as u64,
+            "Gen. timing profile".to_string(),
+            " {msg:30} {wide_bar} ETA [{eta_precise}]",
+        );
+        pm.start([]);
+
+        info!(
+            "Starting {} decapsulations of primary plaintext to detect upper timing bound",
+            num_profiling
+        );
+
+        let mut ct = KEM::Ciphertext::new();
+        let mut ss = KEM::SharedSecret::new();
+
+        KEM::encaps_with_plaintext(&mut ct, &mut ss, pk, pt)?;
+        accounting::count(Operation::Encaps, 1);
+        let mut rec_first_bound = Recorder::saveall("First bound", None);
+        let prof_steps = num_profiling / 10;
+        for _ in 0..10 {
+            rec_first_bound = record_decaps_to::<KEM, _>(rec_first_bound, &mut ct, sk, prof_steps)?;
+            pb_add!(total_pos = pm[0].add(prof_steps as u64));
+        }
+
+        let first_bound =
+            if let Some(val) = rec_first_bound.nth_lowest_value((num_profiling / 100) as u64) {
+                val
+            } else {
+                warn!("No recordings!");
+                return Ok(None);
+            };
+
+        info!(
+            "1% lowest measurment of selected plaintext: {}",
+            first_bound
+        );
+
+        KEM::encaps_with_plaintext(&mut ct, &mut ss, pk, pt_delta)?;
+        accounting::count(Operation::Encaps, 1);
+        let mut rec_delta_bound = Recorder::saveall("Second bound", None);
+        for _ in 0..10 {
+            rec_delta_bound = record_decaps_to::<KEM, _>(rec_delta_bound, &mut ct, sk, prof_steps)?;
+            pb_add!(total_pos = pm[0].add(prof_steps as u64));
+        }
+
+        let delta_bound =
+            if let Some(val) = rec_delta_bound.nth_lowest_value((num_profiling / 100) as u64) {
+                val
+            } else {
+                warn!("No recordings!");
+                return Ok(None);
+            };
+
+        info!("1% lowest mesurment of delta plaintext: {}", delta_bound);
+
+        if delta_bound <= first_bound {
+            warn!("lower bound is higher than the upper bound!");
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            _kem: PhantomData,
+            num_decaps,
+            threshold: delta_bound,
+        }))
+    }
+
+    fn decoding_failure(
+        &mut self,
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<bool>, String> {
+        let recorder =
+            record_decaps_to::<KEM, _>(Recorder::saveall("_", None), ct, sk, self.num_decaps)?;
+        if let Some(val) = recorder.nth_lowest_value((self.num_decaps / 100) as u64) {
+            Ok(Some(val >= self.threshold))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Simulates a measured distinguisher by adding noise to the answers of another oracle
+#[derive(Debug, Clone)]
+pub struct NoisyOracle<INNER, KEM>
+where
+    INNER: DecodingFailureOracle<KEM>,
+    KEM: KemWithRejectionSampling + Send,
+{
+    inner: INNER,
+    pub noise: NoiseModel,
+    in_burst: bool,
+    since_inversion: u32,
+    rng: FrameworkRng,
+    _kem: PhantomData<KEM>,
+}
+
+impl<INNER, KEM> NoisyOracle<INNER, KEM>
+where
+    INNER: DecodingFailureOracle<KEM>,
+    KEM: KemWithRejectionSampling + Send,
+{
+    pub fn wrap(inner: INNER, noise: NoiseModel) -> Self {
+        NoisyOracle {
+            inner,
+            noise,
+            in_burst: false,
+            since_inversion: 0,
+            rng: root_seed().purpose("epsilon").rng(),
+            _kem: PhantomData,
+        }
+    }
+
+    /// Gives this copy of the oracle its own stream of random inversions
+    pub fn reseed(&mut self, seed: RngSeed) {
+        self.rng = seed.rng();
+    }
+
+    fn gen_bool(&mut self, p: f64) -> bool {
+        // Skipped when impossible, so noise free oracles draw no random numbers
+        p > 0.0 && self.rng.gen_bool(p)
+    }
+}
+
+impl<INNER, KEM> DecodingFailureOracle<KEM> for NoisyOracle<INNER, KEM>
+where
+    INNER: DecodingFailureOracle<KEM>,
+    KEM: KemWithRejectionSampling + Send,
+{
+    const NAME: &'static str = INNER::NAME;
+
+    fn generate(
+        num_profiling: i32,
+        num_decaps: i32,
+        num_validate: u32,
+        pt: &mut KEM::Plaintext,
+        pt_delta: &mut KEM::Plaintext,
+        pk: &mut KEM::PublicKey,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<Self>, String>
+    where
+        Self: Sized,
+    {
+        if let Some(inner) = INNER::generate(
+            num_profiling,
+            num_decaps,
+            num_validate,
+            pt,
+            pt_delta,
+            pk,
+            sk,
+        )? {
+            Ok(Some(NoisyOracle::wrap(inner, NoiseModel::default())))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn decoding_failure(
+        &mut self,
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<bool>, String> {
+        // The queries of the wrapped oracle are the ones that are made
+        let answer = accounting::query(|| self.inner.decoding_failure(ct, sk))?;
+        Ok(self.add_noise(answer))
+    }
+
+    fn needs_ciphertext(&self) -> bool {
+        self.inner.needs_ciphertext()
+    }
+
+    fn error_pattern_decoding_failure(
+        &mut self,
+        e: &[u32],
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<bool>, String> {
+        let answer = accounting::query(|| self.inner.error_pattern_decoding_failure(e, ct, sk))?;
+        Ok(self.add_noise(answer))
+    }
+
+    /// Keeps the weight of the answers the noise leaves alone
+    fn weighted_decoding_failure(
+        &mut self,
+        e: &[u32],
+        ct: &mut KEM::Ciphertext,
+        sk: &mut KEM::SecretKey,
+    ) -> Result<Option<(bool, u32)>, String> {
+        let weighted = accounting::query(|| self.inner.weighted_decoding_failure(e, ct, sk))?;
+        let answer = match self.add_noise(weighted.map(|(answer, _)| answer)) {
+            Some(answer) => answer,
+            None => return Ok(None),
+        };
+        Ok(Some(match weighted {
+            Some((inner, weight)) if inner == answer => (answer, weight),
+            _ => (answer, 1),
+        }))
+    }
+}
+
+impl<INNER, KEM> NoisyOracle<INNER, KEM>
+where
+    INNER: DecodingFailureOracle<KEM>,
+    KEM: KemWithRejectionSampling + Send,
+{
+    /// Applies the noise model to an answer of the wrapped oracle
+    fn add_noise(&mut self, answer: Option<bool>) -> Option<bool> {
+        let res = answer?;
+
+        let rates = match self.noise.burst {
+            Some(burst) => {
+                let change = if self.in_burst {
+                    burst.leave
+                } else {
+                    burst.enter
+                };
+                if self.gen_bool(change) {
+                    self.in_burst = !self.in_burst;
+                    debug!("In a burst of noise: {}", self.in_burst);
+                }
+                if self.in_burst {
+                    burst.rates
+                } else {
+                    self.noise.rates
+                }
+            }
+            None => self.noise.rates,
+        };
+
+        if self.gen_bool(rates.undecided) {
+            return None;
+        }
+        self.since_inversion += 1;
+        let invert = if res {
+            self.gen_bool(rates.false_negative)
+        } else {
+            self.gen_bool(rates.false_positive)
+        };
+        if invert {
+            debug!(
+                "Due to the noise model we inverted the detected decoding failure: {} to decoding failure: {} ({} since last inversion)",
+                res,
+                !res,
+                self.since_inversion
+            );
+            self.since_inversion = 0;
+        }
+        Some(res ^ invert)
+    }
+}
+
+pub fn random_sparse_error_pattern(
+    range: Range<usize>,
+    amount: usize,
+    rng: &mut impl Rng,
+) -> Result<SparseErrorPattern, String> {
+    let length = range.end - range.start;
+    let shift = range.start;
+
+    // e_0
+    let indexes = rand::seq::index::sample(rng, length, amount);
+
+    // Shift indices to range
+    Ok(indexes
+        .into_vec()
+        .into_iter()
+        .map(|i| (i + shift) as u32)
+        .collect())
+}
+
+/// A random BIKE error pattern of weight `amount`, split between the blocks e0 and e1 of length `r`
+pub fn random_split_error_pattern(
+    r: usize,
+    split: ErrorSplit,
+    amount: usize,
+    rng: &mut impl Rng,
+) -> Result<SparseErrorPattern, String> {
+    let e0_weight = match split {
+        ErrorSplit::E0Weight(weight) => weight,
+        ErrorSplit::Balanced => amount / 2,
+        ErrorSplit::Uniform => return random_sparse_error_pattern(0..2 * r, amount, rng),
+    };
+    if e0_weight > amount || e0_weight > r || amount - e0_weight > r {
+        return Err(format!(
+            "Can not place {} of {} positions in e0 with blocks of length {}",
+            e0_weight, amount, r
+        ));
+    }
+
+    let mut error_pattern = random_sparse_error_pattern(0..r, e0_weight, rng)?;
+    error_pattern.extend(random_sparse_error_pattern(
+        r..2 * r,
+        amount - e0_weight,
+        rng,
+    )?);
+    Ok(error_pattern)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn construct_decoding_failure_oracle<KEM, ORACLE>(
+    db_location: &Path,
+    num_profiling: i32,
+    num_decaps: i32,
+    delta_iter: u32,
+    delta_by: RejectionCounter,
+    pk: &mut KEM::PublicKey,
+    sk: &mut KEM::SecretKey,
+) -> Result<(ORACLE, KEM::Plaintext), String>
+where
+    KEM: RejectionSamplingStats,
+    ORACLE: DecodingFailureOracle<KEM>,
+{
+    if delta_iter == 0 {
+        return Err(format!("Delta iter ({}) must be grater than 0", delta_iter));
+    }
+    info!(
+        "Constructing a decoding failure oracle of type: {}",
+        ORACLE::NAME
+    );
+    info!("Searching for best candidate plaintext in database...");
+    let mut ptdb = PlaintextDb::<KEM>::new(db_location, 0)?;
+    ptdb.verify_implementation(true)?;
+    let (min, mut min_pt) = ptdb
+        .get_min_max(false, delta_by)?
+        .ok_or_else(|| "No more plaintexts in db!".to_string())?;
+    let min = delta_by.get(&min);
+    info!(
+        "Found candidate with {} {} in the rejection sampling!",
+        min, delta_by
+    );
+
+    let mut selected_iter = min + delta_iter;
+    let oracle = loop {
+        let mut higher = 0;
+        let mut pt_delta = loop {
+            if let Some((rejections, pt_delta)) =
+                ptdb.get_next_iter(delta_by, selected_iter + higher, false)?
+            {
+                let iter = delta_by.get(&rejections);
+                info!(
+                    "Attempting to construct a oracle with granularity of {} {} (using a delta plaintext with {} {})!",
+                    iter - min,
+                    delta_by,
+                    iter,
+                    delta_by
+                );
+                selected_iter = iter;
+                break pt_delta;
+            }
+            higher += 1;
+        };
+
+        if let Some(oracle) = ORACLE::generate(
+            num_profiling,
+            num_decaps,
+            delta_iter,
+            &mut min_pt,
+            &mut pt_delta,
+            pk,
+            sk,
+        )? {
+            break oracle;
+        }
+    };
+    info!("decoding failure oracle constructed!");
+    Ok((oracle, min_pt))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use liboqs_rs_bindings::{bike::BikeL1, Kem, KemBuf, KemWithRejectionSampling};
+
+    use super::{DecodingFailureOracle, NoisyOracle};
+    use crate::attack::rejection_sampling::noise::{BurstModel, ErrorRates, NoiseModel};
+
+    /// Answers with a script, repeated from the start when it runs out, and counts the queries of
+    /// all its copies
+    #[derive(Debug, Clone)]
+    pub(crate) struct ScriptedOracle {
+        script: Vec<Option<bool>>,
+        queries: Arc<AtomicUsize>,
+    }
+
+    impl ScriptedOracle {
+        pub(crate) fn new(script: &[Option<bool>]) -> Self {
+            ScriptedOracle {
+                script: script.to_vec(),
+                queries: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+
+        pub(crate) fn queries(&self) -> usize {
+            self.queries.load(Ordering::Relaxed)
+        }
+    }
+
+    impl<KEM: KemWithRejectionSampling> DecodingFailureOracle<KEM> for ScriptedOracle {
+        const NAME: &'static str = "SCRIPTED";
+
+        fn generate(
+            _num_profiling: i32,
+            _num_decaps: i32,
+            _delta_iter: u32,
+            _pt: &mut KEM::Plaintext,
+            _pt_delta: &mut KEM::Plaintext,
+            _pk: &mut KEM::PublicKey,
+            _sk: &mut KEM::SecretKey,
+        ) -> Result<Option<Self>, String> {
+            Err("A scripted oracle is created with ScriptedOracle::new".to_string())
+        }
+
+        fn decoding_failure(
+            &mut self,
+            _ct: &mut KEM::Ciphertext,
+            _sk: &mut KEM::SecretKey,
+        ) -> Result<Option<bool>, String> {
+            let query = self.queries.fetch_add(1, Ordering::Relaxed);
+            Ok(self.script[query % self.script.len()])
+        }
+    }
+
+    /// The answers of `oracle` to `n` queries
+    pub(crate) fn answers<ORACLE: DecodingFailureOracle<BikeL1>>(
+        oracle: &mut ORACLE,
+        n: usize,
+    ) -> Vec<Option<bool>> {
+        let mut ct = <BikeL1 as Kem>::Ciphertext::new();
+        let mut sk = <BikeL1 as Kem>::SecretKey::new();
+        (0..n)
+            .map(|_| oracle.decoding_failure(&mut ct, &mut sk).unwrap())
+            .collect()
+    }
+
+    fn noisy(
+        script: &[Option<bool>],
+        rates: ErrorRates,
+        burst: Option<BurstModel>,
+    ) -> Vec<Option<bool>> {
+        let inner = ScriptedOracle::new(script);
+        let mut oracle = NoisyOracle::<_, BikeL1>::wrap(inner.clone(), NoiseModel { rates, burst });
+        let answers = answers(&mut oracle, script.len());
+        // Every query is passed on exactly once
+        assert_eq!(inner.queries(), script.len());
+        answers
+    }
+
+    #[test]
+    fn test_noisy_oracle() {
+        let script = [Some(false), Some(true), None, Some(false)];
+        let rates = |false_positive, false_negative, undecided| ErrorRates {
+            false_positive,
+            false_negative,
+            undecided,
+        };
+
+        assert_eq!(noisy(&script, rates(0.0, 0.0, 0.0), None), script);
+        // Only the answers without a decoding failure are inverted, and the other way around
+        assert_eq!(
+            noisy(&script, rates(1.0, 0.0, 0.0), None),
+            [Some(true), Some(true), None, Some(true)]
+        );
+        assert_eq!(
+            noisy(&script, rates(0.0, 1.0, 0.0), None),
+            [Some(false), Some(false), None, Some(false)]
+        );
+        assert_eq!(noisy(&script, rates(1.0, 1.0, 1.0), None), [None; 4]);
+
+        // Entering a burst before the first query and never leaving it
+        let burst = |enter, leave| BurstModel {
+            enter,
+            leave,
+            rates: rates(1.0, 0.0, 0.0),
+        };
+        assert_eq!(
+            noisy(&script, rates(0.0, 0.0, 0.0), Some(burst(1.0, 0.0))),
+            [Some(true), Some(true), None, Some(true)]
+        );
+        // Alternating between a burst and none, the inner undecided answer does not change the state
+        let alternating = [Some(false), Some(false), None, Some(false), Some(false)];
+        assert_eq!(
+            noisy(&alternating, rates(0.0, 0.0, 0.0), Some(burst(1.0, 1.0))),
+            [Some(true), Some(false), None, Some(true), Some(false)]
+        );
+        assert_eq!(
+            noisy(&script, rates(0.0, 0.0, 0.0), Some(burst(0.0, 1.0))),
+            script
+        );
+    }
+}
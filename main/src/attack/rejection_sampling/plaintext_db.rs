@@ -0,0 +1,790 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use libflate::finish::AutoFinishUnchecked;
+use liboqs_rs_bindings as oqs;
+use log::{info, warn};
+use log_derive::logfn_inputs;
+use oqs::{
+    bike::{BikeL1, BikeL3, BikeL5},
+    hqc::{Hqc128, Hqc192, Hqc256},
+    Kem, KemBuf,
+};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+
+use crate::{
+    attack::rejection_sampling::{
+        plaintexts::{PlaintextDb, PlaintextDbMetadata},
+        rejections::{RejectionSamplingStats, Rejections},
+    },
+    utils::StrErr,
+};
+
+#[derive(Debug, StructOpt)]
+pub struct MergePlaintextDbOptions {
+    /// Path to the sqlite db to merge the plaintexts into, created if it does not exist
+    #[structopt(short, long)]
+    destination: PathBuf,
+
+    /// Paths to the sqlite dbs to merge from, which are not modified. Sources that were already merged into the
+    /// destination are refused, since their encountered counts would be added twice.
+    #[structopt(short, long, required(true))]
+    sources: Vec<PathBuf>,
+
+    /// Maximum number of plaintexts to store, per iteration count
+    #[structopt(short("l"), long("limit"), default_value("1000"))]
+    pt_limit_per_iter: u32,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ExportPlaintextDbOptions {
+    /// Path to plaintext database, which is not modified
+    #[structopt(short, long)]
+    db: PathBuf,
+
+    /// Path to save the exported plaintexts (GZIP compressed), e.g. plaintexts.bin.gz
+    #[structopt(short("t"), long)]
+    destination: PathBuf,
+
+    /// Only export plaintexts with the specified iteration counts
+    #[structopt(short, long)]
+    include: Vec<u32>,
+
+    /// Maximum number of plaintexts to export, per iteration count
+    #[structopt(short("l"), long("limit"))]
+    pt_limit_per_iter: Option<u32>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ImportPlaintextDbOptions {
+    /// Path to a file created by the export command
+    #[structopt(short, long)]
+    source: PathBuf,
+
+    /// Path to the sqlite db to import the plaintexts into, created if it does not exist
+    #[structopt(short, long)]
+    destination: PathBuf,
+
+    /// Maximum number of plaintexts to store, per iteration count
+    #[structopt(short("l"), long("limit"), default_value("1000"))]
+    pt_limit_per_iter: u32,
+}
+
+/// A portable selection of plaintexts from a plaintext database
+#[derive(Debug, Serialize, Deserialize)]
+struct PlaintextExport {
+    kem: String,
+    plaintext_len: usize,
+    liboqs_fingerprint: Option<String>,
+    plaintexts: Vec<(Rejections, Vec<u8>)>,
+}
+
+/// Plaintexts collected by different liboqs implementations can not be combined, since their
+/// number of rejections is not comparable.
+fn check_compatible(
+    destination: &PlaintextDbMetadata,
+    plaintext_len: Option<usize>,
+    liboqs_fingerprint: Option<&str>,
+) -> Result<(), String> {
+    if let (Some(expected), Some(found)) = (destination.plaintext_len, plaintext_len) {
+        if expected != found {
+            return Err(format!(
+                "Plaintext length is {} in the destination but {} in the source",
+                expected, found
+            ));
+        }
+    }
+    match (destination.liboqs_fingerprint.as_deref(), liboqs_fingerprint) {
+        (Some(expected), Some(found)) if expected != found => Err(format!(
+            "Plaintexts were collected with a different liboqs implementation, fingerprint is {} in the destination but {} in the source",
+            expected, found
+        )),
+        (Some(_), Some(_)) => Ok(()),
+        _ => {
+            warn!("Can not verify that the plaintexts were collected with the same liboqs implementation, as a fingerprint is missing");
+            Ok(())
+        }
+    }
+}
+
+/// A temporary copy of a database, removed when dropped
+struct TemporaryCopy(PathBuf);
+
+impl Drop for TemporaryCopy {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.0) {
+            warn!("Could not remove temporary copy {:?}: {}", self.0, err);
+        }
+    }
+}
+
+/// Opens the source database at `path` without modifying it. A source with an older schema
+/// version is migrated in a temporary copy, which has to be kept until the database is closed.
+fn open_source<KEM: RejectionSamplingStats>(
+    path: &Path,
+    index: usize,
+) -> Result<(PlaintextDb<KEM>, Option<TemporaryCopy>), String> {
+    if let Some(ptdb) = PlaintextDb::open_read_only(path)? {
+        return Ok((ptdb, None));
+    }
+    let copy = TemporaryCopy(std::env::temp_dir().join(format!(
+        "oqs-afw-source-{}-{}.db",
+        std::process::id(),
+        index
+    )));
+    info!(
+        "{:?} has an older schema version, migrating a temporary copy at {:?}",
+        path, copy.0
+    );
+    std::fs::copy(path, &copy.0).strerr()?;
+    let ptdb = PlaintextDb::new(&copy.0, 0)?;
+    Ok((ptdb, Some(copy)))
+}
+
+/// All plaintexts stored in the database, used to skip duplicates
+fn stored_plaintexts<KEM: RejectionSamplingStats>(
+    ptdb: &mut PlaintextDb<KEM>,
+) -> Result<HashSet<Vec<u8>>, String> {
+    let mut stored = HashSet::new();
+    let mut previous_id = None;
+    while let Some((id, _, pt)) = ptdb.get_next(previous_id)? {
+        previous_id = Some(id);
+        stored.insert(pt.as_slice().to_vec());
+    }
+    Ok(stored)
+}
+
+#[logfn_inputs(Trace)]
+pub fn run_merge<KEM: RejectionSamplingStats>(opt: MergePlaintextDbOptions) -> Result<(), String> {
+    info!(
+        "Opening or creating plaintext database at: {:?}",
+        opt.destination
+    );
+    let mut destination = PlaintextDb::<KEM>::new(&opt.destination, opt.pt_limit_per_iter)?;
+    destination.aggregate_iter_counts()?;
+    let metadata = destination.metadata()?;
+    let mut stored = stored_plaintexts(&mut destination)?;
+
+    for (index, path) in opt.sources.iter().enumerate() {
+        info!("Merging {} plaintexts from {:?}", KEM::NAME, path);
+        let (mut source, _copy) = open_source::<KEM>(path, index)?;
+        let source_metadata = source.metadata()?;
+        check_compatible(
+            &metadata,
+            source_metadata.plaintext_len,
+            source_metadata.liboqs_fingerprint.as_deref(),
+        )?;
+        // The plaintexts of a repeated merge are skipped as duplicates, but the encountered
+        // counts cannot be deduplicated and would be added twice
+        let mut merged_sources = source_metadata.merged_sources;
+        merged_sources.push(source.content_fingerprint()?);
+        let already_merged = destination.metadata()?.merged_sources;
+        if let Some(fingerprint) = merged_sources
+            .iter()
+            .find(|fingerprint| already_merged.contains(fingerprint))
+        {
+            return Err(format!(
+                "The contents of {:?} (fingerprint {}) were already merged into {:?}, merging them again would count their plaintexts twice",
+                path, fingerprint, opt.destination
+            ));
+        }
+
+        let (mut added, mut duplicates, mut over_limit) = (0, 0, 0);
+        let mut previous_id = None;
+        while let Some((id, rejections, pt)) = source.get_next(previous_id)? {
+            previous_id = Some(id);
+            if !stored.insert(pt.as_slice().to_vec()) {
+                duplicates += 1;
+            } else if destination.insert_pt(&pt, rejections)? {
+                added += 1;
+            } else {
+                over_limit += 1;
+            }
+        }
+        for (rejections, count) in source.encountered_counts()? {
+            destination.add_iter_count(rejections, count)?;
+        }
+        destination.add_merged_sources(&merged_sources)?;
+        info!(
+            "Added {} plaintexts from {:?}, skipped {} duplicates and {} above the limit",
+            added, path, duplicates, over_limit
+        );
+    }
+
+    for (rejections, count, stored) in destination.iter_counts()? {
+        info!(
+            "Merged database has {} out of {} encountered plaintexts with {} iterations ({} seedexpanders)",
+            stored, count, rejections.iterations, rejections.seedexpanders
+        );
+    }
+
+    Ok(())
+}
+
+#[logfn_inputs(Trace)]
+pub fn run_export<KEM: RejectionSamplingStats>(
+    opt: ExportPlaintextDbOptions,
+) -> Result<(), String> {
+    let (mut ptdb, _copy) = open_source::<KEM>(&opt.db, 0)?;
+    let metadata = ptdb.metadata()?;
+
+    let mut exported: HashMap<Rejections, u32> = HashMap::new();
+    let mut plaintexts = vec![];
+    let mut previous_id = None;
+    while let Some((id, rejections, pt)) = ptdb.get_next(previous_id)? {
+        previous_id = Some(id);
+        if !opt.include.is_empty() && !opt.include.contains(&rejections.iterations) {
+            continue;
+        }
+        let count = exported.entry(rejections).or_insert(0);
+        if opt.pt_limit_per_iter.map_or(true, |limit| *count < limit) {
+            *count += 1;
+            plaintexts.push((rejections, pt.as_slice().to_vec()));
+        }
+    }
+
+    info!(
+        "Exporting {} {} plaintexts to {:?}",
+        plaintexts.len(),
+        KEM::NAME,
+        opt.destination
+    );
+    let export = PlaintextExport {
+        kem: KEM::NAME.to_string(),
+        plaintext_len: metadata.plaintext_len.unwrap_or_else(KEM::Plaintext::len),
+        liboqs_fingerprint: metadata.liboqs_fingerprint,
+        plaintexts,
+    };
+    let writer = AutoFinishUnchecked::new(
+        libflate::gzip::Encoder::new(BufWriter::new(File::create(&opt.destination).strerr()?))
+            .strerr()?,
+    );
+    bincode::serialize_into(writer, &export).strerr()?;
+
+    Ok(())
+}
+
+#[logfn_inputs(Trace)]
+pub fn run_import<KEM: RejectionSamplingStats>(
+    opt: ImportPlaintextDbOptions,
+) -> Result<(), String> {
+    info!("Reading exported plaintexts from {:?}", opt.source);
+    let reader =
+        libflate::gzip::Decoder::new(BufReader::new(File::open(&opt.source).strerr()?)).strerr()?;
+    let export: PlaintextExport = bincode::deserialize_from(reader).strerr()?;
+    if export.kem != KEM::NAME {
+        return Err(format!(
+            "{:?} contains {} plaintexts, not {}",
+            opt.source,
+            export.kem,
+            KEM::NAME
+        ));
+    }
+
+    let mut ptdb = PlaintextDb::<KEM>::new(&opt.destination, opt.pt_limit_per_iter)?;
+    check_compatible(
+        &ptdb.metadata()?,
+        Some(export.plaintext_len),
+        export.liboqs_fingerprint.as_deref(),
+    )?;
+    let mut stored = stored_plaintexts(&mut ptdb)?;
+
+    let (mut added, mut duplicates, mut over_limit) = (0, 0, 0);
+    let mut pt = KEM::Plaintext::new();
+    for (rejections, bytes) in export.plaintexts {
+        if bytes.len() != KEM::Plaintext::len() {
+            return Err(format!(
+                "Exported plaintext has length {}, expected {}",
+                bytes.len(),
+                KEM::Plaintext::len()
+            ));
+        }
+        pt.as_mut_slice().copy_from_slice(&bytes);
+        if !stored.insert(bytes) {
+            duplicates += 1;
+        } else if ptdb.insert_pt(&pt, rejections)? {
+            added += 1;
+        } else {
+            over_limit += 1;
+        }
+    }
+    info!(
+        "Imported {} plaintexts, skipped {} duplicates and {} above the limit",
+        added, duplicates, over_limit
+    );
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum StatsFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+impl FromStr for StatsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<StatsFormat, String> {
+        match s {
+            "table" => Ok(StatsFormat::Table),
+            "csv" => Ok(StatsFormat::Csv),
+            "json" => Ok(StatsFormat::Json),
+            _ => Err(format!(
+                "Could not parse {} into either table, csv or json.",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct StatsPlaintextDbOptions {
+    /// Path to plaintext database, it is opened read-only
+    #[structopt(short, long)]
+    db: PathBuf,
+
+    /// Output format, either table, csv or json
+    #[structopt(short, long, default_value("table"))]
+    format: StatsFormat,
+
+    /// Where to write the statistics, defaults to stdout
+    #[structopt(short, long)]
+    output: Option<PathBuf>,
+}
+
+/// Statistics for all plaintexts with the same number of rejections
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct RejectionStats {
+    iterations: u32,
+    seedexpanders: u32,
+    encountered: u64,
+    stored: u64,
+    /// Empirical probability that a random plaintext has exactly this many iterations
+    probability: f64,
+    /// Empirical probability that a random plaintext has at least this many iterations
+    tail_probability: f64,
+    /// Expected number of random plaintexts to try before finding one with at least this many
+    /// iterations, unknown if none were encountered
+    expected_plaintexts: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct KemStats {
+    kem: String,
+    schema_version: u32,
+    liboqs_fingerprint: Option<String>,
+    encountered: u64,
+    stored: u64,
+    rejections: Vec<RejectionStats>,
+}
+
+fn rejection_stats(counts: &BTreeMap<Rejections, (u64, u64)>) -> Vec<RejectionStats> {
+    let total: u64 = counts.values().map(|(encountered, _)| encountered).sum();
+    let probability = |count: u64| {
+        if total > 0 {
+            count as f64 / total as f64
+        } else {
+            0.0
+        }
+    };
+    let mut at_least = 0;
+    let mut stats: Vec<_> = counts
+        .iter()
+        .rev()
+        .map(|(rejections, (encountered, stored))| {
+            at_least += encountered;
+            let tail_probability = probability(at_least);
+            RejectionStats {
+                iterations: rejections.iterations,
+                seedexpanders: rejections.seedexpanders,
+                encountered: *encountered,
+                stored: *stored,
+                probability: probability(*encountered),
+                tail_probability,
+                expected_plaintexts: if tail_probability > 0.0 {
+                    Some(1.0 / tail_probability)
+                } else {
+                    None
+                },
+            }
+        })
+        .collect();
+    stats.reverse();
+    stats
+}
+
+/// Splits the raw number of rejections stored by older schema versions, for the KEMs we know of
+fn split_raw_rejections(kem: &str, raw: u64) -> Result<Rejections, String> {
+    if kem == BikeL1::NAME {
+        BikeL1::split_rejections(raw)
+    } else if kem == BikeL3::NAME {
+        BikeL3::split_rejections(raw)
+    } else if kem == BikeL5::NAME {
+        BikeL5::split_rejections(raw)
+    } else if kem == Hqc128::NAME {
+        Hqc128::split_rejections(raw)
+    } else if kem == Hqc192::NAME {
+        Hqc192::split_rejections(raw)
+    } else if kem == Hqc256::NAME {
+        Hqc256::split_rejections(raw)
+    } else {
+        Err(format!("Unknown KEM {}, can not split its rejections", kem))
+    }
+}
+
+/// Reads the statistics of a single KEM, without modifying or migrating its tables
+fn kem_stats(conn: &Connection, kem: &str) -> Result<KemStats, String> {
+    let metadata = |key: &str| -> Result<Option<String>, String> {
+        let has_metadata: u32 = conn
+            .query_row(
+                "SELECT count(name) FROM sqlite_master WHERE type = 'table' AND name = 'metadata'",
+                [],
+                |r| r.get(0),
+            )
+            .strerr()?;
+        if has_metadata == 0 {
+            return Ok(None);
+        }
+        conn.query_row(
+            "SELECT value FROM metadata WHERE kem = ?1 AND key = ?2",
+            params![kem, key],
+            |r| r.get(0),
+        )
+        .optional()
+        .strerr()
+    };
+    let schema_version = metadata("schema_version")?
+        .map(|v| v.parse::<u32>().strerr())
+        .transpose()?
+        .unwrap_or(0);
+    let separate = metadata("iter_encoding")?.as_deref() == Some("separate");
+    let has_count_table: u32 = conn
+        .query_row(
+            "SELECT count(name) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [format!("{}_plaintext_count", kem)],
+            |r| r.get(0),
+        )
+        .strerr()?;
+
+    let query = |sql: String| -> Result<Vec<(Rejections, u64)>, String> {
+        let mut stmt = conn.prepare(&sql).strerr()?;
+        let rows = stmt
+            .query_map([], |r| {
+                Ok((
+                    r.get::<_, u64>(0)?,
+                    r.get::<_, u32>(1)?,
+                    r.get::<_, u64>(2)?,
+                ))
+            })
+            .strerr()?;
+        rows.map(|row| {
+            let (iter, seedexpanders, count) = row.strerr()?;
+            let rejections = if separate {
+                Rejections {
+                    iterations: iter as u32,
+                    seedexpanders,
+                }
+            } else {
+                split_raw_rejections(kem, iter)?
+            };
+            Ok((rejections, count))
+        })
+        .collect()
+    };
+    let (seedexpanders, group_by) = if separate {
+        ("seedexpanders", "iter, seedexpanders")
+    } else {
+        ("0", "iter")
+    };
+
+    let mut counts = BTreeMap::new();
+    if has_count_table > 0 {
+        for (rejections, count) in query(format!(
+            "SELECT iter, {se}, SUM(count) FROM {kem}_plaintext_count GROUP BY {group_by}",
+            se = seedexpanders,
+            kem = kem,
+            group_by = group_by
+        ))? {
+            counts.entry(rejections).or_insert((0, 0)).0 += count;
+        }
+    }
+    for (rejections, count) in query(format!(
+        "SELECT iter, {se}, COUNT(id) FROM {kem}_plaintexts GROUP BY {group_by}",
+        se = seedexpanders,
+        kem = kem,
+        group_by = group_by
+    ))? {
+        counts.entry(rejections).or_insert((0, 0)).1 += count;
+    }
+
+    Ok(KemStats {
+        kem: kem.to_string(),
+        schema_version,
+        liboqs_fingerprint: metadata("liboqs_fingerprint")?,
+        encountered: counts.values().map(|(encountered, _)| encountered).sum(),
+        stored: counts.values().map(|(_, stored)| stored).sum(),
+        rejections: rejection_stats(&counts),
+    })
+}
+
+fn write_table(writer: &mut dyn Write, stats: &[KemStats]) -> Result<(), String> {
+    for kem in stats {
+        writeln!(
+            writer,
+            "{} (schema version {}, liboqs fingerprint {}): {} stored out of {} encountered plaintexts",
+            kem.kem,
+            kem.schema_version,
+            kem.liboqs_fingerprint.as_deref().unwrap_or("unknown"),
+            kem.stored,
+            kem.encountered
+        )
+        .strerr()?;
+        writeln!(
+            writer,
+            "{:>10} {:>13} {:>14} {:>10} {:>12} {:>12} {:>20}",
+            "iterations",
+            "seedexpanders",
+            "encountered",
+            "stored",
+            "P(=k)",
+            "P(>=k)",
+            "plaintexts for >=k"
+        )
+        .strerr()?;
+        for row in &kem.rejections {
+            writeln!(
+                writer,
+                "{:>10} {:>13} {:>14} {:>10} {:>12.4e} {:>12.4e} {:>20}",
+                row.iterations,
+                row.seedexpanders,
+                row.encountered,
+                row.stored,
+                row.probability,
+                row.tail_probability,
+                row.expected_plaintexts
+                    .map_or_else(|| "-".to_string(), |e| format!("{:.1}", e))
+            )
+            .strerr()?;
+        }
+        writeln!(writer).strerr()?;
+    }
+    Ok(())
+}
+
+fn write_csv(writer: &mut dyn Write, stats: &[KemStats]) -> Result<(), String> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer
+        .write_record(&[
+            "kem",
+            "iterations",
+            "seedexpanders",
+            "encountered",
+            "stored",
+            "probability",
+            "tail_probability",
+            "expected_plaintexts",
+        ])
+        .strerr()?;
+    for kem in stats {
+        for row in &kem.rejections {
+            writer
+                .write_record(&[
+                    kem.kem.clone(),
+                    row.iterations.to_string(),
+                    row.seedexpanders.to_string(),
+                    row.encountered.to_string(),
+                    row.stored.to_string(),
+                    row.probability.to_string(),
+                    row.tail_probability.to_string(),
+                    row.expected_plaintexts
+                        .map(|e| e.to_string())
+                        .unwrap_or_default(),
+                ])
+                .strerr()?;
+        }
+    }
+    writer.flush().strerr()
+}
+
+#[logfn_inputs(Trace)]
+pub fn run_stats(opt: StatsPlaintextDbOptions) -> Result<(), String> {
+    let conn = Connection::open_with_flags(&opt.db, OpenFlags::SQLITE_OPEN_READ_ONLY).strerr()?;
+    let kems: Vec<String> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT name FROM sqlite_master WHERE type = 'table' AND name LIKE '%\\_plaintexts' ESCAPE '\\' ORDER BY name",
+            )
+            .strerr()?;
+        let names = stmt
+            .query_map([], |r| r.get::<_, String>(0))
+            .strerr()?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .strerr()?;
+        names
+            .into_iter()
+            .map(|name| name.trim_end_matches("_plaintexts").to_string())
+            .collect()
+    };
+    if kems.is_empty() {
+        warn!("No plaintexts found in {:?}", opt.db);
+    }
+    let stats = kems
+        .iter()
+        .map(|kem| kem_stats(&conn, kem))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut writer: Box<dyn Write> = match &opt.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path).strerr()?)),
+        None => Box::new(std::io::stdout()),
+    };
+    match opt.format {
+        StatsFormat::Table => write_table(&mut writer, &stats)?,
+        StatsFormat::Csv => write_csv(&mut writer, &stats)?,
+        StatsFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &stats).strerr()?;
+            writeln!(writer).strerr()?;
+        }
+    }
+    writer.flush().strerr()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use liboqs_rs_bindings::{hqc::Hqc128, Kem, KemBuf, KemWithRejectionSampling};
+    use rusqlite::{params, Connection};
+
+    use super::{rejection_stats, run_merge, MergePlaintextDbOptions};
+    use crate::attack::rejection_sampling::{plaintexts::PlaintextDb, rejections::Rejections};
+
+    /// Creates a database with the original layout, which does not require a working liboqs to open
+    fn legacy_db(name: &str, plaintexts: &[(u32, u8)], counts: &[(u32, u64)]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("oqs-afw-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(&format!(
+            "CREATE TABLE {kem}_plaintext_count (id INTEGER PRIMARY KEY, iter INTEGER, count INTEGER);
+            CREATE TABLE {kem}_plaintexts (id INTEGER PRIMARY KEY, iter INTEGER, plaintext BLOB);",
+            kem = Hqc128::NAME
+        ))
+        .unwrap();
+        for (iter, byte) in plaintexts {
+            conn.execute(
+                &format!(
+                    "INSERT INTO {kem}_plaintexts (iter, plaintext) VALUES (?1, ?2)",
+                    kem = Hqc128::NAME
+                ),
+                params![
+                    iter,
+                    vec![*byte; <Hqc128 as KemWithRejectionSampling>::Plaintext::len()]
+                ],
+            )
+            .unwrap();
+        }
+        for (iter, count) in counts {
+            conn.execute(
+                &format!(
+                    "INSERT INTO {kem}_plaintext_count (iter, count) VALUES (?1, ?2)",
+                    kem = Hqc128::NAME
+                ),
+                params![iter, count],
+            )
+            .unwrap();
+        }
+        path
+    }
+
+    fn rejections(iterations: u32) -> Rejections {
+        Rejections {
+            iterations,
+            seedexpanders: 0,
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let destination = legacy_db("merge-dst", &[(3, 1), (4, 2)], &[(3, 10), (4, 5)]);
+        let source = legacy_db(
+            "merge-src",
+            &[(3, 1), (3, 3), (3, 4), (5, 5)],
+            &[(3, 7), (5, 1)],
+        );
+        run_merge::<Hqc128>(MergePlaintextDbOptions {
+            destination: destination.clone(),
+            sources: vec![source.clone()],
+            pt_limit_per_iter: 2,
+        })
+        .unwrap();
+
+        // The legacy source is migrated in a temporary copy, and left as it was
+        let tables: u32 = Connection::open(&source)
+            .unwrap()
+            .query_row(
+                "SELECT count(name) FROM sqlite_master WHERE type = 'table' AND name = 'metadata'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(tables, 0);
+        let missing = std::env::temp_dir().join("oqs-afw-missing-source.db");
+        assert!(run_merge::<Hqc128>(MergePlaintextDbOptions {
+            destination: destination.clone(),
+            sources: vec![missing.clone()],
+            pt_limit_per_iter: 2,
+        })
+        .is_err());
+        assert!(!missing.exists());
+        // Merging the same source again would add its counts twice
+        assert!(run_merge::<Hqc128>(MergePlaintextDbOptions {
+            destination: destination.clone(),
+            sources: vec![source.clone()],
+            pt_limit_per_iter: 2,
+        })
+        .is_err());
+
+        let ptdb = PlaintextDb::<Hqc128>::new(&destination, 0).unwrap();
+        // The duplicate is skipped, and only one of the two new plaintexts fits within the limit
+        assert_eq!(
+            ptdb.iter_counts().unwrap(),
+            vec![
+                (rejections(3), 17, 2),
+                (rejections(4), 5, 1),
+                (rejections(5), 1, 1)
+            ]
+        );
+
+        for path in [&destination, &source] {
+            let _ = std::fs::remove_file(Path::new(path));
+        }
+    }
+
+    #[test]
+    fn test_rejection_stats() {
+        let counts = [(3, (6, 2)), (4, (3, 3)), (5, (1, 1))]
+            .iter()
+            .map(|&(iterations, counts)| (rejections(iterations), counts))
+            .collect();
+        let stats = rejection_stats(&counts);
+        let tails: Vec<_> = stats.iter().map(|s| s.tail_probability).collect();
+        assert_eq!(tails, vec![1.0, 0.4, 0.1]);
+        assert_eq!(stats[1].probability, 0.3);
+        assert_eq!(stats[2].expected_plaintexts, Some(10.0));
+
+        // Rows that were never encountered, e.g. of a database with only stored plaintexts
+        let counts = [(3, (0, 2))]
+            .iter()
+            .map(|&(iterations, counts)| (rejections(iterations), counts))
+            .collect();
+        assert_eq!(rejection_stats(&counts)[0].expected_plaintexts, None);
+    }
+}
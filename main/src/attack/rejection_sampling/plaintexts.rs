@@ -1,25 +1,31 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, HashMap},
     marker::PhantomData,
     path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc, Mutex, TryLockError,
+        mpsc::{sync_channel, Receiver, TryRecvError},
+        Arc, Mutex,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use liboqs_rs_bindings as oqs;
-use log::{error, info};
+use log::{error, info, warn};
 use log_derive::logfn_inputs;
 use ms_converter::ms_into_time;
 use oqs::{KemBuf, KemWithRejectionSampling};
-use rand::{thread_rng, Fill};
-use rusqlite::{params, params_from_iter, Connection, Params, Row, ToSql};
-use signal_hook::{consts::TERM_SIGNALS, flag};
+use rand::Fill;
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, Params, Row, ToSql};
 use structopt::StructOpt;
 
-use crate::utils::{BarSelector, ClonableProgressManager, ProgressBars, StrErr};
+use crate::{
+    attack::rejection_sampling::{
+        experiment::root_seed,
+        rejections::{RejectionCounter, RejectionSamplingStats, Rejections},
+    },
+    utils::{get_stop_signal, BarSelector, ClonableProgressManager, ProgressBars, StrErr},
+};
 
 const UPDATE_RATE_HZ: u64 = 1;
 const UPDATE_RATE_MS: Duration = Duration::from_millis(1000 / UPDATE_RATE_HZ);
@@ -54,150 +60,496 @@ pub struct CollectPlaintextsOptions {
     #[structopt(short("a"), long, parse(try_from_str=ms_into_time))]
     stop_after: Option<Duration>,
 
-    /// Walks through each plaintext in the database and recheks the iteration count
+    /// Walks through each plaintext in the database and recheks the iteration count, then records
+    /// the linked liboqs implementation as the one the database was built with
     #[structopt(short, long)]
     reindex_plaintexts: bool,
 }
 
-struct DbConnection {
-    conn: Mutex<Connection>,
-    #[allow(clippy::type_complexity)]
-    queue: Mutex<VecDeque<(String, Vec<Box<dyn ToSql + Send>>)>>,
-}
-
-impl std::fmt::Debug for DbConnection {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("DbConnection")
-            .field("conn", &self.conn)
-            .finish()
-    }
-}
-
 impl BarSelector for u8 {}
 
 #[derive(Debug, Clone)]
 pub struct PlaintextDb<KEM: KemWithRejectionSampling> {
-    db: Arc<DbConnection>,
+    db: Arc<Mutex<Connection>>,
     add_limit: u32,
-    count_below_cache: Vec<bool>,
-    count_plaintexts: Vec<u64>,
+    count_below_cache: HashMap<Rejections, bool>,
     last_get_next_max_id: Option<u32>,
     last_get_next_iter_id: Option<u32>,
-    last_get_next_iter_iter: Option<u32>,
+    last_get_next_iter_filter: Option<(RejectionCounter, u32)>,
     _kem: PhantomData<KEM>,
 }
 
 macro_rules! sql_pt {
-    ($sql:literal, $kem:tt) => {{
-        sql!($sql, $kem, "_plaintexts")
+    ($sql:literal, $kem:tt $(, $name:ident = $value:expr)*) => {{
+        sql!($sql, $kem, "_plaintexts" $(, $name = $value)*)
     }};
 }
 macro_rules! sql_cnt {
-    ($sql:literal, $kem:tt) => {{
-        sql!($sql, $kem, "_plaintext_count")
+    ($sql:literal, $kem:tt $(, $name:ident = $value:expr)*) => {{
+        sql!($sql, $kem, "_plaintext_count" $(, $name = $value)*)
     }};
 }
 macro_rules! sql {
-    ($sql:literal, $kem:tt, $table_name:literal) => {{
-        format!($sql, kem = $kem::NAME, table = $table_name)
+    ($sql:literal, $kem:tt, $table_name:literal $(, $name:ident = $value:expr)*) => {{
+        format!($sql, kem = $kem::NAME, table = $table_name $(, $name = $value)*)
     }};
 }
 
-const MAX_QUEUE_LENGTH: usize = 100;
+/// Number of plaintexts a worker collects before sending them to the writer thread
+const BATCH_SIZE: usize = 256;
+
+/// Number of batches of a worker that may be waiting for the writer thread before the worker blocks
+const MAX_QUEUED_BATCHES_PER_THREAD: usize = 4;
+
+/// How long the writer thread waits when no worker has sent a batch
+const WRITER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Version of the per-KEM table layout, bumped whenever a migration is added to [`PlaintextDb::migrate`]
+const SCHEMA_VERSION: u32 = 2;
+
+/// Number of fixed plaintexts used to fingerprint the rejection sampling of the linked liboqs
+const FINGERPRINT_PLAINTEXTS: u64 = 64;
+
+/// Properties of a plaintext database that must match the running program for the stored
+/// iteration counts to be meaningful.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaintextDbMetadata {
+    pub schema_version: u32,
+    pub iter_encoding: String,
+    pub plaintext_len: Option<usize>,
+    pub ciphertext_len: Option<usize>,
+    pub public_key_len: Option<usize>,
+    pub secret_key_len: Option<usize>,
+    pub liboqs_fingerprint: Option<String>,
+    /// The content fingerprints of the databases merged into this one, see [`PlaintextDb::content_fingerprint`]
+    pub merged_sources: Vec<String>,
+}
+
+/// How the `iter` column is encoded. Up to schema version 1 it was `raw`, the value returned by
+/// `num_rejections` as is. Since version 2 the seedexpander count is stored in a separate column.
+const ITER_ENCODING: &str = "separate";
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Simple and stable 64-bit FNV-1a hash, used to fingerprint the liboqs implementation and the
+/// contents of databases
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Fingerprints the rejection sampling of the liboqs implementation that we are linked with, by
+/// hashing the number of rejections of a fixed set of plaintexts. Any change to the rejection
+/// sampling, which would silently invalidate a plaintext database, changes the fingerprint.
+pub fn liboqs_fingerprint<KEM: KemWithRejectionSampling>() -> Result<String, String> {
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut pt = KEM::Plaintext::new();
+    for n in 0..FINGERPRINT_PLAINTEXTS {
+        // splitmix64, so that the plaintexts never change between versions of the rand crate
+        let mut state = n;
+        for chunk in pt.as_mut_slice().chunks_mut(8) {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^= z >> 31;
+            chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+        }
+        let rejections = KEM::num_rejections(&mut pt)? as u64;
+        hash = fnv1a(hash, &rejections.to_le_bytes());
+    }
+    Ok(format!("{:016x}", hash))
+}
 
-impl<KEM: KemWithRejectionSampling> PlaintextDb<KEM> {
+impl<KEM: RejectionSamplingStats> PlaintextDb<KEM> {
     pub fn new(dest: impl AsRef<Path>, add_limit: u32) -> Result<Self, String> {
-        let conn = Connection::open(dest).strerr()?;
-        conn.busy_handler(Some(|_i| true)).strerr()?; // Ignore busy errors
-        Self::new_shared(
-            Arc::new(DbConnection {
-                conn: Mutex::new(conn),
-                queue: Mutex::new(VecDeque::with_capacity(MAX_QUEUE_LENGTH)),
-            }),
-            add_limit,
-        )
+        Self::new_shared(Arc::new(Mutex::new(open_connection(dest)?)), add_limit)
+    }
+
+    /// Opens an existing database without modifying it, or returns None if the tables of this KEM
+    /// need a migration first. Fails if the database or the tables do not exist.
+    pub fn open_read_only(dest: impl AsRef<Path>) -> Result<Option<Self>, String> {
+        let dest = dest.as_ref();
+        let conn = Connection::open_with_flags(dest, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(|err| format!("Could not open {:?}: {}", dest, err))?;
+        let ptdb = Self::with_connection(Arc::new(Mutex::new(conn)), 0);
+        if !ptdb.table_exists(sql_pt!("{kem}{table}", KEM))? {
+            return Err(format!("{:?} contains no {} plaintexts", dest, KEM::NAME));
+        }
+        let version = if ptdb.table_exists(String::from("metadata"))? {
+            ptdb.metadata()?.schema_version
+        } else {
+            0
+        };
+        Ok(if version == SCHEMA_VERSION {
+            Some(ptdb)
+        } else {
+            None
+        })
     }
 
-    fn new_shared(db: Arc<DbConnection>, add_limit: u32) -> Result<Self, String> {
-        let ptdb = Self {
+    fn with_connection(db: Arc<Mutex<Connection>>, add_limit: u32) -> Self {
+        Self {
             db,
             add_limit,
-            count_below_cache: vec![],
-            count_plaintexts: vec![],
+            count_below_cache: HashMap::new(),
             last_get_next_max_id: None,
             last_get_next_iter_id: None,
-            last_get_next_iter_iter: None,
+            last_get_next_iter_filter: None,
             _kem: PhantomData,
-        };
+        }
+    }
+
+    fn table_exists(&self, name: String) -> Result<bool, String> {
+        Ok(self.query_row(
+            String::from(
+                "SELECT count(name) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            ),
+            [name],
+            |r| r.get::<_, u32>(0),
+            0,
+        )? > 0)
+    }
+
+    fn new_shared(db: Arc<Mutex<Connection>>, add_limit: u32) -> Result<Self, String> {
+        let ptdb = Self::with_connection(db, add_limit);
         ptdb.modify(
+            String::from(
+                "CREATE TABLE IF NOT EXISTS metadata (
+                kem         TEXT NOT NULL,
+                key         TEXT NOT NULL,
+                value       TEXT,
+                PRIMARY KEY (kem, key))",
+            ),
+            vec![],
+        )?;
+
+        let existing = ptdb.table_exists(sql_pt!("{kem}{table}", KEM))?;
+        let version = match ptdb.get_metadata("schema_version")? {
+            Some(version) => version.parse::<u32>().strerr()?,
+            None if existing => 0, // Created before the metadata table existed
+            None => {
+                info!("Creating new plaintext tables for {}", KEM::NAME);
+                ptdb.create_tables()?;
+                ptdb.set_metadata("schema_version", SCHEMA_VERSION)?;
+                ptdb.set_metadata("iter_encoding", ITER_ENCODING)?;
+                ptdb.set_implementation_metadata()?;
+                SCHEMA_VERSION
+            }
+        };
+        ptdb.migrate(version)?;
+
+        Ok(ptdb)
+    }
+
+    fn create_tables(&self) -> Result<(), String> {
+        self.modify(
             sql_cnt!(
                 "CREATE TABLE IF NOT EXISTS {kem}{table} (
-                id          INTEGER PRIMARY KEY,
-                iter        INTEGER,
-                count       INTEGER)",
+                id              INTEGER PRIMARY KEY,
+                iter            INTEGER,
+                seedexpanders   INTEGER,
+                count           INTEGER)",
                 KEM
             ),
             vec![],
         )?;
-        ptdb.modify(
+        self.modify(
             sql_pt!(
                 "CREATE TABLE IF NOT EXISTS {kem}{table}(
-                id          INTEGER PRIMARY KEY,
-                iter        INTEGER,
-                plaintext   BLOB)",
+                id              INTEGER PRIMARY KEY,
+                iter            INTEGER,
+                seedexpanders   INTEGER,
+                plaintext       BLOB)",
                 KEM
             ),
             vec![],
         )?;
-        ptdb.modify(
-            sql_pt!(
+        self.create_indices()
+    }
+
+    fn create_indices(&self) -> Result<(), String> {
+        Self::create_indices_in(&*self.db.lock().strerr()?)
+    }
+
+    fn create_indices_in(conn: &Connection) -> Result<(), String> {
+        conn.execute(
+            &sql_pt!(
                 "CREATE INDEX IF NOT EXISTS {kem}_iter_index ON {kem}{table} (iter)",
                 KEM
             ),
-            vec![],
-        )?;
+            [],
+        )
+        .strerr()?;
+        conn.execute(
+            &sql_pt!(
+                "CREATE INDEX IF NOT EXISTS {kem}_seedexpanders_index ON {kem}{table} (seedexpanders)",
+                KEM
+            ),
+            [],
+        )
+        .strerr()?;
+        Ok(())
+    }
 
-        Ok(ptdb)
+    /// Upgrades the tables of this KEM from the specified version to [`SCHEMA_VERSION`], one step at a time.
+    /// Every step is a transaction together with its new schema version, so an interrupted migration can be
+    /// resumed from the last completed step.
+    fn migrate(&self, from: u32) -> Result<(), String> {
+        if from > SCHEMA_VERSION {
+            return Err(format!(
+                "Plaintext database for {} has schema version {}, but this program only supports up to version {}",
+                KEM::NAME,
+                from,
+                SCHEMA_VERSION
+            ));
+        }
+        for version in from..SCHEMA_VERSION {
+            info!(
+                "Migrating plaintext tables for {} from schema version {} to {}",
+                KEM::NAME,
+                version,
+                version + 1
+            );
+            let mut conn = self.db.lock().strerr()?;
+            let tx = conn.transaction().strerr()?;
+            Self::migrate_step(&tx, version)?;
+            Self::set_metadata_in(&tx, "schema_version", version + 1)?;
+            tx.commit().strerr()?;
+        }
+        Ok(())
     }
 
-    fn modify(&self, sql: String, params: Vec<Box<dyn ToSql + Send>>) -> Result<(), String> {
-        let mut block = false;
-        'restart: loop {
-            let lock = if block {
-                match self.db.conn.lock() {
-                    Ok(conn) => Ok(Some(conn)),
-                    Err(err) => Err(err.to_string()),
-                }
-            } else {
-                match self.db.conn.try_lock() {
-                    Ok(conn) => Ok(Some(conn)),
-                    Err(TryLockError::WouldBlock) => Ok(None),
-                    Err(err) => Err(err.to_string()),
-                }
-            };
-            break match lock {
-                Ok(Some(mut conn)) => {
-                    let tx = conn.transaction().strerr()?;
-                    let mut queue = self.db.queue.lock().strerr()?;
-                    let drain_it = queue.drain(..);
-                    for (sql, params) in drain_it {
-                        tx.execute(&sql, params_from_iter(params)).strerr()?;
+    fn migrate_step(conn: &Connection, version: u32) -> Result<(), String> {
+        match version {
+            0 => {
+                // The original layout. Which liboqs build created the plaintexts is unknown,
+                // so the implementation fingerprint is deliberately left out.
+                conn.execute(
+                    &sql_cnt!(
+                        "CREATE TABLE IF NOT EXISTS {kem}{table} (
+                        id          INTEGER PRIMARY KEY,
+                        iter        INTEGER,
+                        count       INTEGER)",
+                        KEM
+                    ),
+                    [],
+                )
+                .strerr()?;
+                Self::set_metadata_in(conn, "iter_encoding", "raw")?;
+            }
+            1 => {
+                // Split the raw number of rejections into separate iteration and seedexpander columns
+                conn.execute(
+                    &sql_pt!(
+                        "ALTER TABLE {kem}{table} ADD COLUMN seedexpanders INTEGER",
+                        KEM
+                    ),
+                    [],
+                )
+                .strerr()?;
+                conn.execute(
+                    &sql_cnt!(
+                        "ALTER TABLE {kem}{table} ADD COLUMN seedexpanders INTEGER",
+                        KEM
+                    ),
+                    [],
+                )
+                .strerr()?;
+                conn.execute(
+                    &format!("DROP INDEX IF EXISTS {}count_iter_index", KEM::NAME),
+                    [],
+                )
+                .strerr()?;
+                // Rows that are already split have a seedexpander count, which prevents
+                // them from being split twice.
+                for table in ["_plaintexts", "_plaintext_count"] {
+                    let raws = conn
+                        .prepare(&format!(
+                            "SELECT DISTINCT iter FROM {kem}{table}",
+                            kem = KEM::NAME,
+                            table = table
+                        ))
+                        .strerr()?
+                        .query_map([], |r| r.get(0))
+                        .strerr()?
+                        .collect::<rusqlite::Result<Vec<u64>>>()
+                        .strerr()?;
+                    for raw in raws {
+                        let rejections = KEM::split_rejections(raw)?;
+                        conn.execute(
+                            &format!(
+                                "UPDATE {kem}{table} SET iter = ?2, seedexpanders = ?3 WHERE iter = ?1 AND seedexpanders IS NULL",
+                                kem = KEM::NAME,
+                                table = table
+                            ),
+                            params![raw, rejections.iterations, rejections.seedexpanders],
+                        )
+                        .strerr()?;
                     }
-                    tx.execute(&sql, params_from_iter(params)).strerr()?;
-                    tx.commit().strerr()
                 }
-                Ok(None) => {
-                    let mut queue = self.db.queue.lock().strerr()?;
-                    if queue.len() >= MAX_QUEUE_LENGTH {
-                        block = true;
-                        continue 'restart;
-                    }
-                    queue.push_back((sql, params));
-                    Ok(())
+                Self::create_indices_in(conn)?;
+                Self::set_metadata_in(conn, "iter_encoding", ITER_ENCODING)?;
+            }
+            _ => unreachable!("No migration from schema version {}", version),
+        }
+        Ok(())
+    }
+
+    fn get_metadata(&self, key: &str) -> Result<Option<String>, String> {
+        self.query_row(
+            String::from("SELECT value FROM metadata WHERE kem = ?1 AND key = ?2"),
+            params![KEM::NAME, key],
+            |r| r.get(0),
+            None,
+        )
+    }
+
+    fn set_metadata(&self, key: &str, value: impl ToString) -> Result<(), String> {
+        Self::set_metadata_in(&*self.db.lock().strerr()?, key, value)
+    }
+
+    fn set_metadata_in(conn: &Connection, key: &str, value: impl ToString) -> Result<(), String> {
+        conn.execute(
+            "INSERT OR REPLACE INTO metadata (kem, key, value) VALUES (?1, ?2, ?3)",
+            params![KEM::NAME, key, value.to_string()],
+        )
+        .strerr()?;
+        Ok(())
+    }
+
+    /// Records the properties of the currently linked liboqs implementation
+    fn set_implementation_metadata(&self) -> Result<(), String> {
+        self.set_metadata("plaintext_len", KEM::Plaintext::len())?;
+        self.set_metadata("ciphertext_len", KEM::Ciphertext::len())?;
+        self.set_metadata("public_key_len", KEM::PublicKey::len())?;
+        self.set_metadata("secret_key_len", KEM::SecretKey::len())?;
+        self.set_metadata("liboqs_fingerprint", liboqs_fingerprint::<KEM>()?)
+    }
+
+    pub fn metadata(&self) -> Result<PlaintextDbMetadata, String> {
+        let len = |key| -> Result<Option<usize>, String> {
+            self.get_metadata(key)?
+                .map(|v| v.parse::<usize>().strerr())
+                .transpose()
+        };
+        Ok(PlaintextDbMetadata {
+            schema_version: self
+                .get_metadata("schema_version")?
+                .map(|v| v.parse::<u32>().strerr())
+                .transpose()?
+                .unwrap_or(0),
+            iter_encoding: self
+                .get_metadata("iter_encoding")?
+                .unwrap_or_else(|| ITER_ENCODING.to_string()),
+            plaintext_len: len("plaintext_len")?,
+            ciphertext_len: len("ciphertext_len")?,
+            public_key_len: len("public_key_len")?,
+            secret_key_len: len("secret_key_len")?,
+            liboqs_fingerprint: self.get_metadata("liboqs_fingerprint")?,
+            merged_sources: self
+                .get_metadata("merged_sources")?
+                .map(|v| v.split(',').map(String::from).collect())
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Records that the databases with the given content fingerprints were merged into this one
+    pub fn add_merged_sources(&self, fingerprints: &[String]) -> Result<(), String> {
+        let mut merged = self.metadata()?.merged_sources;
+        for fingerprint in fingerprints {
+            if !merged.contains(fingerprint) {
+                merged.push(fingerprint.clone());
+            }
+        }
+        self.set_metadata("merged_sources", merged.join(","))
+    }
+
+    /// Fingerprints the encountered counts and the stored plaintexts, so that merging the same
+    /// database twice, which would add its counts twice, can be detected. Any change to the
+    /// database, e.g. by collecting more plaintexts, changes the fingerprint.
+    pub fn content_fingerprint(&mut self) -> Result<String, String> {
+        let mut hash = FNV_OFFSET_BASIS;
+        for (rejections, count) in self.encountered_counts()? {
+            hash = fnv1a(hash, &rejections.iterations.to_le_bytes());
+            hash = fnv1a(hash, &rejections.seedexpanders.to_le_bytes());
+            hash = fnv1a(hash, &count.to_le_bytes());
+        }
+        let mut previous_id = None;
+        while let Some((id, _, pt)) = self.get_next(previous_id)? {
+            previous_id = Some(id);
+            hash = fnv1a(hash, pt.as_slice());
+        }
+        Ok(format!("{:016x}", hash))
+    }
+
+    /// Verifies that the plaintexts were collected with the liboqs implementation we are linked with.
+    /// A mismatch is an error if `strict`, otherwise only a warning. Databases without a recorded
+    /// fingerprint can not be verified and always result in a warning.
+    pub fn verify_implementation(&self, strict: bool) -> Result<(), String> {
+        let metadata = self.metadata()?;
+        let mut mismatches = vec![];
+        let lengths = [
+            ("plaintext", metadata.plaintext_len, KEM::Plaintext::len()),
+            (
+                "ciphertext",
+                metadata.ciphertext_len,
+                KEM::Ciphertext::len(),
+            ),
+            ("public key", metadata.public_key_len, KEM::PublicKey::len()),
+            ("secret key", metadata.secret_key_len, KEM::SecretKey::len()),
+        ];
+        for (name, stored, current) in lengths {
+            if let Some(stored) = stored {
+                if stored != current {
+                    mismatches.push(format!(
+                        "{} length is {} in the database but {} in liboqs",
+                        name, stored, current
+                    ));
                 }
-                Err(err) => Err(err),
-            };
+            }
+        }
+        match metadata.liboqs_fingerprint {
+            Some(stored) => {
+                let current = liboqs_fingerprint::<KEM>()?;
+                if stored != current {
+                    mismatches.push(format!(
+                        "rejection sampling fingerprint is {} in the database but {} in liboqs",
+                        stored, current
+                    ));
+                }
+            }
+            None => warn!(
+                "Plaintext database does not record which liboqs implementation of {} created it, the iteration counts can not be verified (use --reindex-plaintexts to fix this)",
+                KEM::NAME
+            ),
         }
+
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            let msg = format!(
+                "Plaintext database was created by a different liboqs implementation of {}: {} (use --reindex-plaintexts to fix this)",
+                KEM::NAME,
+                mismatches.join(", ")
+            );
+            if strict {
+                Err(msg)
+            } else {
+                warn!("{}", msg);
+                Ok(())
+            }
+        }
+    }
+
+    fn modify(&self, sql: String, params: Vec<Box<dyn ToSql + Send>>) -> Result<(), String> {
+        self.db
+            .lock()
+            .strerr()?
+            .execute(&sql, params_from_iter(params))
+            .strerr()?;
+        Ok(())
     }
 
     fn query_row<T, P, F>(&self, sql: String, params: P, f: F, or_else: T) -> Result<T, String>
@@ -206,7 +558,6 @@ impl<KEM: KemWithRejectionSampling> PlaintextDb<KEM> {
         F: FnOnce(&Row<'_>) -> rusqlite::Result<T>,
     {
         self.db
-            .conn
             .lock()
             .strerr()?
             .query_row(&sql, params, f)
@@ -217,28 +568,53 @@ impl<KEM: KemWithRejectionSampling> PlaintextDb<KEM> {
             .strerr()
     }
 
-    pub fn get_min_max(&mut self, max: bool) -> Result<Option<(u32, KEM::Plaintext)>, String> {
-        let (id, iter, pt): (Option<u32>, Option<u32>, Option<Vec<u8>>) = self.query_row(
-            if max {
-                sql_pt!(
-                    "SELECT id, iter, plaintext FROM {kem}{table} ORDER BY iter DESC, id DESC LIMIT 1", 
-                    KEM
-                )
-            } else {
-                sql_pt!(
-                    "SELECT id, iter, plaintext FROM {kem}{table} ORDER BY iter ASC, id ASC LIMIT 1", 
-                    KEM
-                )
-            },
-            [],
-            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
-            (None, None, None),
-        )?;
-        if let (Some(id), Some(iter), Some(pt)) = (id, iter, pt) {
+    fn query_map<T, P, F>(&self, sql: String, params: P, f: F) -> Result<Vec<T>, String>
+    where
+        P: Params,
+        F: FnMut(&Row<'_>) -> rusqlite::Result<T>,
+    {
+        let conn = self.db.lock().strerr()?;
+        let mut stmt = conn.prepare(&sql).strerr()?;
+        let rows = stmt.query_map(params, f).strerr()?;
+        rows.collect::<rusqlite::Result<Vec<T>>>().strerr()
+    }
+
+    /// Gets the plaintext with the least (or most if `max`) rejections, as counted by `by`
+    pub fn get_min_max(
+        &mut self,
+        max: bool,
+        by: RejectionCounter,
+    ) -> Result<Option<(Rejections, KEM::Plaintext)>, String> {
+        let (id, iter, se, pt): (Option<u32>, Option<u32>, Option<u32>, Option<Vec<u8>>) = self
+            .query_row(
+                if max {
+                    sql_pt!(
+                        "SELECT id, iter, seedexpanders, plaintext FROM {kem}{table} ORDER BY {col} DESC, id DESC LIMIT 1",
+                        KEM,
+                        col = by.column()
+                    )
+                } else {
+                    sql_pt!(
+                        "SELECT id, iter, seedexpanders, plaintext FROM {kem}{table} ORDER BY {col} ASC, id ASC LIMIT 1",
+                        KEM,
+                        col = by.column()
+                    )
+                },
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+                (None, None, None, None),
+            )?;
+        if let (Some(id), Some(iterations), Some(seedexpanders), Some(pt)) = (id, iter, se, pt) {
             let mut plaintext = KEM::Plaintext::new();
             plaintext.as_mut_slice().copy_from_slice(&pt);
             self.last_get_next_max_id.replace(id);
-            Ok(Some((iter, plaintext)))
+            Ok(Some((
+                Rejections {
+                    iterations,
+                    seedexpanders,
+                },
+                plaintext,
+            )))
         } else {
             Ok(None)
         }
@@ -247,109 +623,112 @@ impl<KEM: KemWithRejectionSampling> PlaintextDb<KEM> {
     pub fn get_next(
         &mut self,
         previous_id: Option<u32>,
-    ) -> Result<Option<(u32, u32, KEM::Plaintext)>, String> {
-        let (id, iter, pt): (Option<u32>, Option<u32>, Option<Vec<u8>>) = if let Some(previous_id) =
-            previous_id
-        {
+    ) -> Result<Option<(u32, Rejections, KEM::Plaintext)>, String> {
+        let (id, iter, se, pt): (Option<u32>, Option<u32>, Option<u32>, Option<Vec<u8>>) =
             self.query_row(
                 sql_pt!(
-                    "SELECT id, iter, plaintext FROM {kem}{table} WHERE id > ?1 ORDER BY id ASC LIMIT 1", 
+                    "SELECT id, iter, seedexpanders, plaintext FROM {kem}{table} WHERE id > ?1 ORDER BY id ASC LIMIT 1",
                     KEM
                 ),
-                params![previous_id],
-                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
-                (None, None, None),
-            )?
-        } else {
-            self.query_row(
-                sql_pt!(
-                    "SELECT id, iter, plaintext FROM {kem}{table} ORDER BY id ASC LIMIT 1",
-                    KEM
-                ),
-                params![],
-                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
-                (None, None, None),
-            )?
-        };
+                params![previous_id.map(i64::from).unwrap_or(-1)],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+                (None, None, None, None),
+            )?;
 
-        if let (Some(id), Some(iter), Some(pt)) = (id, iter, pt) {
+        if let (Some(id), Some(iterations), Some(seedexpanders), Some(pt)) = (id, iter, se, pt) {
             let mut plaintext = KEM::Plaintext::new();
             plaintext.as_mut_slice().copy_from_slice(&pt);
-            Ok(Some((id, iter, plaintext)))
+            Ok(Some((
+                id,
+                Rejections {
+                    iterations,
+                    seedexpanders,
+                },
+                plaintext,
+            )))
         } else {
             Ok(None)
         }
     }
 
-    pub fn update_iter(&mut self, id: u32, iter: u32) -> Result<(), String> {
+    pub fn update_rejections(&mut self, id: u32, rejections: Rejections) -> Result<(), String> {
         self.modify(
-            sql_pt!("UPDATE {kem}{table} SET iter = ?2 WHERE id = ?1", KEM),
-            vec![Box::new(id), Box::new(iter)],
+            sql_pt!(
+                "UPDATE {kem}{table} SET iter = ?2, seedexpanders = ?3 WHERE id = ?1",
+                KEM
+            ),
+            vec![
+                Box::new(id),
+                Box::new(rejections.iterations),
+                Box::new(rejections.seedexpanders),
+            ],
         )?;
 
         Ok(())
     }
 
-    /// Gets the next available plaintext that matches the iteration count specified.
-    /// No plaintext is returned twice for as long as iter remains constant.
+    /// Gets the next available plaintext where the counter `by` matches the value specified.
+    /// No plaintext is returned twice for as long as the filter remains constant.
     pub fn get_next_iter(
         &mut self,
-        iter: u32,
+        by: RejectionCounter,
+        value: u32,
         reset: bool,
-    ) -> Result<Option<(u32, KEM::Plaintext)>, String> {
+    ) -> Result<Option<(Rejections, KEM::Plaintext)>, String> {
         let previous_id: u32 = match (
-            self.last_get_next_iter_iter,
+            self.last_get_next_iter_filter,
             self.last_get_next_iter_id,
             reset,
         ) {
-            (Some(last_iter), Some(last_id), false) if last_iter == iter => last_id,
+            (Some(last_filter), Some(last_id), false) if last_filter == (by, value) => last_id,
             _ => u32::MAX,
         };
-        let (id, iter, pt): (Option<u32>, Option<u32>, Option<Vec<u8>>) = self.query_row(
-            sql_pt!(
-                "SELECT id, iter, plaintext FROM {kem}{table} WHERE id < ?1 AND iter == ?2 ORDER BY iter DESC, id DESC",
-                KEM
-            ),
-            params![previous_id, iter],
-            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
-            (None, None, None),
-        )?;
-        if let (Some(id), Some(iter), Some(pt)) = (id, iter, pt) {
+        let (id, iter, se, pt): (Option<u32>, Option<u32>, Option<u32>, Option<Vec<u8>>) = self
+            .query_row(
+                sql_pt!(
+                    "SELECT id, iter, seedexpanders, plaintext FROM {kem}{table} WHERE id < ?1 AND {col} == ?2 ORDER BY id DESC LIMIT 1",
+                    KEM,
+                    col = by.column()
+                ),
+                params![previous_id, value],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+                (None, None, None, None),
+            )?;
+        if let (Some(id), Some(iterations), Some(seedexpanders), Some(pt)) = (id, iter, se, pt) {
             let mut plaintext = KEM::Plaintext::new();
             plaintext.as_mut_slice().copy_from_slice(&pt);
             self.last_get_next_iter_id.replace(id);
-            self.last_get_next_iter_iter.replace(iter);
-            Ok(Some((iter, plaintext)))
+            self.last_get_next_iter_filter.replace((by, value));
+            Ok(Some((
+                Rejections {
+                    iterations,
+                    seedexpanders,
+                },
+                plaintext,
+            )))
         } else {
             Ok(None)
         }
     }
 
-    fn count_below(&mut self, iter: u32, below: u32) -> Result<bool, String> {
-        let iterus = iter as usize;
-        if let Some(below_cache) = self.count_below_cache.get(iterus) {
-            if !below_cache {
-                return Ok(false);
-            }
+    fn count_below(&mut self, rejections: Rejections, below: u32) -> Result<bool, String> {
+        if let Some(false) = self.count_below_cache.get(&rejections) {
+            return Ok(false);
         }
-        let count = self.count(iter)?;
-        if self.count_below_cache.len() <= iterus {
-            self.count_below_cache.resize(iterus + 1, true);
-        }
-
-        let cmp = count < below;
-
-        // Resize above makes this safe
-        unsafe { *self.count_below_cache.get_unchecked_mut(iterus) = cmp };
+        let cmp = self.count(rejections)? < below;
+        self.count_below_cache.insert(rejections, cmp);
 
         Ok(cmp)
     }
 
-    pub fn count(&self, iter: u32) -> Result<u32, String> {
+    pub fn count(&self, rejections: Rejections) -> Result<u32, String> {
         if let Some(count) = self
             .query_row(
-                sql_pt!("SELECT count(id) FROM {kem}{table} WHERE iter == ?1", KEM),
-                [iter],
+                sql_pt!(
+                    "SELECT count(id) FROM {kem}{table} WHERE iter == ?1 AND seedexpanders == ?2",
+                    KEM
+                ),
+                [rejections.iterations, rejections.seedexpanders],
                 |r| r.get(0),
                 None,
             )
@@ -361,24 +740,10 @@ impl<KEM: KemWithRejectionSampling> PlaintextDb<KEM> {
         }
     }
 
-    pub fn iter_minmax(&self) -> Result<(Option<u32>, Option<u32>), String> {
-        self.query_row(
-            sql_pt!("SELECT MIN(iter), MAX(iter) FROM {kem}{table}", KEM),
-            [],
-            |r| match (r.get(0), r.get(1)) {
-                (Ok(min), Ok(max)) => Ok((min, max)),
-                (_, Err(e)) => Err(e),
-                (Err(e), _) => Err(e),
-            },
-            (None, None),
-        )
-        .strerr()
-    }
-
     pub fn aggregate_iter_counts(&self) -> Result<(), String> {
         if let Some(max_duplicate) = self.query_row(
             sql_cnt!(
-                "SELECT MAX(a.c) FROM (SELECT iter, COUNT(*) as c FROM {kem}{table} GROUP BY iter ORDER BY iter) a;",
+                "SELECT MAX(a.c) FROM (SELECT COUNT(*) as c FROM {kem}{table} GROUP BY iter, seedexpanders) a;",
                 KEM
             ),
             [],
@@ -390,20 +755,26 @@ impl<KEM: KemWithRejectionSampling> PlaintextDb<KEM> {
                     "Aggregating up to {} duplicates per iteration count!",
                     max_duplicate
                 );
-                if let Some(iter_counts) = self.iter_counts()? {
-                    self.modify(sql_cnt!("DELETE FROM {kem}{table}", KEM), vec![])?;
-                    for (iter, count, _saved) in iter_counts {
-                        self.modify(
-                            sql_cnt!("INSERT INTO {kem}{table}(iter, count) VALUES (?1, ?2)", KEM),
-                            vec![Box::new(iter), Box::new(count)],
-                        )?;
-                    }
+                let encountered = self.encountered_counts()?;
+                self.modify(sql_cnt!("DELETE FROM {kem}{table}", KEM), vec![])?;
+                for (rejections, count) in encountered {
+                    self.modify(
+                        sql_cnt!(
+                            "INSERT INTO {kem}{table}(iter, seedexpanders, count) VALUES (?1, ?2, ?3)",
+                            KEM
+                        ),
+                        vec![
+                            Box::new(rejections.iterations),
+                            Box::new(rejections.seedexpanders),
+                            Box::new(count),
+                        ],
+                    )?;
                 }
             }
         }
         self.modify(
             sql_cnt!(
-                "CREATE UNIQUE INDEX IF NOT EXISTS {kem}count_iter_index ON {kem}{table}(iter)",
+                "CREATE UNIQUE INDEX IF NOT EXISTS {kem}count_rejections_index ON {kem}{table}(iter, seedexpanders)",
                 KEM
             ),
             vec![],
@@ -411,142 +782,243 @@ impl<KEM: KemWithRejectionSampling> PlaintextDb<KEM> {
         Ok(())
     }
 
-    #[allow(clippy::type_complexity)]
-    pub fn iter_counts(&self) -> Result<Option<Vec<(u64, u64, u64)>>, String> {
-        let v = if let (Some(iter_min), Some(iter_max)) = self.iter_minmax()? {
-            let mut v = vec![];
-            for iter in iter_min..=iter_max {
-                if let Some(count) = self.query_row(
-                    sql_cnt!("SELECT SUM(count) FROM {kem}{table} WHERE iter = ?1", KEM),
-                    params![iter],
-                    |r| r.get(0),
-                    Some(0),
-                )? {
-                    let saved_count = self.count(iter)?;
-                    if count > 0 || saved_count > 0 {
-                        v.push((iter as u64, count, saved_count as u64));
-                    }
-                }
-            }
-            Some(v)
-        } else {
-            None
-        };
-
-        Ok(v)
+    /// The number of encountered plaintexts, per number of rejections
+    pub fn encountered_counts(&self) -> Result<Vec<(Rejections, u64)>, String> {
+        self.query_map(
+            sql_cnt!(
+                "SELECT iter, seedexpanders, SUM(count) FROM {kem}{table} GROUP BY iter, seedexpanders ORDER BY iter, seedexpanders",
+                KEM
+            ),
+            [],
+            |r| {
+                Ok((
+                    Rejections {
+                        iterations: r.get(0)?,
+                        seedexpanders: r.get(1)?,
+                    },
+                    r.get(2)?,
+                ))
+            },
+        )
     }
 
-    pub fn iter_saved_counts(&self) -> Result<Option<Vec<(u64, u64)>>, String> {
-        let v = if let (Some(iter_min), Some(iter_max)) = self.iter_minmax()? {
-            let mut v = vec![];
-            for iter in iter_min..=iter_max {
-                let saved_count = self.count(iter)?;
-                if saved_count > 0 {
-                    v.push((iter as u64, saved_count as u64));
-                }
-            }
-            Some(v)
-        } else {
-            None
-        };
+    /// The number of encountered and saved plaintexts, per number of rejections
+    pub fn iter_counts(&self) -> Result<Vec<(Rejections, u64, u64)>, String> {
+        let mut counts = BTreeMap::new();
+        for (rejections, count) in self.encountered_counts()? {
+            counts.entry(rejections).or_insert((0, 0)).0 += count;
+        }
+        for (rejections, saved_count) in self.iter_saved_counts()? {
+            counts.entry(rejections).or_insert((0, 0)).1 += saved_count;
+        }
 
-        Ok(v)
+        Ok(counts
+            .into_iter()
+            .map(|(rejections, (count, saved_count))| (rejections, count, saved_count))
+            .collect())
     }
 
-    fn add_pt(&mut self, pt: &KEM::Plaintext, iter: u32) -> Result<(), String> {
-        if self.count_below(iter, self.add_limit)? {
-            self.modify(
-                sql_pt!(
-                    "INSERT INTO {kem}{table} (iter, plaintext) VALUES (?1, ?2)",
-                    KEM
-                ),
-                vec![Box::new(iter), Box::new(pt.as_slice().to_vec())],
-            )?;
-        }
+    /// The number of saved plaintexts, per number of rejections
+    pub fn iter_saved_counts(&self) -> Result<Vec<(Rejections, u64)>, String> {
+        self.query_map(
+            sql_pt!(
+                "SELECT iter, seedexpanders, COUNT(id) FROM {kem}{table} GROUP BY iter, seedexpanders ORDER BY iter, seedexpanders",
+                KEM
+            ),
+            [],
+            |r| {
+                Ok((
+                    Rejections {
+                        iterations: r.get(0)?,
+                        seedexpanders: r.get(1)?,
+                    },
+                    r.get(2)?,
+                ))
+            },
+        )
+    }
 
-        if let Some(count) = self.count_plaintexts.get_mut(iter as usize) {
-            *count += 1;
-        } else {
-            self.count_plaintexts.resize((iter + 1) as usize, 0);
-            // Resize above makes this safe
-            unsafe { *self.count_plaintexts.get_unchecked_mut(iter as usize) = 1 };
+    /// Stores the plaintext unless there already are `add_limit` plaintexts with the same number
+    /// of rejections. Returns whether the plaintext was stored.
+    pub fn insert_pt(
+        &mut self,
+        pt: &KEM::Plaintext,
+        rejections: Rejections,
+    ) -> Result<bool, String> {
+        if !self.count_below(rejections, self.add_limit)? {
+            return Ok(false);
         }
+        self.modify(
+            sql_pt!(
+                "INSERT INTO {kem}{table} (iter, seedexpanders, plaintext) VALUES (?1, ?2, ?3)",
+                KEM
+            ),
+            vec![
+                Box::new(rejections.iterations),
+                Box::new(rejections.seedexpanders),
+                Box::new(pt.as_slice().to_vec()),
+            ],
+        )?;
+        Ok(true)
+    }
 
-        Ok(())
+    /// Adds to the number of encountered plaintexts. Requires the unique index created by
+    /// [`PlaintextDb::aggregate_iter_counts`].
+    pub fn add_iter_count(&self, rejections: Rejections, count: u64) -> Result<(), String> {
+        self.modify(
+            sql_cnt!(
+                "INSERT INTO {kem}{table} (iter, seedexpanders, count) VALUES (?1, ?2, ?3) ON CONFLICT(iter, seedexpanders) DO UPDATE SET count=count + ?3",
+                KEM
+            ),
+            vec![
+                Box::new(rejections.iterations),
+                Box::new(rejections.seedexpanders),
+                Box::new(count),
+            ],
+        )
     }
 
-    fn save_iter_counts(&mut self) -> Result<(), String> {
-        for iter in 0..self.count_plaintexts.len() {
-            if let Some(count) = self.count_plaintexts.get(iter as usize).copied() {
-                if count > 0 {
-                    info!(
-                        "{} new plaintexts encountered with {} iterations",
-                        count, iter
-                    );
-                    self.modify(
-                        sql_cnt!(
-                            "INSERT INTO {kem}{table} (iter, count) VALUES (?1, ?2) ON CONFLICT(iter) DO UPDATE SET count=count + ?2", 
-                            KEM
-                        ),
-                        vec![Box::new(iter), Box::new(count)],
-                    )?;
+    fn clear(&self) -> Result<(), String> {
+        self.modify(sql_pt!("DELETE FROM {kem}{table}", KEM), vec![])?;
+        self.modify(sql_cnt!("DELETE FROM {kem}{table}", KEM), vec![])
+    }
+}
+
+fn open_connection(dest: impl AsRef<Path>) -> Result<Connection, String> {
+    let conn = Connection::open(dest).strerr()?;
+    conn.busy_handler(Some(|_i| true)).strerr()?; // Ignore busy errors
+    Ok(conn)
+}
 
-                    self.count_plaintexts[iter] = 0;
+/// Plaintexts and encountered counts gathered by a single worker since it sent its last batch
+#[derive(Debug, Default)]
+struct Batch {
+    plaintexts: Vec<(Rejections, Vec<u8>)>,
+    encountered: HashMap<Rejections, u64>,
+}
+
+/// Owns the only connection that is written to while collecting plaintexts. Every worker sends
+/// its batches to it over its own channel, which are written in a single transaction with
+/// prepared statements.
+struct PlaintextWriter<KEM: KemWithRejectionSampling> {
+    conn: Connection,
+    limit: u64,
+    stored: Arc<Mutex<BTreeMap<Rejections, u64>>>,
+    unsaved: HashMap<Rejections, u64>,
+    _kem: PhantomData<KEM>,
+}
+
+impl<KEM: KemWithRejectionSampling> PlaintextWriter<KEM> {
+    fn write_batches(&mut self, batches: &[Batch]) -> Result<(), String> {
+        let tx = self.conn.transaction().strerr()?;
+        {
+            let mut insert = tx
+                .prepare_cached(&sql_pt!(
+                    "INSERT INTO {kem}{table} (iter, seedexpanders, plaintext) VALUES (?1, ?2, ?3)",
+                    KEM
+                ))
+                .strerr()?;
+            let mut stored = self.stored.lock().strerr()?;
+            for batch in batches {
+                for (rejections, pt) in &batch.plaintexts {
+                    let count = stored.entry(*rejections).or_insert(0);
+                    if *count < self.limit {
+                        insert
+                            .execute(params![rejections.iterations, rejections.seedexpanders, pt])
+                            .strerr()?;
+                        *count += 1;
+                    }
+                }
+                for (rejections, count) in &batch.encountered {
+                    *self.unsaved.entry(*rejections).or_insert(0) += count;
                 }
             }
         }
+        tx.commit().strerr()
+    }
 
-        Ok(())
+    fn save_iter_counts(&mut self) -> Result<(), String> {
+        let tx = self.conn.transaction().strerr()?;
+        {
+            let mut upsert = tx
+                .prepare_cached(&sql_cnt!(
+                    "INSERT INTO {kem}{table} (iter, seedexpanders, count) VALUES (?1, ?2, ?3) ON CONFLICT(iter, seedexpanders) DO UPDATE SET count=count + ?3",
+                    KEM
+                ))
+                .strerr()?;
+            for (rejections, count) in self.unsaved.drain() {
+                info!(
+                    "{} new plaintexts encountered with {} iterations ({} seedexpanders)",
+                    count, rejections.iterations, rejections.seedexpanders
+                );
+                upsert
+                    .execute(params![
+                        rejections.iterations,
+                        rejections.seedexpanders,
+                        count
+                    ])
+                    .strerr()?;
+            }
+        }
+        tx.commit().strerr()
     }
 
-    fn clear(&self) -> Result<(), String> {
-        self.modify(sql_pt!("DELETE FROM {kem}{table}", KEM), vec![])?;
-        self.modify(sql_cnt!("DELETE FROM {kem}{table}", KEM), vec![])
+    /// Writes the batches of every worker until all of them have hung up, the encountered counts
+    /// are saved whenever `save_signal` is raised and once all workers are done.
+    fn run(
+        mut self,
+        mut workers: Vec<Receiver<Batch>>,
+        save_signal: Arc<AtomicBool>,
+    ) -> Result<(), String> {
+        while !workers.is_empty() {
+            let mut pending = vec![];
+            // A worker that hung up is only removed once its queued batches are received
+            workers.retain(|batches| loop {
+                match batches.try_recv() {
+                    Ok(batch) => pending.push(batch),
+                    Err(TryRecvError::Empty) => break true,
+                    Err(TryRecvError::Disconnected) => break false,
+                }
+            });
+            if pending.is_empty() {
+                std::thread::sleep(WRITER_POLL_INTERVAL);
+            } else {
+                self.write_batches(&pending)?;
+            }
+            if save_signal.swap(false, Ordering::Relaxed) {
+                self.save_iter_counts()?;
+            }
+        }
+        self.save_iter_counts()
     }
 }
 
-fn update_progress<KEM: KemWithRejectionSampling>(
-    ptdb: &PlaintextDb<KEM>,
+fn update_progress(
+    stored: &Mutex<BTreeMap<Rejections, u64>>,
     pm: &ClonableProgressManager<u8>,
 ) -> Result<(), String> {
     let mut parts = String::from("Saved plaintexts, per iteration count: {");
-    if let (Some(min), Some(max)) = ptdb.iter_minmax()? {
-        for iter in (min..=max).rev() {
-            let count = ptdb.count(iter)?;
-            if count > 0 {
-                parts.push_str(&iter.to_string());
-                parts.push_str(": ");
-                parts.push_str(&count.to_string());
-                parts.push_str(", ");
-            }
+    let saved_counts = stored.lock().strerr()?;
+    if !saved_counts.is_empty() {
+        for (rejections, count) in saved_counts.iter().rev() {
+            parts.push_str(&rejections.iterations.to_string());
+            parts.push_str(": ");
+            parts.push_str(&count.to_string());
+            parts.push_str(", ");
         }
         parts.pop(); // ' '
         parts.pop(); // ','
     }
     parts.push('}');
+    drop(saved_counts);
     pm.set_message(0, parts);
     pm.tick();
 
     Ok(())
 }
 
-fn get_stop_signal() -> Result<Arc<AtomicBool>, String> {
-    // Make sure double CTRL+C and similar kills
-    let stop_signal = Arc::new(AtomicBool::new(false));
-    for sig in TERM_SIGNALS {
-        // When terminated by a second term signal, exit with exit code 1.
-        // This will do nothing the first time (because term_now is false).
-        flag::register_conditional_shutdown(*sig, 1, Arc::clone(&stop_signal)).strerr()?;
-        // But this will "arm" the above for the second time, by setting it to true.
-        // The order of registering these is important, if you put this one first, it will
-        // first arm and then terminate ‒ all in the first round.
-        flag::register(*sig, Arc::clone(&stop_signal)).strerr()?;
-    }
-    Ok(stop_signal)
-}
-
 #[logfn_inputs(Trace)]
-pub fn run<KEM: 'static + KemWithRejectionSampling + std::marker::Send + std::marker::Sync>(
+pub fn run<KEM: 'static + RejectionSamplingStats + std::marker::Send + std::marker::Sync>(
     opt: CollectPlaintextsOptions,
 ) -> Result<(), String> {
     let start_time = std::time::Instant::now();
@@ -558,18 +1030,17 @@ pub fn run<KEM: 'static + KemWithRejectionSampling + std::marker::Send + std::ma
     {
         if opt.clear {
             ptdb.clear()?;
+            ptdb.set_implementation_metadata()?;
             info!("Cleared all plaintexts from database");
         }
 
         ptdb.aggregate_iter_counts()?;
 
-        if let Some(iter_counts) = ptdb.iter_counts()? {
-            for (iter, count, stored) in iter_counts {
-                info!(
-                    "Currently {} out of {} encountered plaintexts with {} iterations",
-                    stored, count, iter
-                );
-            }
+        for (rejections, count, stored) in ptdb.iter_counts()? {
+            info!(
+                "Currently {} out of {} encountered plaintexts with {} iterations ({} seedexpanders)",
+                stored, count, rejections.iterations, rejections.seedexpanders
+            );
         }
     }
 
@@ -577,27 +1048,31 @@ pub fn run<KEM: 'static + KemWithRejectionSampling + std::marker::Send + std::ma
         let mut previous_id = None;
         let mut mapping = HashMap::new();
         let mut counter = 0;
-        while let Some((id, iter, mut pt)) = ptdb.get_next(previous_id)? {
+        while let Some((id, rejections, mut pt)) = ptdb.get_next(previous_id)? {
             previous_id = Some(id);
             counter += 1;
             if counter % 1000 == 0 {
                 info!("Reindexed {} plaintexts", counter);
             };
 
-            let new_iter = KEM::num_rejections(&mut pt)? as u32;
+            let new_rejections = KEM::rejections(&mut pt)?;
 
-            let key = if iter != new_iter {
+            let key = if rejections != new_rejections {
                 format!(
-                    "Changed plaintext number of iterations from {} to {}",
-                    iter, new_iter
+                    "Changed plaintext number of iterations from {} to {} (seedexpanders from {} to {})",
+                    rejections.iterations,
+                    new_rejections.iterations,
+                    rejections.seedexpanders,
+                    new_rejections.seedexpanders
                 )
             } else {
                 String::from("No change")
             };
             let count = mapping.entry(key).or_insert(0);
             *count += 1;
-            ptdb.update_iter(id, new_iter)?;
+            ptdb.update_rejections(id, new_rejections)?;
         }
+        ptdb.set_implementation_metadata()?;
 
         let mut mapping: Vec<_> = mapping.drain().collect();
         mapping.sort();
@@ -609,35 +1084,79 @@ pub fn run<KEM: 'static + KemWithRejectionSampling + std::marker::Send + std::ma
         return Ok(());
     }
 
-    let mut save_signals: Vec<_> = (0..opt.threads)
-        .map(|_| Arc::new(AtomicBool::new(false)))
-        .collect();
-
     let stop_signal = get_stop_signal()?;
+    let save_signal = Arc::new(AtomicBool::new(false));
+
+    let initially_stored: HashMap<Rejections, u64> =
+        ptdb.iter_saved_counts()?.into_iter().collect();
+    let stored = Arc::new(Mutex::new(
+        initially_stored
+            .iter()
+            .map(|(rejections, count)| (*rejections, *count))
+            .collect::<BTreeMap<_, _>>(),
+    ));
+    let (senders, receivers): (Vec<_>, Vec<_>) = (0..opt.threads)
+        .map(|_| sync_channel::<Batch>(MAX_QUEUED_BATCHES_PER_THREAD))
+        .unzip();
+
+    let writer_failed = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let writer = PlaintextWriter::<KEM> {
+            conn: open_connection(&opt.destination)?,
+            limit: opt.pt_limit_per_iter as u64,
+            stored: stored.clone(),
+            unsaved: HashMap::new(),
+            _kem: PhantomData,
+        };
+        let save_signal = save_signal.clone();
+        let writer_failed = writer_failed.clone();
+        std::thread::spawn(move || {
+            let result = writer.run(receivers, save_signal);
+            writer_failed.store(result.is_err(), Ordering::Relaxed);
+            result
+        })
+    };
 
-    let workers: Vec<_> = save_signals
-        .iter()
-        .cloned()
+    let workers: Vec<_> = senders
+        .into_iter()
         .enumerate()
-        .map(|(t, savesignal)| {
-            let mut ptdb = ptdb.clone();
+        .map(|(t, sender)| {
             let stop_signal = stop_signal.clone();
+            let initially_stored = initially_stored.clone();
+            let limit = opt.pt_limit_per_iter as u64;
             std::thread::spawn(move || {
-                let mut inner = || -> Result<(), String> {
+                let inner = || -> Result<(), String> {
                     info!("Thread {} has started working!", t);
-                    let mut rng = thread_rng();
+                    let mut rng = root_seed().purpose("plaintexts").thread(t).rng();
                     let mut pt = KEM::Plaintext::new();
+                    // Local estimate of the number of stored plaintexts, per number of rejections.
+                    // Other workers are not accounted for, the writer thread enforces the exact limit.
+                    let mut sent = initially_stored;
+                    let mut batch = Batch::default();
+                    let mut last_sent = Instant::now();
                     loop {
                         pt.as_mut_slice().try_fill(&mut rng).strerr()?;
-                        let iter = KEM::num_rejections(&mut pt)? as u32;
-                        ptdb.add_pt(&pt, iter)?;
-                        if savesignal.load(Ordering::Relaxed) {
-                            ptdb.save_iter_counts()?;
-                            savesignal.store(false, Ordering::Relaxed);
+                        let rejections = KEM::rejections(&mut pt)?;
+                        *batch.encountered.entry(rejections).or_insert(0) += 1;
+                        let count = sent.entry(rejections).or_insert(0);
+                        if *count < limit {
+                            *count += 1;
+                            batch.plaintexts.push((rejections, pt.as_slice().to_vec()));
                         }
-                        if stop_signal.load(Ordering::Relaxed) {
+
+                        let stop = stop_signal.load(Ordering::Relaxed);
+                        if stop
+                            || batch.plaintexts.len() >= BATCH_SIZE
+                            || last_sent.elapsed() >= UPDATE_RATE_MS
+                        {
+                            if sender.send(std::mem::take(&mut batch)).is_err() {
+                                // The writer failed, which the main thread reports
+                                break Ok(());
+                            }
+                            last_sent = Instant::now();
+                        }
+                        if stop {
                             info!("Stopping thread {} gracefully...", t);
-                            ptdb.save_iter_counts()?;
                             break Ok(());
                         }
                     }
@@ -665,7 +1184,7 @@ pub fn run<KEM: 'static + KemWithRejectionSampling + std::marker::Send + std::ma
     loop {
         // First, update progress (unless --quiet)
         if let Some(pm) = &progress {
-            update_progress(&ptdb, pm)?;
+            update_progress(&stored, pm)?;
         }
 
         // Then we sleep for a while
@@ -675,9 +1194,7 @@ pub fn run<KEM: 'static + KemWithRejectionSampling + std::marker::Send + std::ma
         if let Some(until_save) = until_save.as_mut() {
             *until_save -= 1;
             if *until_save == 0 {
-                save_signals
-                    .iter_mut()
-                    .for_each(|savesignal| savesignal.store(true, Ordering::Relaxed));
+                save_signal.store(true, Ordering::Relaxed);
                 *until_save = reset_until_save.unwrap();
             }
         }
@@ -694,32 +1211,114 @@ pub fn run<KEM: 'static + KemWithRejectionSampling + std::marker::Send + std::ma
             }
         }
 
+        // Stop the workers if the writer failed, its error is returned below
+        if writer_failed.load(Ordering::Relaxed) {
+            error!("Could not write the plaintexts, stopping all threads");
+            stop_signal.store(true, Ordering::Relaxed);
+        }
+
         // Shutdown if stop_signal is true
         if stop_signal.load(Ordering::Relaxed) {
             break;
         }
     }
 
-    // Wait for all the worker threads to stop
+    // Wait for all the worker threads to stop, and then for the writer to finish the last batches
     for jh in workers {
         jh.join().strerr()?
     }
+    writer.join().strerr()??;
 
     // Report on the final statistics
-    if let Some(iter_counts) = ptdb.iter_counts()? {
-        for (iter, count, stored) in iter_counts {
-            info!(
-                "Ended with {} out of {} in total encountered plaintexts with {} iterations",
-                stored, count, iter
-            );
-        }
+    for (rejections, count, stored) in ptdb.iter_counts()? {
+        info!(
+            "Ended with {} out of {} in total encountered plaintexts with {} iterations ({} seedexpanders)",
+            stored, count, rejections.iterations, rejections.seedexpanders
+        );
     }
 
     // Update and stop the live-terminal progress reporting
     if let Some(pm) = &progress {
-        update_progress(&ptdb, pm)?;
+        update_progress(&stored, pm)?;
         pm.stop();
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use liboqs_rs_bindings::{hqc::Hqc128, Kem};
+    use rusqlite::{params, Connection};
+
+    use super::{PlaintextDb, SCHEMA_VERSION};
+    use crate::attack::rejection_sampling::rejections::Rejections;
+
+    fn temp_db(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("oqs-afw-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_migrate_legacy_layout() {
+        let path = temp_db("legacy");
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(&format!(
+                "CREATE TABLE {kem}_plaintext_count (id INTEGER PRIMARY KEY, iter INTEGER, count INTEGER);
+                CREATE TABLE {kem}_plaintexts (id INTEGER PRIMARY KEY, iter INTEGER, plaintext BLOB);",
+                kem = Hqc128::NAME
+            ))
+            .unwrap();
+            conn.execute(
+                &format!(
+                    "INSERT INTO {kem}_plaintexts (iter, plaintext) VALUES (?1, ?2)",
+                    kem = Hqc128::NAME
+                ),
+                params![3, vec![0u8; 16]],
+            )
+            .unwrap();
+        }
+
+        let ptdb = PlaintextDb::<Hqc128>::new(&path, 0).unwrap();
+        let metadata = ptdb.metadata().unwrap();
+        assert_eq!(metadata.schema_version, SCHEMA_VERSION);
+        assert_eq!(metadata.liboqs_fingerprint, None);
+        assert_eq!(
+            ptdb.count(Rejections {
+                iterations: 3,
+                seedexpanders: 0
+            })
+            .unwrap(),
+            1
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_refuse_newer_schema() {
+        let path = temp_db("newer");
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(&format!(
+                "CREATE TABLE metadata (kem TEXT NOT NULL, key TEXT NOT NULL, value TEXT, PRIMARY KEY (kem, key));
+                CREATE TABLE {kem}_plaintexts (id INTEGER PRIMARY KEY, iter INTEGER, plaintext BLOB);",
+                kem = Hqc128::NAME
+            ))
+            .unwrap();
+            conn.execute(
+                "INSERT INTO metadata (kem, key, value) VALUES (?1, 'schema_version', ?2)",
+                params![Hqc128::NAME, SCHEMA_VERSION + 1],
+            )
+            .unwrap();
+        }
+
+        assert!(PlaintextDb::<Hqc128>::new(&path, 0).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+use std::{fmt, str::FromStr};
+
+use liboqs_rs_bindings as oqs;
+use oqs::{
+    bike::{BikeL1, BikeL3, BikeL5},
+    hqc::{Hqc128, Hqc192, Hqc256},
+    KemWithRejectionSampling,
+};
+use serde::{Deserialize, Serialize};
+
+/// The cost of the rejection sampling of a single plaintext
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Rejections {
+    /// Number of iterations in the rejection sampling loop
+    pub iterations: u32,
+    /// Number of invocations of the seedexpander (PRNG)
+    pub seedexpanders: u32,
+}
+
+/// Selects which of the counters in [`Rejections`] to filter or order by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RejectionCounter {
+    Iterations,
+    Seedexpanders,
+}
+
+impl RejectionCounter {
+    /// Name of the corresponding column in the plaintext database
+    pub fn column(self) -> &'static str {
+        match self {
+            RejectionCounter::Iterations => "iter",
+            RejectionCounter::Seedexpanders => "seedexpanders",
+        }
+    }
+
+    pub fn get(self, rejections: &Rejections) -> u32 {
+        match self {
+            RejectionCounter::Iterations => rejections.iterations,
+            RejectionCounter::Seedexpanders => rejections.seedexpanders,
+        }
+    }
+}
+
+impl fmt::Display for RejectionCounter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectionCounter::Iterations => write!(f, "iterations"),
+            RejectionCounter::Seedexpanders => write!(f, "seedexpanders"),
+        }
+    }
+}
+
+impl FromStr for RejectionCounter {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<RejectionCounter, String> {
+        match s {
+            "iterations" => Ok(RejectionCounter::Iterations),
+            "seedexpanders" => Ok(RejectionCounter::Seedexpanders),
+            _ => Err(format!(
+                "Could not parse {} into either iterations or seedexpanders.",
+                s
+            )),
+        }
+    }
+}
+
+/// Reports the number of iterations and the seedexpander usage of the rejection sampling
+/// as separate values.
+///
+/// The bindings only expose a single number through `KemWithRejectionSampling::num_rejections`,
+/// this trait is the only place that knows how that number is composed for each KEM. Once
+/// `num_rejections` returns both counters, `rejections` should take them from it directly and
+/// `split_rejections` is only needed to migrate databases with packed values.
+pub trait RejectionSamplingStats: KemWithRejectionSampling {
+    /// Splits the value returned by `num_rejections` into its parts
+    fn split_rejections(raw: u64) -> Result<Rejections, String>;
+
+    fn rejections(pt: &mut Self::Plaintext) -> Result<Rejections, String> {
+        Self::split_rejections(Self::num_rejections(pt)? as u64)
+    }
+}
+
+/// The BIKE bindings return `iterations + seedexpanders * 1000` where the seedexpander is
+/// invoked once for every fourth iteration. Since the packed value strictly increases with the
+/// number of iterations it can be inverted exactly, also when there are 1000 iterations or more.
+fn split_bike_rejections(raw: u64) -> Result<Rejections, String> {
+    let pack = |iterations: u64| iterations + ((iterations + 3) / 4) * 1000;
+    let (mut low, mut high) = (0, raw);
+    while low < high {
+        let middle = (low + high) / 2;
+        if pack(middle) < raw {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    if pack(low) != raw {
+        return Err(format!(
+            "{} is not a valid number of BIKE rejections, has the encoding of the bindings changed?",
+            raw
+        ));
+    }
+    Ok(Rejections {
+        iterations: low as u32,
+        seedexpanders: ((low + 3) / 4) as u32,
+    })
+}
+
+macro_rules! bike_rejections {
+    ($($kem:ty),*) => {$(
+        impl RejectionSamplingStats for $kem {
+            fn split_rejections(raw: u64) -> Result<Rejections, String> {
+                split_bike_rejections(raw)
+            }
+        }
+    )*};
+}
+
+/// The HQC bindings only report the number of iterations, the seedexpander usage is not available
+macro_rules! hqc_rejections {
+    ($($kem:ty),*) => {$(
+        impl RejectionSamplingStats for $kem {
+            fn split_rejections(raw: u64) -> Result<Rejections, String> {
+                Ok(Rejections {
+                    iterations: raw as u32,
+                    seedexpanders: 0,
+                })
+            }
+        }
+    )*};
+}
+
+bike_rejections!(BikeL1, BikeL3, BikeL5);
+hqc_rejections!(Hqc128, Hqc192, Hqc256);
+
+#[cfg(test)]
+mod tests {
+    use super::{split_bike_rejections, Rejections};
+
+    #[test]
+    fn test_split_bike_rejections() {
+        let expected = |iterations, seedexpanders| {
+            Ok(Rejections {
+                iterations,
+                seedexpanders,
+            })
+        };
+        assert_eq!(split_bike_rejections(0), expected(0, 0));
+        assert_eq!(split_bike_rejections(43172), expected(172, 43));
+        assert_eq!(split_bike_rejections(44173), expected(173, 44));
+        assert_eq!(split_bike_rejections(44176), expected(176, 44));
+        assert_eq!(split_bike_rejections(45177), expected(177, 45));
+        // Beyond the range of the old `iter % 1000` decoding
+        assert_eq!(split_bike_rejections(251000), expected(1000, 250));
+        assert_eq!(split_bike_rejections(252001), expected(1001, 251));
+    }
+
+    #[test]
+    fn test_split_invalid_bike_rejections() {
+        assert!(split_bike_rejections(172).is_err());
+        assert!(split_bike_rejections(44172).is_err());
+    }
+}
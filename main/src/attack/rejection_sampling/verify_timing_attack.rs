@@ -1,16 +1,20 @@
 use std::{io::Write, iter::once, path::PathBuf};
 
 use crate::{
-    attack::fo_timing::{MeasureSource, NoCachePrepping},
+    attack::{
+        fo_timing::{MeasureSource, NoCachePrepping},
+        rejection_sampling::experiment::root_seed,
+    },
     utils::{
-        pb_add, ClonableProgressManager, ProgressBars, Rec, Recorder, SaveAllRecorder, StrErr,
+        pb_add, ClonableProgressManager, KeySeedOptions, ProgressBars, Rec, Recorder,
+        SaveAllRecorder, StrErr,
     },
 };
 use liboqs_rs_bindings as oqs;
 use log::{debug, info};
 use log_derive::logfn_inputs;
 use oqs::{KemBuf, KemWithRejectionSampling};
-use rand::{thread_rng, Fill};
+use rand::Fill;
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
@@ -24,6 +28,8 @@ pub struct VerifyTimingAttackOptions {
     /// Save all recordings to this file
     #[structopt(short("f"), long)]
     pub save: Option<PathBuf>,
+    #[structopt(flatten)]
+    pub key_seed: KeySeedOptions,
 }
 
 #[logfn_inputs(Trace)]
@@ -40,7 +46,7 @@ pub fn find_min_max_pt<KEM: KemWithRejectionSampling>(
         " {msg:30} {wide_bar} ETA [{eta_precise}] ",
     );
     pm.start([]);
-    let mut rng = thread_rng();
+    let mut rng = root_seed().purpose("plaintexts").rng();
     let mut pt = KEM::Plaintext::new();
     let mut max_rejection = 0;
     let mut max_rejection_pt = KEM::Plaintext::new();
@@ -105,6 +111,7 @@ where
     W: Write + std::fmt::Debug,
 {
     info!("Running with {}", KEM::NAME);
+    opt.key_seed.apply();
     //Find the best plaintext candidates for timing measurments
     let ((min, mut min_pt), (max, mut max_pt)) = find_min_max_pt::<KEM>(opt.num_plaintexts)?;
 
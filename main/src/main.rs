@@ -11,7 +11,7 @@ use log::{debug, error};
 use structopt::clap::Shell;
 use structopt::StructOpt;
 
-use crate::utils::setup_logging;
+use crate::utils::{set_root_seed, setup_logging};
 
 #[macro_use]
 mod utils;
@@ -38,6 +38,9 @@ struct ProgramArgs {
     /// Additionally write logs to the specified destination.
     #[structopt(short("d"), long)]
     logdest: Option<PathBuf>,
+    /// Seed for all randomness in the framework, a random seed is used (and logged) if not specified.
+    #[structopt(long)]
+    seed: Option<u64>,
     #[structopt(subcommand)]
     command: Command,
 }
@@ -55,6 +58,7 @@ fn main() {
 
     let _log_scope_guard = setup_logging(matches.loglevel, &matches.logdest);
     debug!("command line arguments parsed: {:?}", matches);
+    set_root_seed(matches.seed);
 
     let result = match matches.command {
         Command::Attack(opt) => attack::run(opt),
@@ -8,6 +8,8 @@ mod logging;
 pub use logging::*;
 mod threadding;
 pub use threadding::*;
+mod random;
+pub use random::*;
 
 use std::{
     convert::TryInto,
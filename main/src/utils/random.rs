@@ -0,0 +1,140 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
+use log::{error, info};
+use rand::{rngs::StdRng, thread_rng, Rng, RngCore, SeedableRng};
+use structopt::StructOpt;
+
+/// The random number generator used for all randomness in the framework
+pub type FrameworkRng = StdRng;
+
+static ROOT_SEED: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the seed that all random streams are derived from, a random seed is drawn if none is
+/// specified. The seed is logged so that any run can be reproduced with `--seed`.
+pub fn set_root_seed(seed: Option<u64>) -> u64 {
+    let seed = seed.unwrap_or_else(|| thread_rng().gen());
+    ROOT_SEED.store(seed, Ordering::Relaxed);
+    info!("Random seed: {} (reproduce with --seed {})", seed, seed);
+    seed
+}
+
+fn splitmix64(z: u64) -> u64 {
+    let mut z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// A node in the hierarchy of random streams derived from the root seed.
+///
+/// Each purpose (and each thread within it) gets an independent stream, so that drawing more
+/// random numbers for one purpose never changes the numbers drawn for another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RngSeed(u64);
+
+impl RngSeed {
+    pub fn root() -> Self {
+        RngSeed(ROOT_SEED.load(Ordering::Relaxed))
+    }
+
+    /// Derives the stream used for a specific purpose, e.g. "error-patterns"
+    pub fn purpose(self, purpose: &str) -> Self {
+        let state = purpose.bytes().fold(splitmix64(self.0), |state, byte| {
+            splitmix64(state ^ byte as u64)
+        });
+        RngSeed(splitmix64(state ^ purpose.len() as u64))
+    }
+
+    /// Derives the stream used by a single worker thread
+    pub fn thread(self, tid: usize) -> Self {
+        RngSeed(splitmix64(self.0 ^ splitmix64(!(tid as u64))))
+    }
+
+    pub fn rng(self) -> FrameworkRng {
+        FrameworkRng::seed_from_u64(self.0)
+    }
+}
+
+extern "C" {
+    // Provided by liboqs, which is linked in through the bindings. The bindings do not export it
+    // yet, once they do this declaration should be replaced by theirs.
+    fn OQS_randombytes_custom_algorithm(algorithm_ptr: extern "C" fn(*mut u8, usize));
+}
+
+static LIBOQS_RNG: Mutex<Option<FrameworkRng>> = Mutex::new(None);
+
+extern "C" fn liboqs_randombytes(random_array: *mut u8, bytes_to_read: usize) {
+    let mut rng = LIBOQS_RNG.lock().unwrap_or_else(|e| e.into_inner());
+    let rng = match rng.as_mut() {
+        Some(rng) => rng,
+        None => {
+            // Unwinding into liboqs is undefined behaviour, so a panic is not an option here
+            error!("liboqs randombytes hook installed without a DRBG");
+            std::process::abort();
+        }
+    };
+    // Safety: liboqs guarantees that random_array points to at least bytes_to_read bytes
+    let buf = unsafe { std::slice::from_raw_parts_mut(random_array, bytes_to_read) };
+    rng.fill_bytes(buf);
+}
+
+/// Replaces the randomness source of liboqs with a DRBG seeded by `seed`, making keypairs,
+/// encapsulations and plaintexts generated by liboqs reproducible.
+///
+/// The DRBG is shared by all threads, so output is only reproducible if liboqs is called in
+/// the same order, i.e. keys should be generated before any worker threads are started.
+pub fn seed_liboqs(seed: u64) {
+    *LIBOQS_RNG.lock().unwrap_or_else(|e| e.into_inner()) =
+        Some(RngSeed(seed).purpose("liboqs").rng());
+    unsafe { OQS_randombytes_custom_algorithm(liboqs_randombytes) };
+    info!(
+        "liboqs randomness seeded with key seed {} (reproduce with --key-seed {})",
+        seed, seed
+    );
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct KeySeedOptions {
+    /// Seed liboqs' random number generator, making generated keys and encapsulations reproducible
+    #[structopt(long)]
+    pub key_seed: Option<u64>,
+}
+
+impl KeySeedOptions {
+    pub fn apply(&self) {
+        if let Some(seed) = self.key_seed {
+            seed_liboqs(seed);
+        }
+    }
+}
+
+mod test {
+    #![cfg(test)]
+
+    use rand::Rng;
+
+    use super::RngSeed;
+
+    #[test]
+    pub fn test_independent_streams() {
+        let seed = RngSeed(42);
+        assert_eq!(seed.purpose("a"), RngSeed(42).purpose("a"));
+        assert_ne!(seed.purpose("a"), seed.purpose("b"));
+        assert_ne!(seed.purpose("ab"), seed.purpose("a").purpose("b"));
+        assert_ne!(seed.thread(0), seed.thread(1));
+        assert_ne!(seed.thread(0), seed);
+
+        let draw = |seed: RngSeed| seed.rng().gen::<u64>();
+        assert_eq!(
+            draw(seed.purpose("a").thread(3)),
+            draw(seed.purpose("a").thread(3))
+        );
+        assert_ne!(
+            draw(seed.purpose("a").thread(3)),
+            draw(seed.purpose("a").thread(4))
+        );
+    }
+}
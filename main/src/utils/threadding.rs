@@ -1,11 +1,16 @@
-use std::sync::Arc;
+use std::sync::{atomic::AtomicBool, Arc};
 
-use log::info;
+use core_affinity::CoreId;
+use log::{info, warn};
+use signal_hook::{consts::TERM_SIGNALS, flag};
 
-use crate::utils::{StrErr, RATE_MS};
+use crate::utils::{RngSeed, StrErr, RATE_MS};
 
+/// Runs `work` on `num_threads` threads until `watch_stop` returns true. Each thread is given
+/// its own random stream, derived from `seed`.
 pub fn thread_work<I, T, ARG, F, FS>(
     num_threads: usize,
+    seed: RngSeed,
     arg: ARG,
     work: F,
     watch_stop: FS,
@@ -14,7 +19,7 @@ where
     I: Default + Send + Sync + 'static,
     T: Send + 'static,
     ARG: Clone + Send + 'static,
-    F: Fn(usize, Arc<I>, ARG) -> Result<Option<T>, String>,
+    F: Fn(usize, Arc<I>, ARG, RngSeed) -> Result<Option<T>, String>,
     F: Send + Clone + 'static,
     FS: Fn(&Arc<I>) -> bool,
 {
@@ -26,7 +31,7 @@ where
             let arg = arg.clone();
             let syncronization = syncronization.clone();
             let work = work.clone();
-            std::thread::spawn(move || work(tid, syncronization, arg))
+            std::thread::spawn(move || work(tid, syncronization, arg, seed.thread(tid)))
         })
         .collect(); // Collect so that we start all threads before we start to join them
 
@@ -48,3 +53,119 @@ where
 
     Ok(some_results)
 }
+
+/// Raised on the first Ctrl-C or similar, a second one terminates the process
+pub fn get_stop_signal() -> Result<Arc<AtomicBool>, String> {
+    // Make sure double CTRL+C and similar kills
+    let stop_signal = Arc::new(AtomicBool::new(false));
+    for sig in TERM_SIGNALS {
+        // When terminated by a second term signal, exit with exit code 1.
+        // This will do nothing the first time (because term_now is false).
+        flag::register_conditional_shutdown(*sig, 1, Arc::clone(&stop_signal)).strerr()?;
+        // But this will "arm" the above for the second time, by setting it to true.
+        // The order of registering these is important, if you put this one first, it will
+        // first arm and then terminate ‒ all in the first round.
+        flag::register(*sig, Arc::clone(&stop_signal)).strerr()?;
+    }
+    Ok(stop_signal)
+}
+
+/// Pins the current thread to the CPU core with the id `core`. The core does not have to be one
+/// the process is allowed to run on, so cores isolated with isolcpus can be used.
+pub fn pin_to_core(core: usize) -> Result<(), String> {
+    info!("Setting CPU affinity to core: {}", core);
+    core_affinity::set_for_current(CoreId { id: core });
+    // set_for_current does not report failures, but the affinity mask shows if the thread moved
+    match core_affinity::get_core_ids().as_deref() {
+        Some([pinned]) if pinned.id == core => Ok(()),
+        _ => Err(format!("Failed to pin the thread to CPU core {}", core)),
+    }
+}
+
+/// The ids of all online CPU cores. Unlike [`core_affinity::get_core_ids`] this includes the
+/// cores isolated with isolcpus, which are not in the affinity mask of the process.
+fn online_cores() -> Result<Vec<usize>, String> {
+    let online = std::fs::read_to_string("/sys/devices/system/cpu/online").strerr()?;
+    parse_cpu_list(online.trim())
+}
+
+/// Parses a list of CPU ids in the format of the kernel, e.g. "0-3,6"
+fn parse_cpu_list(list: &str) -> Result<Vec<usize>, String> {
+    let mut cores = vec![];
+    for range in list.split(',') {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        cores.extend(first.parse::<usize>().strerr()?..=last.parse::<usize>().strerr()?);
+    }
+    Ok(cores)
+}
+
+/// The ids of the CPU cores isolated with isolcpus, empty if there are none
+fn isolated_cores() -> Result<Vec<usize>, String> {
+    let isolated = std::fs::read_to_string("/sys/devices/system/cpu/isolated").strerr()?;
+    match isolated.trim() {
+        "" => Ok(vec![]),
+        list => parse_cpu_list(list),
+    }
+}
+
+/// The ids of `num` separate CPU cores for measurements, preferably isolated with isolcpus. If
+/// there are not enough isolated cores, the last online cores are used and a warning is logged,
+/// since other processes may disturb measurements on them.
+pub fn measurement_cores(num: usize) -> Result<Vec<usize>, String> {
+    let mut isolated = isolated_cores()?;
+    if isolated.len() >= num {
+        isolated.sort_unstable();
+        isolated.truncate(num);
+        info!("Using the isolated CPU cores {:?}", isolated);
+        return Ok(isolated);
+    }
+    let mut cores = online_cores()?;
+    cores.sort_unstable();
+    if cores.len() < num {
+        return Err(format!(
+            "Cannot pin {} threads to separate cores, there are only {} cores",
+            num,
+            cores.len()
+        ));
+    }
+    let cores = cores.split_off(cores.len() - num);
+    warn!(
+        "Only {} of the {} CPU cores needed are isolated, using the cores {:?} which other processes may disturb. Isolate them with isolcpus, or choose the cores explicitly.",
+        isolated.len(),
+        num,
+        cores
+    );
+    Ok(cores)
+}
+
+/// Runs `work` on a new thread, pinned to `core` if given
+pub fn run_on_core<T, F>(core: Option<usize>, work: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+{
+    std::thread::spawn(move || {
+        if let Some(core) = core {
+            pin_to_core(core)?;
+        }
+        work()
+    })
+    .join()
+    .strerr()?
+}
+
+mod test {
+    #![cfg(test)]
+
+    use super::parse_cpu_list;
+
+    #[test]
+    pub fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0").unwrap(), vec![0]);
+        assert_eq!(
+            parse_cpu_list("0-3,6,8-9").unwrap(),
+            vec![0, 1, 2, 3, 6, 8, 9]
+        );
+        assert!(parse_cpu_list("0-x").is_err());
+    }
+}
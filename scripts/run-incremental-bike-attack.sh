@@ -12,15 +12,26 @@ ROOT="data/compressed/bike-l1"
 KEYPAIR="$ROOT/$ALG.keypair"
 SUFFIX="csv.gz"
 LOGSUFFIX="log"
+CHECKPOINTSUFFIX="checkpoint"
 DB="rejection-sampling-plaintexts.db"
 BASE=$ROOT/$ALG-hw$HW-$LEN-ep$EPSILON
 
+# Ctrl-C makes bike-attack write its checkpoint and partial results, stop here once it has
+trap "exit 130" INT
+
 for IDX in {00..19}; do
     DEST=$BASE-$IDX.$SUFFIX
     LOGDEST=$BASE-$IDX.$LOGSUFFIX
-    if [[ -f "$DEST" ]]; then
+    CHECKPOINT=$BASE-$IDX.$CHECKPOINTSUFFIX
+    RESUME=""
+    if [[ -f "$CHECKPOINT" ]]; then
+        RESUME="--resume"
+    fi
+    if [[ -f "$DEST" && ! -f "$CHECKPOINT" ]]; then
         echo "$DEST already exists"
     else
-        cargo run $RELEASE -- --logdest $LOGDEST attack rejection-sampling bike-attack --chain-length $LEN --db $DB --hamming-weight $HW --epsilon $EPSILON --destination $DEST --threads=$THREADS --reuse-key-pair $KEYPAIR $ALG
+        # bike-attack fails unless the chain is complete, which keeps the checkpoint of a partial chain
+        cargo run $RELEASE -- --logdest $LOGDEST attack rejection-sampling bike-attack --chain-length $LEN --db $DB --hamming-weight $HW --epsilon $EPSILON --destination $DEST --threads=$THREADS --reuse-key-pair $KEYPAIR --checkpoint $CHECKPOINT $RESUME $ALG || exit 1
+        rm "$CHECKPOINT"
     fi
 done